@@ -0,0 +1,534 @@
+//! Offline maintenance tool for capture files.
+//!
+//! Subcommands operate on files written by the recorder and go through the
+//! library's [`CaptureReader`]/[`CaptureWriter`], so their output is framed
+//! and CRC-checked exactly like a live capture:
+//! - `info`: read a whole capture, check it and print its header and trailer
+//! - `verify`: check the hash chain of a capture and find the first broken link
+//! - `upgrade`: rewrite a capture written by an older format version
+//! - `recompress`: rewrite a capture with a different frame codec
+//! - `reencode`: rewrite a capture with a different event encoding
+//! - `index`: (re)build the seek index sidecar of a capture
+//! - `checkpoint`: rewrite a capture with fresh reconstructed-book checkpoints
+//! - `repair`: salvage the valid frames of a damaged or truncated capture
+//! - `merge`: interleave several captures into one by receive time
+//! - `slice`: cut a time or seq window out of a capture, starting from checkpoints
+//! - `normalize`: derive a capture of pre-decoded events from a raw one
+//! - `export` (feature `parquet`): write trades, book events and book
+//!   snapshots as Parquet tables
+//! - `stream` (feature `arrow`): write one of those tables as an Arrow IPC
+//!   stream, to stdout or a file
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use market_data::index::{CaptureIndex, DEFAULT_INDEX_EVERY};
+use market_data::record::{self, CaptureError, CaptureReader, Codec, Encoding, Provenance, RecordFrame, FORMAT_VERSION};
+use market_data::slice::{self, Bound};
+use market_data::normalize::{self, SERVER_UTC_OFFSET_SECS};
+use market_data::{merge, replay};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(about = "Inspect and maintain recorded capture files")]
+struct Args {
+    #[command(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Read a capture end to end and report whether it was closed cleanly
+    Info {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+    },
+    /// Check the hash chain of a capture recorded with --hash-chain
+    Verify {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+    },
+    /// Rewrite a capture in the current format version
+    Upgrade {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output capture (.bin); must differ from the input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+    },
+    /// Rewrite a capture with a different frame compression codec
+    Recompress {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output capture (.bin); must differ from the input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+
+        /// Codec for the output (none, zstd, lz4)
+        #[arg(long, default_value_t = Codec::Zstd)]
+        codec: Codec,
+    },
+    /// Rewrite a capture with another event encoding (bincode, or fixed for in-place reads)
+    Reencode {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output capture (.bin); must differ from the input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+
+        /// Event encoding for the output (bincode, fixed)
+        #[arg(long, default_value_t = Encoding::Fixed)]
+        encoding: Encoding,
+    },
+    /// Build the seek index sidecar (`<input>.idx`) of a capture
+    Index {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output path; defaults to `<input>.idx`
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+
+        /// Frames between regular index entries (FullBook starts are always indexed)
+        #[arg(long, default_value_t = DEFAULT_INDEX_EVERY)]
+        every: u32,
+    },
+    /// Copy the valid frames of a damaged capture, marking dropped bytes with gap frames
+    Repair {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output capture (.bin); must differ from the input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+    },
+    /// Rewrite a capture with reconstructed-book checkpoints, replacing existing ones
+    Checkpoint {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output capture (.bin); must differ from the input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+
+        /// Seconds of capture time between checkpoints
+        #[arg(long, default_value_t = 60)]
+        every_secs: u64,
+    },
+    /// Merge several captures into one, ordered by receive time
+    Merge {
+        /// Input captures (.bin), in source order; repeat or comma-separate
+        #[arg(long, short = 'i', required = true, num_args = 1.., value_delimiter = ',')]
+        input: Vec<PathBuf>,
+
+        /// Output capture (.bin); must differ from every input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+    },
+    /// Cut a window out of a capture; the slice starts with checkpoints of the books at the cut
+    Slice {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output capture (.bin); must differ from the input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+
+        /// Window start: UNIX ns, or local HH:MM[:SS] on the capture's date
+        #[arg(long, conflicts_with = "from_seq", required_unless_present = "from_seq")]
+        from: Option<String>,
+
+        /// Window end (exclusive): UNIX ns, or local HH:MM[:SS] on the capture's date
+        #[arg(long, conflicts_with = "to_seq")]
+        to: Option<String>,
+
+        /// First seq in the window
+        #[arg(long)]
+        from_seq: Option<u64>,
+
+        /// First seq after the window
+        #[arg(long)]
+        to_seq: Option<u64>,
+    },
+    /// Decode a raw capture into a normalized one (typed actions, whole FullBooks, parsed trade times)
+    Normalize {
+        /// Input capture (.bin)
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output capture (.bin); must differ from the input
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+
+        /// UTC offset of the server clock in trade times, in seconds
+        #[arg(long, default_value_t = SERVER_UTC_OFFSET_SECS, allow_hyphen_values = true)]
+        server_utc_offset_secs: i32,
+    },
+    /// Write trades, book events and (with --top) book snapshots as Parquet files
+    #[cfg(feature = "parquet")]
+    Export {
+        /// Input capture (.bin), raw or normalized
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Output directory for <table>.parquet
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+
+        /// Also write a snapshots table with this many levels per side after every book event
+        #[arg(long)]
+        top: Option<usize>,
+
+        /// Rows per Parquet row group
+        #[arg(long, default_value_t = market_data::export::DEFAULT_BATCH_ROWS)]
+        batch_rows: usize,
+
+        /// UTC offset of server clock date strings, in seconds [default: a normalized input's, else -10800]
+        #[arg(long, allow_hyphen_values = true)]
+        server_utc_offset_secs: Option<i32>,
+    },
+    /// Write one table (trades, book_events or snapshots) as an Arrow IPC stream
+    #[cfg(feature = "arrow")]
+    Stream {
+        /// Input capture (.bin), raw or normalized
+        #[arg(long, short = 'i')]
+        input: PathBuf,
+
+        /// Table to write: trades, book_events or snapshots (needs --top)
+        #[arg(long)]
+        table: market_data::export::Table,
+
+        /// Output file (.arrows); stdout if omitted
+        #[arg(long, short = 'o')]
+        output: Option<PathBuf>,
+
+        /// Levels per side of the snapshots table
+        #[arg(long)]
+        top: Option<usize>,
+
+        /// Rows per record batch
+        #[arg(long, default_value_t = market_data::export::DEFAULT_BATCH_ROWS)]
+        batch_rows: usize,
+
+        /// UTC offset of server clock date strings, in seconds [default: a normalized input's, else -10800]
+        #[arg(long, allow_hyphen_values = true)]
+        server_utc_offset_secs: Option<i32>,
+    },
+}
+
+/// Refuse to write over the file being read.
+fn check_distinct(input: &Path, output: &Path) -> Result<()> {
+    let same = match (input.canonicalize(), output.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    };
+    if same { bail!("output {:?} is the same file as input {:?}", output, input); }
+    Ok(())
+}
+
+fn info(input: &Path) -> Result<()> {
+    let mut rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let h = rdr.header();
+    println!("{:?}: v{} {}-{} created={}ns codec={} encoding={}", input, h.version, h.ticker, h.exchange, h.created_unix_ns, h.codec(), h.encoding());
+    if let Some((part, previous, last_seq)) = h.continuation() {
+        println!("part {} of a rotated set, continues {} after seq={:?}", part, previous, last_seq);
+    }
+    if h.hash_chain() { println!("hash chain: sha256 (check with `capture verify`)"); }
+    if let Some(p) = h.provenance() { print_provenance(p, ""); }
+    if let Some((source, offset)) = h.normalized() {
+        println!("normalized from {} (server times at UTC{:+}s)", source, offset);
+    }
+    for (n, s) in h.sources().iter().enumerate() {
+        println!("source {}: {} created={}ns offset_ms={}", n, s.name, s.created_unix_ns, s.server_clock_offset_ms);
+    }
+    let mut instruments = h.instruments();
+    let (mut checkpoints, mut gaps, mut resumes) = (0u64, 0u64, Vec::new());
+    for frame in rdr.by_ref() {
+        match frame.with_context(|| format!("read {:?}", input))? {
+            RecordFrame::Checkpoint(_) => checkpoints += 1,
+            RecordFrame::Gap(_) => gaps += 1,
+            RecordFrame::Resumed(m) => resumes.push(m),
+            RecordFrame::Instrument(i) => instruments.push(i),
+            _ => {}
+        }
+    }
+    if instruments.len() > 1 {
+        println!("{} instruments:", instruments.len());
+        for i in &instruments {
+            println!("  {:>3}: {}-{} feed={}", i.id, i.ticker, i.exchange, i.feed);
+        }
+    }
+    println!("{} frames, {} bytes, {} checkpoints, {} gaps, {} resumes", rdr.frames_read(), rdr.offset(), checkpoints, gaps, resumes.len());
+    for m in &resumes {
+        println!("resumed at {}ns after seq={:?} ({} partial bytes cut)", m.resumed_unix_ns, m.last_seq, m.truncated_bytes);
+        if let Some(p) = m.provenance() { print_provenance(p, "  "); }
+    }
+    match rdr.trailer() {
+        Some(t) => {
+            let c = &t.counts;
+            println!("clean: final seq={:?}, events={} (book={} trades={} hist={} state={}), dropped={}, checksum={:#x} ok",
+                t.final_seq, c.total(), c.offer_book, c.new_trade, c.history_trade, c.state, t.dropped_events, t.file_crc32);
+            if let (Some(a), Some(b)) = (t.first_recv_unix_ns, t.last_recv_unix_ns) {
+                println!("events received from {}ns to {}ns ({:.1}s)", a, b, b.saturating_sub(a) as f64 / 1e9);
+            }
+        }
+        None => println!("UNCLEAN: no trailer; the recorder did not shut down cleanly (or the file predates trailers)"),
+    }
+    if let Some(d) = rdr.chain_digest() { println!("final chain digest: {}", hex(&d)); }
+    Ok(())
+}
+
+/// Lines of `capture info` describing a recorder run, each after `indent`.
+fn print_provenance(p: &Provenance, indent: &str) {
+    let c = &p.server_clock;
+    println!("{indent}recorded by v{} on {:?}, queue={} frames, utc_offset_s={:?}", p.recorder_version, p.hostname, p.channel_capacity, p.utc_offset_secs);
+    println!("{indent}dll: {} sha256={}", p.dll_path, p.dll_sha256.map(|d| hex(&d)).unwrap_or_else(|| "?".into()));
+    println!(
+        "{indent}server clock at start: result={} dtDate={} {:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        c.result, c.date, c.year, c.month, c.day, c.hour, c.minute, c.second, c.millisecond
+    );
+    println!("{indent}options: {}", p.options);
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn verify(input: &Path) -> Result<()> {
+    let mut rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    if !rdr.header().hash_chain() { bail!("{:?} has no hash chain (record it with --hash-chain)", input); }
+    loop {
+        match rdr.read_frame() {
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(CaptureError::ChainMismatch { frame, offset }) => {
+                bail!("chain BROKEN at frame {} (offset {}): that frame or the one before it was changed after recording", frame, offset)
+            }
+            Err(e) => bail!("frame {} (offset {}) is unreadable, so the chain cannot be followed past it: {}", rdr.frames_read(), rdr.offset(), e),
+        }
+    }
+    let digest = rdr.chain_digest().map(|d| hex(&d)).unwrap_or_default();
+    println!("chain intact: {} frames, final digest {}", rdr.frames_read(), digest);
+    if rdr.trailer().is_none() {
+        println!("no trailer: the file may have been cut short; compare the final digest with a copy kept elsewhere");
+    }
+    Ok(())
+}
+
+fn upgrade(input: &Path, output: &Path) -> Result<()> {
+    check_distinct(input, output)?;
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let from = rdr.version();
+    if from == FORMAT_VERSION {
+        eprintln!("{:?} is already format v{}; rewriting anyway", input, FORMAT_VERSION);
+    }
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, frames) = record::upgrade(rdr, out).with_context(|| format!("upgrade {:?}", input))?;
+    eprintln!("Upgraded {:?} v{} -> {:?} v{} ({} frames after header).", input, from, output, FORMAT_VERSION, frames);
+    Ok(())
+}
+
+fn recompress(input: &Path, output: &Path, codec: Codec) -> Result<()> {
+    check_distinct(input, output)?;
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let mut header = rdr.header().clone();
+    let from = header.codec();
+    header.set_codec(codec);
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, frames) = record::rewrite(rdr, out, header).with_context(|| format!("recompress {:?}", input))?;
+    let (before, after) = (std::fs::metadata(input)?.len(), std::fs::metadata(output)?.len());
+    eprintln!("Recompressed {:?} ({}, {} bytes) -> {:?} ({}, {} bytes), {} frames after header.", input, from, before, output, codec, after, frames);
+    Ok(())
+}
+
+fn reencode(input: &Path, output: &Path, encoding: Encoding) -> Result<()> {
+    check_distinct(input, output)?;
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let mut header = rdr.header().clone();
+    let from = header.encoding();
+    header.set_encoding(encoding);
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, frames) = record::rewrite(rdr, out, header).with_context(|| format!("reencode {:?}", input))?;
+    let (before, after) = (std::fs::metadata(input)?.len(), std::fs::metadata(output)?.len());
+    eprintln!("Re-encoded {:?} ({}, {} bytes) -> {:?} ({}, {} bytes), {} frames after header.", input, from, before, output, encoding, after, frames);
+    Ok(())
+}
+
+fn index(input: &Path, output: Option<PathBuf>, every: u32) -> Result<()> {
+    let out = output.unwrap_or_else(|| CaptureIndex::sidecar_path(input));
+    check_distinct(input, &out)?;
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let idx = CaptureIndex::build(rdr, every).with_context(|| format!("index {:?}", input))?;
+    idx.save(&out).with_context(|| format!("write {:?}", out))?;
+    let syncs = idx.entries.iter().filter(|e| e.sync).count();
+    eprintln!("Indexed {:?}: {} entries ({} sync points) -> {:?}.", input, idx.entries.len(), syncs, out);
+    Ok(())
+}
+
+fn checkpoint(input: &Path, output: &Path, every_secs: u64) -> Result<()> {
+    check_distinct(input, output)?;
+    if every_secs == 0 { bail!("--every-secs must be greater than 0"); }
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, written) = replay::insert_checkpoints(rdr, out, Duration::from_secs(every_secs)).with_context(|| format!("checkpoint {:?}", input))?;
+    eprintln!("Wrote {:?} with {} checkpoints (every {}s).", output, written, every_secs);
+    Ok(())
+}
+
+fn merge(inputs: &[PathBuf], output: &Path) -> Result<()> {
+    let mut sources = Vec::new();
+    for input in inputs {
+        check_distinct(input, output)?;
+        let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+        let name = input.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        sources.push((name, rdr));
+    }
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, report) = merge::merge(sources, out).with_context(|| format!("merge into {:?}", output))?;
+    for (input, events) in inputs.iter().zip(&report.events) {
+        eprintln!("  {:?}: {} events", input, events);
+    }
+    for &i in &report.unclean {
+        eprintln!("Warning: {:?} has no trailer; it was not closed cleanly.", inputs[i]);
+    }
+    eprintln!("Merged {} captures -> {:?} ({} events).", inputs.len(), output, report.events.iter().sum::<u64>());
+    Ok(())
+}
+
+fn slice(input: &Path, output: &Path, from: Option<String>, to: Option<String>, from_seq: Option<u64>, to_seq: Option<u64>) -> Result<()> {
+    check_distinct(input, output)?;
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let created = rdr.header().created_unix_ns;
+    let bound = |time: Option<String>, seq: Option<u64>, what: &str| -> Result<Option<Bound>> {
+        match (time, seq) {
+            (Some(t), _) => Ok(Some(Bound::Time(slice::parse_time(&t, created).with_context(|| format!("--{what}"))?))),
+            (None, Some(s)) => Ok(Some(Bound::Seq(s))),
+            (None, None) => Ok(None),
+        }
+    };
+    let Some(start) = bound(from, from_seq, "from")? else { bail!("--from or --from-seq is required") };
+    let end = bound(to, to_seq, "to")?;
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, report) = slice::slice(rdr, out, start, end).with_context(|| format!("slice {:?}", input))?;
+    for id in &report.unsynced {
+        eprintln!("Warning: instrument {} had no reliable book at the cut; it replays from its next FullBook or checkpoint.", id);
+    }
+    eprintln!(
+        "Sliced {:?} -> {:?}: {} checkpoints, {} lead-in FullBook packets, {} events.",
+        input, output, report.checkpoints, report.lead_in, report.events
+    );
+    Ok(())
+}
+
+fn normalize(input: &Path, output: &Path, server_utc_offset_secs: i32) -> Result<()> {
+    check_distinct(input, output)?;
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let name = input.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, report) = normalize::normalize(rdr, out, &name, server_utc_offset_secs).with_context(|| format!("normalize {:?}", input))?;
+    if report.unknown > 0 {
+        eprintln!("Warning: skipped {} book events with an unknown action or side.", report.unknown);
+    }
+    if report.unparsed_times > 0 {
+        eprintln!("Warning: {} trades have a server time that could not be parsed.", report.unparsed_times);
+    }
+    eprintln!(
+        "Normalized {:?} -> {:?}: {} events read, {} written ({} FullBook packets assembled).",
+        input, output, report.events, report.written, report.full_book_packets
+    );
+    Ok(())
+}
+
+/// UTC offset to read server date strings with: a normalized input's own,
+/// which `flag` must not contradict, else `flag` or the default.
+#[cfg(feature = "arrow")]
+fn utc_offset(rdr: &CaptureReader<impl std::io::Read>, flag: Option<i32>) -> Result<i32> {
+    match (rdr.header().normalized(), flag) {
+        (Some((_, offset)), Some(flag)) if flag != offset => {
+            bail!("--server-utc-offset-secs {} conflicts with the input, normalized at {}", flag, offset)
+        }
+        (Some((_, offset)), _) => Ok(offset),
+        (None, flag) => Ok(flag.unwrap_or(SERVER_UTC_OFFSET_SECS)),
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn export(input: &Path, output: &Path, top: Option<usize>, batch_rows: usize, server_utc_offset_secs: Option<i32>) -> Result<()> {
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let server_utc_offset_secs = utc_offset(&rdr, server_utc_offset_secs)?;
+    let report = market_data::export::write_parquet(rdr, output, server_utc_offset_secs, top, batch_rows).with_context(|| format!("export {:?}", input))?;
+    for (table, path) in &report.files {
+        eprintln!("  {:?}: {} rows", path, report.rows.get(table).copied().unwrap_or_default());
+    }
+    if report.unknown > 0 {
+        eprintln!("Warning: skipped {} book events with an unknown action or side.", report.unknown);
+    }
+    eprintln!("Exported {:?} -> {:?}.", input, output);
+    Ok(())
+}
+
+#[cfg(feature = "arrow")]
+fn stream(input: &Path, table: market_data::export::Table, output: Option<&Path>, top: Option<usize>, batch_rows: usize, server_utc_offset_secs: Option<i32>) -> Result<()> {
+    use market_data::export::{write_ipc, Table};
+    if table == Table::Snapshots && top.is_none() { bail!("--table snapshots needs --top"); }
+    let rdr = CaptureReader::open(input).with_context(|| format!("open {:?}", input))?;
+    let server_utc_offset_secs = utc_offset(&rdr, server_utc_offset_secs)?;
+    let rows = match output {
+        Some(output) => {
+            let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+            write_ipc(rdr, out, table, server_utc_offset_secs, top, batch_rows)
+        }
+        None => write_ipc(rdr, BufWriter::new(std::io::stdout().lock()), table, server_utc_offset_secs, top, batch_rows),
+    };
+    let rows = rows.with_context(|| format!("stream {:?}", input))?;
+    eprintln!("Streamed {} rows of {} from {:?}.", rows, table.name(), input);
+    Ok(())
+}
+
+fn repair(input: &Path, output: &Path) -> Result<()> {
+    check_distinct(input, output)?;
+    let file = File::open(input).with_context(|| format!("open {:?}", input))?;
+    let out = BufWriter::with_capacity(1 << 20, File::create(output).with_context(|| format!("create {:?}", output))?);
+    let (_, report) = record::repair(BufReader::with_capacity(1 << 20, file), out).with_context(|| format!("repair {:?}", input))?;
+    for g in &report.gaps {
+        eprintln!("  skipped {} bytes at offset {} (last seq={:?}, next seq={:?})", g.skipped_bytes, g.offset, g.last_seq, g.next_seq);
+    }
+    eprintln!("Repaired {:?} -> {:?}: {} frames kept, {} gaps, {} bytes skipped.", input, output, report.frames, report.gaps.len(), report.skipped_bytes());
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    match args.cmd {
+        Command::Info { input } => info(&input),
+        Command::Verify { input } => verify(&input),
+        Command::Upgrade { input, output } => upgrade(&input, &output),
+        Command::Recompress { input, output, codec } => recompress(&input, &output, codec),
+        Command::Reencode { input, output, encoding } => reencode(&input, &output, encoding),
+        Command::Index { input, output, every } => index(&input, output, every),
+        Command::Repair { input, output } => repair(&input, &output),
+        Command::Checkpoint { input, output, every_secs } => checkpoint(&input, &output, every_secs),
+        Command::Merge { input, output } => merge(&input, &output),
+        Command::Slice { input, output, from, to, from_seq, to_seq } => slice(&input, &output, from, to, from_seq, to_seq),
+        Command::Normalize { input, output, server_utc_offset_secs } => normalize(&input, &output, server_utc_offset_secs),
+        #[cfg(feature = "parquet")]
+        Command::Export { input, output, top, batch_rows, server_utc_offset_secs } => export(&input, &output, top, batch_rows, server_utc_offset_secs),
+        #[cfg(feature = "arrow")]
+        Command::Stream { input, table, output, top, batch_rows, server_utc_offset_secs } => {
+            stream(&input, table, output.as_deref(), top, batch_rows, server_utc_offset_secs)
+        }
+    }
+}
@@ -1,18 +1,48 @@
 //! Player binary that replays recorded captures and reconstructs the L3 book.
 //!
 //! It reads frames written by the recorder (`len+crc32+bincode`), validates
-//! CRC, and applies Offer Book V2 actions to a local [`Book`]. It can also
-//! print trades. Use `--dump` or `--top` to print book snapshots.
+//! CRC, and applies Offer Book V2 actions to a local book through
+//! [`Replayer`]. It can also print trades (`--print-trades`) and decoded book
+//! events (`--print-book`). Use `--dump` or `--top` to print book snapshots,
+//! per offer or, with `--levels`, per price level (see [`market_data::l2`]).
+//! With `--format csv` or `--format jsonl` these are written as rows with
+//! stable column names instead (see [`market_data::rows`]). `--offer` and
+//! `--order-stats` follow individual orders (see [`market_data::orders`]) and
+//! report their histories at the end. `--queue` places a virtual order at
+//! the start point and reports how the queue ahead of it drained (see
+//! [`market_data::queue`]). Checkpoint frames are verified against the replayed book.
+//! Damaged captures stop playback at the first bad frame; `capture repair`
+//! turns them into files with gap markers, which the player reports. Resume
+//! markers (recorder restarts) are treated like gaps.
+//!
+//! `--start-seq`/`--start-time` use the capture's seek index (see
+//! [`market_data::index`]) to jump to the last FullBook or checkpoint before
+//! the requested point, replay silently up to it, and print from there on.
+//!
+//! Normalized files (see [`market_data::normalize`]) play the same way, from
+//! their pre-decoded events.
+//!
+//! If the input is the first file of a rotated set, the following parts are
+//! played as one stream (see [`market_data::rotate`]).
+//!
+//! Multi-instrument captures are played one instrument at a time, chosen
+//! with `--ticker` (default: the first instrument in the header).
 use anyhow::{bail, Context, Result};
 use clap::Parser;
-use crc32fast::Hasher as Crc32;
-use market_data::book::{parse_block_v2, Book, Entry};
-use market_data::record::{EventKind, RecordFrame};
-use std::fs::File;
-use std::io::{BufReader, Read};
+use market_data::book::Side;
+use market_data::l2::{L2Book, Level};
+use market_data::index::{CaptureIndex, IndexEntry, DEFAULT_INDEX_EVERY};
+use market_data::normalize::{NormalizedEvent, NormalizedKind, Normalizer, SERVER_UTC_OFFSET_SECS};
+use market_data::orders::{OrderEventKind, OrderHistory, OrderTracker};
+use market_data::queue::VirtualOrder;
+use market_data::record::{CaptureReader, EventKind, Instrument, RecordFrame};
+use market_data::replay::{CheckpointOutcome, Replayer};
+use market_data::rotate::CaptureSet;
+use market_data::rows::{BookEventRow, Cell, Format, RowWriter, SnapshotRow, TradeRow};
+use market_data::slice::{parse_time, Bound};
+use std::io::{BufWriter, Stdout};
 use std::path::PathBuf;
 
-
 #[derive(Debug, Parser)]
 #[command(about = "Play a recorded L3/Trades log and reconstruct the book")]
 struct Args {
@@ -28,149 +58,475 @@ struct Args {
     #[arg(long, default_value_t = 5)]
     top: usize,
 
+    /// Print price levels (total qty, offers and agents per price) instead of offers when dumping
+    #[arg(long, default_value_t = false)]
+    levels: bool,
+
     /// Print trades (NewTrade and HistoryTrade) as they are read
     #[arg(long, default_value_t = false)]
     print_trades: bool,
+
+    /// Print decoded book events (action, side, position and entry fields)
+    #[arg(long, default_value_t = false)]
+    print_book: bool,
+
+    /// Print the history of this offer id and where it rests at the end
+    #[arg(long, value_name = "ID")]
+    offer: Option<i64>,
+
+    /// Print order lifecycle statistics (edits, removals, lifetimes) at the end
+    #[arg(long, default_value_t = false)]
+    order_stats: bool,
+
+    /// Place a virtual order SIDE:PRICE:QTY (e.g. bid:125000:5) at the start and track its queue
+    #[arg(long, value_name = "SIDE:PRICE:QTY", value_parser = parse_queue)]
+    queue: Option<(Side, f64, i64)>,
+
+    /// Output of --dump, --print-trades and --print-book: text, csv (one of them) or jsonl
+    #[arg(long, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Start printing at the first event with seq >= N
+    #[arg(long, conflicts_with = "start_time")]
+    start_seq: Option<u64>,
+
+    /// Start printing at the first event received at or after this time:
+    /// UNIX nanoseconds, or local HH:MM[:SS] on the capture's date
+    #[arg(long)]
+    start_time: Option<String>,
+
+    /// Instrument to play in a multi-instrument capture (default: the first one)
+    #[arg(long)]
+    ticker: Option<String>,
+}
+
+fn parse_queue(s: &str) -> Result<(Side, f64, i64), String> {
+    let [side, price, qty] = s.split(':').collect::<Vec<_>>()[..] else { return Err(format!("expected SIDE:PRICE:QTY, got {s:?}")) };
+    let side = match side.to_ascii_lowercase().as_str() {
+        "bid" | "buy" => Side::Bid,
+        "ask" | "sell" => Side::Ask,
+        _ => return Err(format!("side must be bid or ask, got {side:?}")),
+    };
+    Ok((side, price.parse().map_err(|e| format!("price: {e}"))?, qty.parse().map_err(|e| format!("qty: {e}"))?))
+}
+
+/// Last sync point of `instrument` at or before `start`, searching the parts
+/// of `set` from the newest back. Returns the part number and its index entry.
+fn find_sync(set: &CaptureSet, instrument: u32, start: Bound) -> Result<Option<(usize, IndexEntry)>> {
+    for (part, path) in set.parts().iter().enumerate().rev() {
+        let idx = match CaptureIndex::load_fresh(path) {
+            Some(idx) => idx,
+            None => {
+                eprintln!("No up-to-date index for {:?}; building one in memory (run `capture index` to persist it).", path);
+                CaptureIndex::build(CaptureReader::open(path)?, DEFAULT_INDEX_EVERY)?
+            }
+        };
+        let entry = match start {
+            Bound::Seq(s) => idx.sync_at_or_before_seq(instrument, s),
+            Bound::Time(t) => idx.sync_at_or_before_time(instrument, t),
+        };
+        if let Some(e) = entry { return Ok(Some((part, e.clone()))); }
+    }
+    Ok(None)
+}
+
+/// CSV or JSON Lines output of the tables chosen on the command line, with
+/// their column names.
+struct Rows {
+    w: RowWriter<BufWriter<Stdout>>,
+    trades: Option<Vec<String>>,
+    book: Option<Vec<String>>,
+    snapshots: Option<(usize, Vec<String>)>,
+}
+
+impl Rows {
+    fn new(args: &Args) -> Result<Self> {
+        let mut rows = Rows {
+            w: RowWriter::new(BufWriter::new(std::io::stdout()), args.format),
+            trades: args.print_trades.then(TradeRow::columns),
+            book: args.print_book.then(BookEventRow::columns),
+            snapshots: args.dump.then(|| (args.top, SnapshotRow::columns(args.top))),
+        };
+        if args.format == Format::Csv {
+            let chosen: Vec<_> = [rows.trades.clone(), rows.book.clone(), rows.snapshots.clone().map(|(_, c)| c)].into_iter().flatten().collect();
+            let [columns] = &chosen[..] else {
+                bail!("--format csv writes one table: pass exactly one of --print-trades, --print-book and --dump")
+            };
+            rows.w.header(columns)?;
+        }
+        Ok(rows)
+    }
+
+    /// Write the rows of `ev`, after `replay` applied it.
+    fn emit(&mut self, ev: &NormalizedEvent, ticker: &str, replay: &Replayer, utc_offset_secs: i32) -> Result<()> {
+        if let (Some(columns), Some(row)) = (&self.trades, TradeRow::new(ev, ticker)) {
+            self.w.row(TradeRow::TABLE, columns, &row.cells())?;
+        }
+        let Some(row) = BookEventRow::new(ev, ticker, utc_offset_secs) else { return Ok(()) };
+        if let Some(columns) = &self.book { self.w.row(BookEventRow::TABLE, columns, &row.cells())?; }
+        if let Some((top, columns)) = &self.snapshots
+            && replay.is_consistent()
+            && !replay.is_stale()
+        {
+            self.w.row(SnapshotRow::TABLE, columns, &SnapshotRow::new(ev, ticker, replay.book(), *top).cells())?;
+        }
+        Ok(())
+    }
+}
+
+/// `BOOK` line of `--print-book` in text mode.
+fn print_book_event(ev: &NormalizedEvent, ticker: &str, utc_offset_secs: i32) {
+    let Some(row) = BookEventRow::new(ev, ticker, utc_offset_secs) else { return };
+    let fields: Vec<String> = BookEventRow::columns().iter().zip(row.cells())
+        .filter(|(_, cell)| *cell != Cell::Null)
+        .map(|(name, cell)| format!("{}={}", name, cell))
+        .collect();
+    println!("BOOK {}", fields.join(" "));
+}
+
+/// Print the top `top` offers (or price levels, if the replayer keeps them)
+/// of both sides under a `label` line.
+fn print_top(replay: &Replayer, top: usize, label: &str) {
+    if let Some(l2) = replay.levels() { return print_levels(l2, top, label); }
+    let book = replay.book();
+    let tb = book.buys.iter().take(top).collect::<Vec<_>>();
+    let ta = book.sells.iter().take(top).collect::<Vec<_>>();
+    println!("{} | top{} bids / asks:", label, top);
+    for i in 0..top.max(tb.len()).max(ta.len()) {
+        let b = tb.get(i).map(|e| format!("{:>3}: {:>10.2} x {:>7}", i, e.price, e.qty)).unwrap_or_else(|| format!("{:>3}: -", i));
+        let a = ta.get(i).map(|e| format!("{:>10.2} x {:>7}", e.price, e.qty)).unwrap_or_else(|| "-".to_string());
+        println!("{} | {}", b, a);
+    }
+    println!("---");
+}
+
+fn print_levels(l2: &L2Book, top: usize, label: &str) {
+    let (tb, ta) = (l2.top(Side::Bid, top), l2.top(Side::Ask, top));
+    println!("{} | top{} bid / ask levels (qty, offers, agents):", label, top);
+    let fmt = |l: &Level| format!("{:>10.2} x {:>7} {:>4} {:>3}", l.price, l.qty, l.orders, l.agent_count());
+    for i in 0..top.max(tb.len()).max(ta.len()) {
+        let b = tb.get(i).map(|l| format!("{:>3}: {}", i, fmt(l))).unwrap_or_else(|| format!("{:>3}: -", i));
+        let a = ta.get(i).map(|l| fmt(l)).unwrap_or_else(|| "-".to_string());
+        println!("{} | {}", b, a);
+    }
+    println!("---");
+}
+
+/// History of `offer_id` for `--offer`, one line per event.
+fn print_offer(replay: &Replayer, orders: &OrderTracker, offer_id: i64) {
+    let histories: Vec<&OrderHistory> = orders.history(offer_id);
+    if histories.is_empty() { eprintln!("Offer {}: never seen.", offer_id); }
+    for h in histories {
+        eprintln!("Offer {} ({:?}, agent {}):", h.offer_id, h.side, h.agent);
+        for e in &h.events {
+            let what = match e.kind {
+                OrderEventKind::Inserted { price, qty } => format!("inserted {} x {}", price, qty),
+                OrderEventKind::Seen { price, qty } => format!("seen {} x {}", price, qty),
+                OrderEventKind::Edited { price, qty } => format!("edited to {} x {}", price, qty),
+                OrderEventKind::Reduced { qty } => format!("reduced to {}", qty),
+                OrderEventKind::Removed(reason) => format!("removed ({:?})", reason),
+            };
+            eprintln!("  seq={} recv={}ns {}", e.seq, e.recv_unix_ns, what);
+        }
+        if let Some(ns) = h.lifetime_ns() { eprintln!("  lifetime {:.3}s", ns as f64 / 1e9); }
+    }
+    match replay.find_offer(offer_id) {
+        Some(p) => eprintln!("Offer {} rests at {:?} index {} (nPosition {}).", offer_id, p.side, p.index, p.n_position),
+        None => eprintln!("Offer {} is not in the final book.", offer_id),
+    }
+}
+
+/// Queue drain of the virtual order of `--queue`.
+fn print_queue(order: &VirtualOrder) {
+    let Some(first) = order.samples().first() else { return };
+    eprintln!(
+        "Virtual {:?} {} @ {} placed after seq={}: {} ahead in {} offers.",
+        order.side(), order.qty(), order.price(), first.seq, first.ahead_qty, first.ahead_orders
+    );
+    for s in &order.samples()[1..] {
+        eprintln!("  seq={} recv={}ns ahead={} ({} offers) filled={}", s.seq, s.recv_unix_ns, s.ahead_qty, s.ahead_orders, s.filled_qty);
+    }
+    match order.filled_at() {
+        Some((seq, recv_unix_ns)) => eprintln!("Filled at seq={} after {:.3}s.", seq, recv_unix_ns.saturating_sub(first.recv_unix_ns) as f64 / 1e9),
+        None => eprintln!("Not filled: {} ahead, {} of {} filled.", order.ahead_qty(), order.filled_qty(), order.qty()),
+    }
+}
+
+fn print_order_stats(orders: &OrderTracker) {
+    let stats = orders.stats();
+    let removed: Vec<String> = stats.removed.iter().map(|(reason, n)| format!("{:?}={}", reason, n)).collect();
+    eprintln!(
+        "Orders: {} tracked, {} open, {} edits, {} reductions; removed: {}.",
+        stats.orders, stats.open, stats.edits, stats.reductions, if removed.is_empty() { "none".to_string() } else { removed.join(" ") }
+    );
+    if let Some(l) = stats.lifetimes {
+        let ms = |ns: u128| ns as f64 / 1e6;
+        eprintln!(
+            "Lifetimes of {} orders (ms): min={:.3} p50={:.3} p90={:.3} p99={:.3} max={:.3} mean={:.3}",
+            l.count, ms(l.min_ns), ms(l.p50_ns), ms(l.p90_ns), ms(l.p99_ns), ms(l.max_ns), ms(l.mean_ns)
+        );
+    }
+}
+
+/// Effect of a gap or resume marker on the instrument being played.
+fn lost_events(lost: bool) -> &'static str {
+    if lost { "book unreliable until the next FullBook/checkpoint" } else { "the instrument being played is not affected" }
 }
 
-fn read_u32<R: Read>(r: &mut R) -> std::io::Result<u32> {
-    let mut buf = [0u8;4]; r.read_exact(&mut buf)?; Ok(u32::from_le_bytes(buf))
+/// Short description of a normalized book event for `--dump`.
+fn describe(kind: &NormalizedKind) -> String {
+    match kind {
+        NormalizedKind::Add { side, position, .. } => format!("action=add side={:?} pos={}", side, position),
+        NormalizedKind::Edit { side, position, .. } => format!("action=edit side={:?} pos={}", side, position),
+        NormalizedKind::Delete { side, position } => format!("action=delete side={:?} pos={}", side, position),
+        NormalizedKind::DeleteFrom { side, position } => format!("action=delete_from side={:?} pos={}", side, position),
+        NormalizedKind::FullBook { bids, asks, complete } => format!(
+            "action=full_book bids={:?} asks={:?} complete={}",
+            bids.as_ref().map(Vec::len), asks.as_ref().map(Vec::len), complete
+        ),
+        NormalizedKind::Trade(_) => "trade".into(),
+        NormalizedKind::State { state_type, value } => format!("state type={} value={}", state_type, value),
+    }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let mut rdr = BufReader::new(File::open(&args.input).with_context(|| format!("open {:?}", args.input))?);
-    let mut book = Book::default();
-    let mut pend_buy: Vec<Entry> = Vec::new();
-    let mut pend_sell: Vec<Entry> = Vec::new();
-    let mut frames = 0usize;
-    const OB_LAST_PACKET: u32 = 1; // footer flag bit meaning 'last packet' for this block
-    loop {
-        let len = match read_u32(&mut rdr) {
-            Ok(v) => v as usize,
-            Err(e) => {
-                // EOF or error
-                if e.kind() == std::io::ErrorKind::UnexpectedEof { break; } else { return Err(e.into()); }
+    let set = CaptureSet::discover(&args.input);
+    let mut rdr = set.reader().with_context(|| format!("open {:?}", args.input))?;
+    let (mut checkpoints_ok, mut checkpoints_bad) = (0usize, 0usize);
+    let (mut gaps, mut resumes) = (0usize, 0usize);
+    let mut frames = 1usize;
+    let h = rdr.header();
+    if args.dump { eprintln!("Header: v{} {}-{} offset_ms={} created={}ns codec={}", h.version, h.ticker, h.exchange, h.server_clock_offset_ms, h.created_unix_ns, h.codec()); }
+    let instruments = h.instruments();
+    let is_wanted = |i: &Instrument| args.ticker.as_ref().is_none_or(|t| i.ticker.eq_ignore_ascii_case(t));
+    // Instrument being played; a requested ticker missing from the header may
+    // still be defined later by an instrument frame
+    let mut selected = instruments.iter().find(|i| is_wanted(i)).map(|i| i.id);
+    let mut ticker = instruments.iter().find(|i| is_wanted(i)).map_or_else(|| h.ticker.clone(), |i| i.ticker.clone());
+    let utc_offset = h.normalized().map_or(SERVER_UTC_OFFSET_SECS, |(_, offset)| offset);
+    // Raw events are decoded for --print-book and the structured formats
+    let mut normalizer = (args.print_book || args.format != Format::Text).then(|| Normalizer::new(utc_offset));
+    let mut rows = (args.format != Format::Text).then(|| Rows::new(&args)).transpose()?;
+    let text = args.format == Format::Text;
+    match (&args.ticker, selected) {
+        (Some(t), None) => eprintln!("{} is not in the header's instrument table; waiting for its definition.", t),
+        (_, Some(id)) if instruments.len() > 1 => {
+            let names = instruments.iter().map(|i| i.ticker.as_str()).collect::<Vec<_>>().join(", ");
+            eprintln!("{} instruments ({}); playing id {} (choose with --ticker).", instruments.len(), names, id);
+            if let Some(source) = h.sources().get(market_data::merge::source_of(id).0 as usize) {
+                eprintln!("Merged capture; playing events from {}.", source.name);
             }
-        };
-        let crc_on_file = read_u32(&mut rdr)?;
-        let mut payload = vec![0u8; len];
-        rdr.read_exact(&mut payload)?;
-        let mut hasher = Crc32::new(); hasher.update(&payload); let crc_calc = hasher.finalize();
-        if crc_calc != crc_on_file { bail!("CRC mismatch at frame {}: file={:#x}, calc={:#x}", frames, crc_on_file, crc_calc); }
-        let frame: RecordFrame = bincode::deserialize(&payload).context("bincode decode")?;
+        }
+        _ => {}
+    }
+    let new_replayer = |id| {
+        let mut r = Replayer::for_instrument(id);
+        if args.levels { r = r.with_levels(); }
+        if args.offer.is_some() || args.order_stats { r = r.with_offer_index().with_order_tracking(); }
+        r
+    };
+    let mut replay = new_replayer(selected.unwrap_or(0));
+    let start = match (args.start_seq, &args.start_time) {
+        (Some(s), _) => Some(Bound::Seq(s)),
+        (None, Some(t)) => Some(Bound::Time(parse_time(t, h.created_unix_ns).context("--start-time")?)),
+        (None, None) => None,
+    };
+    if set.parts().len() > 1 { eprintln!("Rotated set: {} parts.", set.parts().len()); }
+    if let Some(start) = start
+        && let Some(id) = selected
+        && let Some((part, e)) = find_sync(&set, id, start)?
+    {
+        rdr.seek_to(part, e.frame, e.offset)?;
+        frames = e.frame as usize;
+    }
+    // Replay silently until the requested start point
+    let mut quiet = start.is_some();
+    // Placed once printing starts and the book is whole
+    let mut queue = args.queue;
+    while let Some(frame) = rdr.next() {
+        let frame = frame.with_context(|| format!("reading {:?} (`capture repair` can recover the frames after the damage)", rdr.path()))?;
         match frame {
             RecordFrame::Header(h) => {
                 frames += 1;
-                if args.dump { eprintln!("Header: v{} {}-{} offset_ms={} created={}ns", h.version, h.ticker, h.exchange, h.server_clock_offset_ms, h.created_unix_ns); }
+                if args.dump {
+                    if let Some((part, previous, last_seq)) = h.continuation() {
+                        eprintln!("Part {} continues {} after seq={:?}", part, previous, last_seq);
+                    }
+                    eprintln!("Header: v{} {}-{} offset_ms={} created={}ns", h.version, h.ticker, h.exchange, h.server_clock_offset_ms, h.created_unix_ns);
+                }
+            }
+            RecordFrame::Instrument(i) => {
+                frames += 1;
+                if args.dump { eprintln!("Instrument {}: {}-{} feed={}", i.id, i.ticker, i.exchange, i.feed); }
+                if selected.is_none() && is_wanted(&i) {
+                    selected = Some(i.id);
+                    ticker = i.ticker.clone();
+                    replay = new_replayer(i.id);
+                }
+            }
+            RecordFrame::Checkpoint(cp) => {
+                frames += 1;
+                if Some(cp.instrument) != selected { continue; }
+                match replay.apply_checkpoint(&cp) {
+                    CheckpointOutcome::Restored => {
+                        if args.dump { eprintln!("Restored book from checkpoint at seq={}", cp.seq); }
+                    }
+                    CheckpointOutcome::Verified => checkpoints_ok += 1,
+                    CheckpointOutcome::Mismatch { expected, actual } => {
+                        checkpoints_bad += 1;
+                        eprintln!("Checkpoint mismatch after seq={}: recorded={:#x}, replayed={:#x}", cp.seq, expected, actual);
+                    }
+                }
+            }
+            RecordFrame::Trailer(t) => {
+                frames += 1;
+                if args.dump {
+                    eprintln!(
+                        "Trailer: final seq={:?}, {} events (book={} trades={} hist={} state={}), dropped={}",
+                        t.final_seq, t.counts.total(), t.counts.offer_book, t.counts.new_trade, t.counts.history_trade, t.counts.state, t.dropped_events
+                    );
+                }
+            }
+            RecordFrame::Gap(gap) => {
+                frames += 1;
+                gaps += 1;
+                let lost = selected.is_none_or(|id| gap.scope.covers(id));
+                if lost { replay.apply_gap(); }
+                if let Some(n) = &mut normalizer { n.apply_gap(&gap.scope); }
+                eprintln!(
+                    "Gap: {} bytes lost at offset {} (last seq={:?}, next seq={:?}); {}",
+                    gap.skipped_bytes, gap.offset, gap.last_seq, gap.next_seq, lost_events(lost)
+                );
+            }
+            RecordFrame::Resumed(m) => {
+                frames += 1;
+                resumes += 1;
+                let lost = selected.is_none_or(|id| m.scope.covers(id));
+                if lost { replay.apply_gap(); }
+                if let Some(n) = &mut normalizer { n.apply_gap(&m.scope); }
+                eprintln!(
+                    "Resumed: recorder restarted at {}ns after seq={:?} ({} partial bytes cut); {}",
+                    m.resumed_unix_ns, m.last_seq, m.truncated_bytes, lost_events(lost)
+                );
+                if let Some(p) = m.provenance() {
+                    eprintln!("Restarted recorder: v{} on {:?}, utc_offset_s={:?}, options: {}", p.recorder_version, p.hostname, p.utc_offset_secs, p.options);
+                }
             }
             RecordFrame::Event(ev) => {
                 frames += 1;
+                if Some(ev.instrument) != selected { continue; }
+                // Seqs of a merged capture are only ordered per source
+                if quiet && start.is_some_and(|s| s.reached(&ev)) { quiet = false; }
+                if !quiet && replay.is_consistent() && !replay.is_stale()
+                    && let Some((side, price, qty)) = queue.take()
+                    && replay.place_order(side, price, qty).is_none()
+                {
+                    eprintln!("--queue: {:?} at {} crosses the book at the start point; the virtual order was not placed.", side, price);
+                }
+                replay.apply_event(&ev)?;
+                // Decoded even when quiet, so FullBook packets assemble
+                let decoded = match &mut normalizer { Some(n) => n.apply(&ev)?, None => None };
+                if let (Some(ev), false) = (&decoded, quiet) {
+                    match &mut rows {
+                        Some(rows) => rows.emit(ev, &ticker, &replay, utc_offset)?,
+                        None if args.print_book => print_book_event(ev, &ticker, utc_offset),
+                        None => {}
+                    }
+                }
+                if !text { continue; }
                 match ev.kind {
-                    EventKind::OfferBookV2 {
-                        n_action,
-                        n_position,
-                        n_side,
-                        n_qtd,
-                        n_agent,
-                        n_offer_id,
-                        d_price,
-                        has_price,
-                        has_qtd,
-                        has_date,
-                        has_offer_id,
-                        has_agent,
-                        date_str,
-                        array_sell,
-                        array_buy,
-                    } => {
-                        match n_action {
-                            4 => { // atFullBook (may come in multiple packets per side)
-                                if let Some(b) = &array_buy {
-                                    let (mut entries, flags) = parse_block_v2(b)?;
-                                    if (flags & OB_LAST_PACKET) == 0 {
-                                        pend_buy.append(&mut entries);
-                                    } else {
-                                        pend_buy.append(&mut entries);
-                                        book.apply_full(Some(std::mem::take(&mut pend_buy)), None);
-                                    }
-                                }
-                                if let Some(s) = &array_sell {
-                                    let (mut entries, flags) = parse_block_v2(s)?;
-                                    if (flags & OB_LAST_PACKET) == 0 {
-                                        pend_sell.append(&mut entries);
-                                    } else {
-                                        pend_sell.append(&mut entries);
-                                        book.apply_full(None, Some(std::mem::take(&mut pend_sell)));
-                                    }
-                                }
-                            }
-                            0 => { // atAdd
-                                let e = Entry { price: d_price, qty: n_qtd, agent: n_agent, offer_id: n_offer_id, date: date_str };
-                                book.apply_add(n_side, n_position, e);
-                            }
-                            1 => { // atEdit
-                                let e = Entry { price: d_price, qty: n_qtd, agent: n_agent, offer_id: n_offer_id, date: date_str };
-                                book.apply_edit(n_side, n_position, e, has_price, has_qtd, has_agent, has_offer_id, has_date);
-                            }
-                            2 => { // atDelete
-                                book.apply_delete(n_side, n_position);
-                            }
-                            3 => { // atDeleteFrom
-                                book.apply_delete_from(n_side, n_position);
-                            }
-                            _ => {}
-                        }
-                        if args.dump {
-                            let tb = book.buys.iter().take(args.top).collect::<Vec<_>>();
-                            let ta = book.sells.iter().take(args.top).collect::<Vec<_>>();
-                            println!("seq={} action={} side={} pos={} | top{} bids / asks:", ev.seq, n_action, n_side, n_position, args.top);
-                            for i in 0..args.top.max(tb.len()).max(ta.len()) {
-                                let b = tb.get(i).map(|e| format!("{:>3}: {:>10.2} x {:>7}", i, e.price, e.qty)).unwrap_or_else(|| format!("{:>3}: -", i));
-                                let a = ta.get(i).map(|e| format!("{:>10.2} x {:>7}", e.price, e.qty)).unwrap_or_else(|| "-".to_string());
-                                println!("{} | {}", b, a);
-                            }
-                            println!("---");
-                        }
+                    EventKind::OfferBookV2 { n_action, n_position, n_side, .. } if args.dump && !quiet => {
+                        print_top(&replay, args.top, &format!("seq={} action={} side={} pos={}", ev.seq, n_action, n_side, n_position));
                     }
-                    EventKind::NewTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag } => {
-                        if args.print_trades {
-                            println!(
-                                "TRADE new seq={} ts={} num={} price={} qty={} vol={} type={} buy_agent={} sell_agent={} edit={}",
-                                ev.seq,
-                                date_str,
-                                trade_number,
-                                price,
-                                qty,
-                                volume,
-                                trade_type,
-                                buy_agent,
-                                sell_agent,
-                                edit_flag
-                            );
-                        }
+                    EventKind::NewTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag } if args.print_trades && !quiet => {
+                        println!(
+                            "TRADE new seq={} ts={} num={} price={} qty={} vol={} type={} buy_agent={} sell_agent={} edit={}",
+                            ev.seq,
+                            date_str,
+                            trade_number,
+                            price,
+                            qty,
+                            volume,
+                            trade_type,
+                            buy_agent,
+                            sell_agent,
+                            edit_flag
+                        );
                     }
-                    EventKind::HistoryTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type } => {
-                        if args.print_trades {
-                            println!(
-                                "TRADE hist seq={} ts={} num={} price={} qty={} vol={} type={} buy_agent={} sell_agent={}",
-                                ev.seq,
-                                date_str,
-                                trade_number,
-                                price,
-                                qty,
-                                volume,
-                                trade_type,
-                                buy_agent,
-                                sell_agent
-                            );
-                        }
+                    EventKind::HistoryTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type } if args.print_trades && !quiet => {
+                        println!(
+                            "TRADE hist seq={} ts={} num={} price={} qty={} vol={} type={} buy_agent={} sell_agent={}",
+                            ev.seq,
+                            date_str,
+                            trade_number,
+                            price,
+                            qty,
+                            volume,
+                            trade_type,
+                            buy_agent,
+                            sell_agent
+                        );
                     }
-                    _ => { /* ignore state */ }
+                    _ => { /* nothing to print */ }
+                }
+            }
+            RecordFrame::Normalized(ev) => {
+                frames += 1;
+                if Some(ev.instrument) != selected { continue; }
+                if quiet && start.is_some_and(|s| s.reached_at(ev.seq, ev.recv_unix_ns)) { quiet = false; }
+                if !quiet && replay.is_consistent() && !replay.is_stale()
+                    && let Some((side, price, qty)) = queue.take()
+                    && replay.place_order(side, price, qty).is_none()
+                {
+                    eprintln!("--queue: {:?} at {} crosses the book at the start point; the virtual order was not placed.", side, price);
+                }
+                replay.apply_normalized(&ev);
+                if quiet { continue; }
+                match &mut rows {
+                    Some(rows) => {
+                        rows.emit(&ev, &ticker, &replay, utc_offset)?;
+                        continue;
+                    }
+                    None if args.print_book => print_book_event(&ev, &ticker, utc_offset),
+                    None => {}
+                }
+                match &ev.kind {
+                    NormalizedKind::Trade(t) if args.print_trades => {
+                        let kind = if t.history { "hist" } else { "new" };
+                        println!(
+                            "TRADE {} seq={} ts={} server_ns={:?} num={} price={} qty={} vol={} type={} buy_agent={} sell_agent={} edit={}",
+                            kind, ev.seq, t.date_str, t.server_unix_ns, t.trade_number, t.price, t.qty, t.volume, t.trade_type, t.buy_agent, t.sell_agent, t.edit_flag
+                        );
+                    }
+                    NormalizedKind::Trade(_) | NormalizedKind::State { .. } => {}
+                    kind if args.dump => print_top(&replay, args.top, &format!("seq={} {}", ev.seq, describe(kind))),
+                    _ => {}
                 }
             }
         }
     }
+    if let Some(rows) = rows { rows.w.finish()?; }
+    for path in rdr.unclean_parts() {
+        eprintln!("Warning: {:?} has no trailer; the recorder did not shut down cleanly (or the file predates trailers).", path);
+    }
+    let book = replay.book();
     eprintln!("Read {} frames. Final book: {} bids, {} asks.", frames, book.buys.len(), book.sells.len());
+    if checkpoints_ok + checkpoints_bad > 0 {
+        eprintln!("Checkpoints: {} verified, {} mismatched.", checkpoints_ok, checkpoints_bad);
+    }
+    let anomalies = replay.anomalies();
+    if anomalies.total() > 0 {
+        eprintln!("Skipped {} book events: {} with an unknown action, {} with an unknown side.", anomalies.total(), anomalies.unknown_action, anomalies.unknown_side);
+    }
+    if gaps + resumes > 0 {
+        eprintln!("Gaps: {}, resumes: {}{}.", gaps, resumes, if replay.is_stale() { "; final book is stale" } else { "" });
+    }
+    if let Some(orders) = replay.orders() {
+        if let Some(id) = args.offer { print_offer(&replay, orders, id); }
+        if args.order_stats { print_order_stats(orders); }
+    }
+    if let Some(order) = replay.virtual_orders().first() { print_queue(order); }
+    if queue.is_some() { eprintln!("--queue: no consistent book at the start point; the virtual order was not placed."); }
     Ok(())
 }
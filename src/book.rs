@@ -10,10 +10,21 @@
 //! by the recorder and returns vectors of entries along with footer flags.
 //! The [`OB_LAST_PACKET`] flag indicates that the block completes the current
 //! multi-packet transmission for the side.
+//!
+//! [`BookAction`] decodes the raw `nAction`/`nSide` codes of an event into a
+//! typed action with a [`Side`], folding the `has_*` flags into `Option`s;
+//! codes the DLL does not define are reported as a [`BookAnomaly`].
+//! [`BookBuilder`] ties these together: it takes Offer Book V2 events as
+//! recorded, dispatches on the decoded action, counts anomalies, and
+//! assembles FullBook packets (see [`PendingFullBook`]) before replacing a
+//! side.
 use anyhow::{bail, Result};
-use crate::record::RawArrayBlock;
+use crate::l2::L2Book;
+use crate::orders::OfferIndex;
+use crate::record::{EventKind, KindView, RawArrayBlock};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
     /// Price level.
     pub price: f64,
@@ -27,7 +38,28 @@ pub struct Entry {
     pub date: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+/// Book side, as carried by Offer Book V2's `nSide`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Side {
+    /// Buy side (`nSide` 0).
+    Bid,
+    /// Sell side (`nSide` 1).
+    Ask,
+}
+
+impl Side {
+    /// Side for a raw `nSide` value, if it is one the DLL defines.
+    pub fn from_raw(n_side: i32) -> Option<Side> {
+        match n_side { 0 => Some(Side::Bid), 1 => Some(Side::Ask), _ => None }
+    }
+
+    /// Raw `nSide` value of this side.
+    pub fn raw(self) -> i32 {
+        match self { Side::Bid => 0, Side::Ask => 1 }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Book {
     /// Buy side, best price at index 0.
     pub buys: Vec<Entry>, // index 0 = best bid
@@ -42,6 +74,29 @@ impl Book {
         if let Some(s) = sell { self.sells = s; }
     }
 
+    /// Stable 64-bit FNV-1a digest of both sides, in order. Equal books hash
+    /// equal across runs and platforms, so a replay can compare its state with
+    /// a recorded checkpoint.
+    pub fn state_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+        let mut h = FNV_OFFSET;
+        let mut eat = |bytes: &[u8]| for b in bytes { h = (h ^ *b as u64).wrapping_mul(FNV_PRIME); };
+        for side in [&self.buys, &self.sells] {
+            eat(&(side.len() as u64).to_le_bytes());
+            for e in side {
+                eat(&e.price.to_bits().to_le_bytes());
+                eat(&e.qty.to_le_bytes());
+                eat(&e.agent.to_le_bytes());
+                eat(&e.offer_id.to_le_bytes());
+                let date = e.date.as_deref().unwrap_or("");
+                eat(&(date.len() as u64).to_le_bytes());
+                eat(date.as_bytes());
+            }
+        }
+        h
+    }
+
     /// Convert `nPosition` (index from end) to zero-based index from start.
     fn index_from_end(len: usize, n_position: i32) -> Option<usize> {
         if n_position < 0 { return None; }
@@ -50,43 +105,185 @@ impl Book {
         Some(len - pos - 1)
     }
 
-    /// Insert a new entry at a position derived from `nPosition`.
-    /// Insert a new entry at a position derived from `nPosition`.
-    pub fn apply_add(&mut self, side: i32, n_position: i32, e: Entry) {
-        let v = match side { 0 => &mut self.buys, 1 => &mut self.sells, _ => return };
-        if v.is_empty() { v.push(e); return; }
+    fn side_mut(&mut self, side: Side) -> &mut Vec<Entry> {
+        match side { Side::Bid => &mut self.buys, Side::Ask => &mut self.sells }
+    }
+
+    /// Entries of `side`, best first.
+    pub fn side(&self, side: Side) -> &[Entry] {
+        match side { Side::Bid => &self.buys, Side::Ask => &self.sells }
+    }
+
+    /// Entry at the position derived from `nPosition`.
+    pub fn entry(&self, side: Side, n_position: i32) -> Option<&Entry> {
+        let v = self.side(side);
+        v.get(Self::index_from_end(v.len(), n_position)?)
+    }
+
+    /// Entries an `atDeleteFrom` at `n_position` would remove.
+    pub fn entries_from(&self, side: Side, n_position: i32) -> &[Entry] {
+        let v = self.side(side);
+        Self::index_from_end(v.len(), n_position).map_or(&[], |idx| &v[idx..])
+    }
+
+    /// Insert a new entry at a position derived from `nPosition`. Returns
+    /// the index (from the best end) it was inserted at.
+    pub fn apply_add(&mut self, side: Side, n_position: i32, e: Entry) -> usize {
+        let v = self.side_mut(side);
         let insert_at = match Self::index_from_end(v.len(), n_position) {
             Some(idx) => (idx + 1).min(v.len()),
             None => v.len(),
         };
         v.insert(insert_at, e);
+        insert_at
     }
 
     /// Edit an existing entry at the position derived from `nPosition`.
-    pub fn apply_edit(&mut self, side: i32, n_position: i32, e: Entry, has_price: bool, has_qtd: bool, has_agent: bool, has_offer_id: bool, has_date: bool) {
-        let v = match side { 0 => &mut self.buys, 1 => &mut self.sells, _ => return };
-        if let Some(idx) = Self::index_from_end(v.len(), n_position) {
-            if let Some(cur) = v.get_mut(idx) {
-                if has_price { cur.price = e.price; }
-                if has_qtd { cur.qty = e.qty; }
-                if has_agent { cur.agent = e.agent; }
-                if has_offer_id { cur.offer_id = e.offer_id; }
-                if has_date { cur.date = e.date; }
-            }
-        }
+    /// Returns its index and previous value, if there was one.
+    pub fn apply_edit(&mut self, side: Side, n_position: i32, edit: EntryEdit) -> Option<(usize, Entry)> {
+        let v = self.side_mut(side);
+        let idx = Self::index_from_end(v.len(), n_position)?;
+        let cur = &mut v[idx];
+        let old = cur.clone();
+        if let Some(price) = edit.price { cur.price = price; }
+        if let Some(qty) = edit.qty { cur.qty = qty; }
+        if let Some(agent) = edit.agent { cur.agent = agent; }
+        if let Some(offer_id) = edit.offer_id { cur.offer_id = offer_id; }
+        if let Some(date) = edit.date { cur.date = date; }
+        Some((idx, old))
     }
 
     /// Remove a single entry at the position derived from `nPosition`.
-    pub fn apply_delete(&mut self, side: i32, n_position: i32) {
-        let v = match side { 0 => &mut self.buys, 1 => &mut self.sells, _ => return };
-        if let Some(idx) = Self::index_from_end(v.len(), n_position) { let _ = v.remove(idx); }
+    /// Returns its index and the entry, if there was one.
+    pub fn apply_delete(&mut self, side: Side, n_position: i32) -> Option<(usize, Entry)> {
+        let v = self.side_mut(side);
+        let idx = Self::index_from_end(v.len(), n_position)?;
+        Some((idx, v.remove(idx)))
     }
 
     /// Remove all entries from the derived index (inclusive) to the end of the side.
-    pub fn apply_delete_from(&mut self, side: i32, n_position: i32) {
-        let v = match side { 0 => &mut self.buys, 1 => &mut self.sells, _ => return };
-        if let Some(idx) = Self::index_from_end(v.len(), n_position) { v.truncate(idx); }
+    /// Returns the removed entries, which started at the side's new length.
+    pub fn apply_delete_from(&mut self, side: Side, n_position: i32) -> Vec<Entry> {
+        let v = self.side_mut(side);
+        match Self::index_from_end(v.len(), n_position) {
+            Some(idx) => v.split_off(idx),
+            None => Vec::new(),
+        }
     }
+
+    /// Apply an entry action. FullBook packets need a [`BookBuilder`] and
+    /// are ignored here.
+    pub fn apply_action(&mut self, action: BookAction) {
+        match action {
+            BookAction::Add { side, position, entry } => { self.apply_add(side, position, entry); }
+            BookAction::Edit { side, position, edit } => { self.apply_edit(side, position, edit); }
+            BookAction::Delete { side, position } => { self.apply_delete(side, position); }
+            BookAction::DeleteFrom { side, position } => { self.apply_delete_from(side, position); }
+            BookAction::FullBook { .. } => {}
+        }
+    }
+}
+
+/// Fields of an `atEdit`; `None` keeps the entry's current value (the
+/// event's `has_*` flag was clear).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntryEdit {
+    pub price: Option<f64>,
+    pub qty: Option<i64>,
+    pub agent: Option<i32>,
+    pub offer_id: Option<i64>,
+    /// `Some(None)` clears the date.
+    pub date: Option<Option<String>>,
+}
+
+/// Offer Book V2 event decoded from its raw `nAction`/`nSide` codes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookAction<'a> {
+    /// `atAdd` (0).
+    Add { side: Side, position: i32, entry: Entry },
+    /// `atEdit` (1).
+    Edit { side: Side, position: i32, edit: EntryEdit },
+    /// `atDelete` (2).
+    Delete { side: Side, position: i32 },
+    /// `atDeleteFrom` (3).
+    DeleteFrom { side: Side, position: i32 },
+    /// `atFullBook` (4): one packet, with the raw block of each side it
+    /// carries (see [`PendingFullBook`]). `nSide` is not used.
+    FullBook { bids: Option<&'a [u8]>, asks: Option<&'a [u8]> },
+}
+
+impl<'a> BookAction<'a> {
+    /// Decode a recorded event. `Ok(None)` for events other than Offer Book
+    /// V2; an error for action or side codes the DLL does not define.
+    pub fn decode(kind: KindView<'a>) -> Result<Option<Self>, BookAnomaly> {
+        let KindView::OfferBookV2 {
+            n_action, n_position: position, n_side, n_qtd, n_agent, n_offer_id, d_price,
+            has_price, has_qtd, has_date, has_offer_id, has_agent, date_str, array_sell, array_buy,
+        } = kind else { return Ok(None) };
+        if n_action == 4 {
+            return Ok(Some(BookAction::FullBook { bids: array_buy.map(|b| b.bytes), asks: array_sell.map(|s| s.bytes) }));
+        }
+        if !(0..=3).contains(&n_action) { return Err(BookAnomaly::UnknownAction(n_action)); }
+        let side = Side::from_raw(n_side).ok_or(BookAnomaly::UnknownSide { action: n_action, side: n_side })?;
+        Ok(Some(match n_action {
+            0 => BookAction::Add {
+                side,
+                position,
+                entry: Entry { price: d_price, qty: n_qtd, agent: n_agent, offer_id: n_offer_id, date: date_str.map(str::to_string) },
+            },
+            1 => BookAction::Edit {
+                side,
+                position,
+                edit: EntryEdit {
+                    price: has_price.then_some(d_price),
+                    qty: has_qtd.then_some(n_qtd),
+                    agent: has_agent.then_some(n_agent),
+                    offer_id: has_offer_id.then_some(n_offer_id),
+                    date: has_date.then(|| date_str.map(str::to_string)),
+                },
+            },
+            2 => BookAction::Delete { side, position },
+            _ => BookAction::DeleteFrom { side, position },
+        }))
+    }
+}
+
+/// Offer Book V2 event that cannot be applied to a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookAnomaly {
+    /// `nAction` outside `atAdd..=atFullBook`.
+    UnknownAction(i32),
+    /// `nSide` other than buy or sell on an entry action.
+    UnknownSide { action: i32, side: i32 },
+}
+
+impl std::fmt::Display for BookAnomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BookAnomaly::UnknownAction(a) => write!(f, "unknown book action {}", a),
+            BookAnomaly::UnknownSide { action, side } => write!(f, "unknown side {} for book action {}", side, action),
+        }
+    }
+}
+
+impl std::error::Error for BookAnomaly {}
+
+/// Book events skipped by a [`BookBuilder`], by reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AnomalyCounts {
+    pub unknown_action: u64,
+    pub unknown_side: u64,
+}
+
+impl AnomalyCounts {
+    pub fn record(&mut self, anomaly: BookAnomaly) {
+        match anomaly {
+            BookAnomaly::UnknownAction(_) => self.unknown_action += 1,
+            BookAnomaly::UnknownSide { .. } => self.unknown_side += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 { self.unknown_action + self.unknown_side }
 }
 
 /// Offer book footer flag meaning "this block is the last packet for the side".
@@ -99,7 +296,12 @@ pub const OB_LAST_PACKET: u32 = 1;
 /// `f64 price`, `i64 qty`, `i32 agent`, `i64 offer_id`, `i16 date_len`, then
 /// `date_len` bytes for a UTF-8 date string.
 pub fn parse_block_v2(block: &RawArrayBlock) -> Result<(Vec<Entry>, u32)> {
-    let bytes = &block.bytes;
+    parse_block_bytes(&block.bytes)
+}
+
+/// [`parse_block_v2`] over the raw bytes of a block, e.g. a
+/// [`crate::record::BlockView`] read in place.
+pub fn parse_block_bytes(bytes: &[u8]) -> Result<(Vec<Entry>, u32)> {
     if bytes.len() < 8 { bail!("array block too small"); }
     let mut off = 0usize;
     let read_i32 = |b: &[u8], o: &mut usize| -> i32 { let mut tmp = [0u8;4]; tmp.copy_from_slice(&b[*o..*o+4]); *o += 4; i32::from_le_bytes(tmp) };
@@ -134,42 +336,297 @@ pub fn parse_block_v2(block: &RawArrayBlock) -> Result<(Vec<Entry>, u32)> {
     Ok((out, flags))
 }
 
+/// Bid and ask sides completed by a FullBook packet, if any.
+pub type CompletedSides = (Option<Vec<Entry>>, Option<Vec<Entry>>);
+
+/// FullBook packets received for each side whose last packet has not
+/// arrived yet.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PendingFullBook {
+    bids: Vec<Entry>,
+    asks: Vec<Entry>,
+    /// A non-last bid packet arrived (it may have had no entries).
+    bids_in_progress: bool,
+    asks_in_progress: bool,
+}
+
+impl PendingFullBook {
+    /// Add the raw blocks of one FullBook packet. Returns the sides this
+    /// packet completed, with all their packets' entries in order.
+    pub fn push(&mut self, array_buy: Option<&[u8]>, array_sell: Option<&[u8]>) -> Result<CompletedSides> {
+        let side = |block: Option<&[u8]>, pend: &mut Vec<Entry>, in_progress: &mut bool| -> Result<Option<Vec<Entry>>> {
+            let Some(bytes) = block else { return Ok(None) };
+            let (mut entries, flags) = parse_block_bytes(bytes)?;
+            pend.append(&mut entries);
+            *in_progress = flags & OB_LAST_PACKET == 0;
+            Ok((!*in_progress).then(|| std::mem::take(pend)))
+        };
+        Ok((
+            side(array_buy, &mut self.bids, &mut self.bids_in_progress)?,
+            side(array_sell, &mut self.asks, &mut self.asks_in_progress)?,
+        ))
+    }
+
+    /// `true` when no side is waiting for more packets.
+    pub fn is_empty(&self) -> bool { !self.bids_in_progress && !self.asks_in_progress }
+
+    /// Drop the packets received so far.
+    pub fn clear(&mut self) { *self = Self::default(); }
+}
+
+/// Change to the entries of a book side made by one action, as reported by
+/// [`BookBuilder::apply_observed`]. Indexes count from the best end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OfferChange<'a> {
+    /// `entry` was inserted at `index`.
+    Added { side: Side, index: usize, entry: &'a Entry },
+    /// The entry at `index` changed from `old` to `new`.
+    Edited { side: Side, index: usize, old: &'a Entry, new: &'a Entry },
+    /// `entry` was removed from `index` by a Delete, or by a DeleteFrom
+    /// (`cascade`).
+    Removed { side: Side, index: usize, entry: &'a Entry, cascade: bool },
+    /// A FullBook replaced the side's entries.
+    Replaced { side: Side, old: &'a [Entry], new: &'a [Entry] },
+}
+
+/// Where an offer is in the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfferPosition {
+    pub side: Side,
+    /// Index from the best end.
+    pub index: usize,
+    /// The same position as the DLL's `nPosition` (counted from the worst end).
+    pub n_position: i32,
+}
+
+/// [`Book`] fed with Offer Book V2 events, including FullBook snapshots
+/// split across packets.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BookBuilder {
+    book: Book,
+    pending: PendingFullBook,
+    /// An assembled FullBook replaced one side and the other is still
+    /// arriving (see [`BookBuilder::apply_assembled`]).
+    pending_assembled: bool,
+    anomalies: AnomalyCounts,
+    /// Price levels, when enabled with [`BookBuilder::with_levels`].
+    levels: Option<L2Book>,
+    /// Offer positions, when enabled with [`BookBuilder::with_offer_index`].
+    offers: Option<OfferIndex>,
+}
+
+impl BookBuilder {
+    pub fn new() -> Self { Self::default() }
+
+    /// Builder continuing from `book`, with no FullBook in progress.
+    pub fn from_book(book: Book) -> Self { BookBuilder { book, ..Self::default() } }
+
+    /// Current book. Mid-FullBook, sides not yet replaced still hold their
+    /// previous entries.
+    pub fn book(&self) -> &Book { &self.book }
+
+    pub fn into_book(self) -> Book { self.book }
+
+    /// Also maintain the book's price levels (see [`crate::l2`]).
+    pub fn with_levels(mut self) -> Self {
+        self.levels = Some(L2Book::from_book(&self.book));
+        self
+    }
+
+    /// Price levels of the current book, if enabled.
+    pub fn levels(&self) -> Option<&L2Book> { self.levels.as_ref() }
+
+    /// Also maintain an `offer_id` to position index (see
+    /// [`crate::orders::OfferIndex`]), used by [`Self::find_offer`].
+    pub fn with_offer_index(mut self) -> Self {
+        self.offers = Some(OfferIndex::from_book(&self.book));
+        self
+    }
+
+    /// `false` while a multi-packet FullBook is being accumulated.
+    pub fn is_consistent(&self) -> bool { self.pending.is_empty() && !self.pending_assembled }
+
+    /// Events skipped so far for an unknown action or side.
+    pub fn anomalies(&self) -> AnomalyCounts { self.anomalies }
+
+    /// Apply one event. Events other than Offer Book V2 are ignored, and so
+    /// are unknown actions or sides, which are counted (see
+    /// [`Self::anomalies`]). Returns `true` for a FullBook packet after which
+    /// no side is pending, i.e. a complete snapshot now makes up the book.
+    pub fn apply(&mut self, kind: &EventKind) -> Result<bool> { self.apply_view(KindView::from(kind)) }
+
+    /// [`Self::apply`] for an event read in place.
+    pub fn apply_view(&mut self, kind: KindView) -> Result<bool> {
+        match self.decode(kind) {
+            Some(action) => self.apply_action(action),
+            None => Ok(false),
+        }
+    }
+
+    /// [`Self::apply`] for a decoded event.
+    pub fn apply_action(&mut self, action: BookAction) -> Result<bool> { self.apply_observed(action, &mut |_| {}) }
+
+    /// [`Self::apply_action`], passing every change it makes to the book's
+    /// entries to `observe`.
+    pub fn apply_observed(&mut self, action: BookAction, observe: &mut dyn FnMut(&OfferChange)) -> Result<bool> {
+        let BookAction::FullBook { bids, asks } = action else {
+            self.apply_entry_observed(action, observe);
+            return Ok(false);
+        };
+        let (bids, asks) = self.pending.push(bids, asks)?;
+        self.replace_sides(bids, asks, observe);
+        self.pending_assembled = false;
+        Ok(self.is_consistent())
+    }
+
+    /// Decode `kind`, counting an anomaly if it has an unknown action or
+    /// side (see [`Self::apply`]).
+    pub fn decode<'a>(&mut self, kind: KindView<'a>) -> Option<BookAction<'a>> {
+        BookAction::decode(kind).unwrap_or_else(|anomaly| {
+            self.anomalies.record(anomaly);
+            None
+        })
+    }
+
+    /// Apply an entry action, e.g. of a normalized event. FullBook packets
+    /// are ignored.
+    pub fn apply_entry(&mut self, action: BookAction) { self.apply_entry_observed(action, &mut |_| {}) }
+
+    /// [`Self::apply_entry`], passing the change to `observe`.
+    pub fn apply_entry_observed(&mut self, action: BookAction, observe: &mut dyn FnMut(&OfferChange)) {
+        let book = &mut self.book;
+        let mut notify = |book: &Book, change: OfferChange| {
+            if let Some(levels) = &mut self.levels { levels.apply_change(&change); }
+            if let Some(offers) = &mut self.offers { offers.apply_change(&change, book); }
+            observe(&change);
+        };
+        match action {
+            BookAction::Add { side, position, entry } => {
+                let index = book.apply_add(side, position, entry);
+                notify(book, OfferChange::Added { side, index, entry: &book.side(side)[index] });
+            }
+            BookAction::Edit { side, position, edit } => {
+                if let Some((index, old)) = book.apply_edit(side, position, edit) {
+                    notify(book, OfferChange::Edited { side, index, old: &old, new: &book.side(side)[index] });
+                }
+            }
+            BookAction::Delete { side, position } => {
+                if let Some((index, entry)) = book.apply_delete(side, position) {
+                    notify(book, OfferChange::Removed { side, index, entry: &entry, cascade: false });
+                }
+            }
+            BookAction::DeleteFrom { side, position } => {
+                let removed = book.apply_delete_from(side, position);
+                let start = book.side(side).len();
+                for (k, entry) in removed.iter().enumerate() {
+                    notify(book, OfferChange::Removed { side, index: start + k, entry, cascade: true });
+                }
+            }
+            BookAction::FullBook { .. } => {}
+        }
+    }
+
+    fn replace_sides(&mut self, bids: Option<Vec<Entry>>, asks: Option<Vec<Entry>>, observe: &mut dyn FnMut(&OfferChange)) {
+        for (side, new) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            let Some(new) = new else { continue };
+            let old = std::mem::replace(self.book.side_mut(side), new);
+            let change = OfferChange::Replaced { side, old: &old, new: self.book.side(side) };
+            if let Some(levels) = &mut self.levels { levels.apply_change(&change); }
+            if let Some(offers) = &mut self.offers { offers.apply_change(&change, &self.book); }
+            observe(&change);
+        }
+    }
+
+    /// Apply a FullBook whose packets were already assembled elsewhere (e.g.
+    /// a normalized event): replace the given sides; `complete` tells whether
+    /// the snapshot is whole after them.
+    pub fn apply_assembled(&mut self, bids: Option<Vec<Entry>>, asks: Option<Vec<Entry>>, complete: bool) {
+        self.apply_assembled_observed(bids, asks, complete, &mut |_| {})
+    }
+
+    /// [`Self::apply_assembled`], passing the changes to `observe`.
+    pub fn apply_assembled_observed(&mut self, bids: Option<Vec<Entry>>, asks: Option<Vec<Entry>>, complete: bool, observe: &mut dyn FnMut(&OfferChange)) {
+        self.pending.clear();
+        self.replace_sides(bids, asks, observe);
+        self.pending_assembled = !complete;
+    }
+
+    /// Position of the offer with `offer_id`, from the offer index if enabled
+    /// (see [`Self::with_offer_index`]), otherwise by scanning both sides.
+    pub fn find_offer(&self, offer_id: i64) -> Option<OfferPosition> {
+        let (side, index) = match &self.offers {
+            Some(offers) => offers.get(offer_id)?,
+            None => [Side::Bid, Side::Ask]
+                .into_iter()
+                .find_map(|side| self.book.side(side).iter().position(|e| e.offer_id == offer_id).map(|index| (side, index)))?,
+        };
+        Some(OfferPosition { side, index, n_position: (self.book.side(side).len() - index - 1) as i32 })
+    }
+
+    /// Replace the book, dropping any FullBook in progress. Anomaly counts
+    /// are kept, and so are levels and the offer index if enabled.
+    pub fn reset(&mut self, book: Book) {
+        let levels = self.levels.as_ref().map(|_| L2Book::from_book(&book));
+        let offers = self.offers.as_ref().map(|_| OfferIndex::from_book(&book));
+        *self = BookBuilder { anomalies: self.anomalies, levels, offers, ..Self::from_book(book) };
+    }
+
+    /// Drop any FullBook in progress; the book keeps its current sides.
+    pub fn abort_full_book(&mut self) {
+        self.pending.clear();
+        self.pending_assembled = false;
+    }
+}
+
+/// Book fixtures shared by the crate's tests.
 #[cfg(test)]
-mod tests {
+pub(crate) mod testing {
     use super::*;
 
-    fn make_block(entries: &[Entry], last: bool) -> RawArrayBlock {
-        let mut bytes = Vec::new();
-        let q = entries.len() as i32;
-        bytes.extend_from_slice(&q.to_le_bytes());
-        // placeholder for size
-        let size_pos = bytes.len();
+    /// Entry of agent 1 without a date.
+    pub(crate) fn entry(offer_id: i64, price: f64, qty: i64) -> Entry { Entry { price, qty, agent: 1, offer_id, date: None } }
+
+    /// Raw Offer Book V2 array block holding `entries`, flagged
+    /// [`OB_LAST_PACKET`] if `last`.
+    pub(crate) fn block(entries: &[Entry], last: bool) -> RawArrayBlock {
+        let mut bytes = (entries.len() as i32).to_le_bytes().to_vec();
+        // Size, filled in below
         bytes.extend_from_slice(&0i32.to_le_bytes());
         for e in entries {
             bytes.extend_from_slice(&e.price.to_le_bytes());
             bytes.extend_from_slice(&e.qty.to_le_bytes());
             bytes.extend_from_slice(&e.agent.to_le_bytes());
             bytes.extend_from_slice(&e.offer_id.to_le_bytes());
-            let db = e.date.clone().unwrap_or_default().into_bytes();
-            let dl = db.len() as i16;
-            bytes.extend_from_slice(&dl.to_le_bytes());
-            bytes.extend_from_slice(&db);
+            let date = e.date.clone().unwrap_or_default().into_bytes();
+            bytes.extend_from_slice(&(date.len() as i16).to_le_bytes());
+            bytes.extend_from_slice(&date);
         }
-        let flags = if last { OB_LAST_PACKET } else { 0 };
-        bytes.extend_from_slice(&flags.to_le_bytes());
-        let size = (bytes.len() as i32).to_le_bytes();
-        bytes[size_pos..size_pos+4].copy_from_slice(&size);
+        bytes.extend_from_slice(&(if last { OB_LAST_PACKET } else { 0 }).to_le_bytes());
+        let size = bytes.len() as i32;
+        bytes[4..8].copy_from_slice(&size.to_le_bytes());
         RawArrayBlock { size: entries.len() as u32, bytes }
     }
 
+    /// [`block`] of one qty-1 entry per price, with offer ids from 0.
+    pub(crate) fn price_block(prices: &[f64], last: bool) -> RawArrayBlock {
+        block(&prices.iter().enumerate().map(|(i, &p)| entry(i as i64, p, 1)).collect::<Vec<_>>(), last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{block, entry};
+    use super::*;
+    use crate::record::testing::full_book;
+
     #[test]
     fn parse_v2_roundtrip() {
         let es = vec![
             Entry { price: 101.0, qty: 2, agent: 10, offer_id: 1, date: Some("d1".into()) },
             Entry { price: 100.5, qty: 1, agent: 11, offer_id: 2, date: None },
         ];
-        let block = make_block(&es, true);
-        let (out, flags) = parse_block_v2(&block).unwrap();
+        let raw = block(&es, true);
+        let (out, flags) = parse_block_v2(&raw).unwrap();
         assert_eq!(flags & OB_LAST_PACKET, OB_LAST_PACKET);
         assert_eq!(out, es);
     }
@@ -177,48 +634,104 @@ mod tests {
     #[test]
     fn book_actions_apply() {
         let mut b = Book::default();
-        b.apply_add(0, 0, Entry { price: 100.0, qty: 1, agent: 1, offer_id: 1, date: None });
-        b.apply_add(0, 0, Entry { price: 101.0, qty: 1, agent: 1, offer_id: 2, date: None });
+        b.apply_add(Side::Bid, 0, Entry { price: 100.0, qty: 1, agent: 1, offer_id: 1, date: None });
+        b.apply_add(Side::Bid, 0, Entry { price: 101.0, qty: 1, agent: 1, offer_id: 2, date: None });
         // nPosition=0 from end => insert after last => becomes worse level
         assert_eq!(b.buys.len(), 2);
 
         // Edit best (nPosition from end: len-1 -> 1)
-        b.apply_edit(0, 1, Entry { price: 102.0, qty: 3, agent: 2, offer_id: 1, date: None }, true, true, true, true, false);
+        b.apply_edit(Side::Bid, 1, EntryEdit { price: Some(102.0), qty: Some(3), agent: Some(2), offer_id: Some(1), date: None });
         assert_eq!(b.buys[0].price, 102.0);
         assert_eq!(b.buys[0].qty, 3);
 
         // Delete worst (nPosition 0)
-        b.apply_delete(0, 0);
+        b.apply_delete(Side::Bid, 0);
         assert_eq!(b.buys.len(), 1);
 
         // Add more and delete from (truncate)
-        b.apply_add(0, 0, Entry { price: 99.0, qty: 1, agent: 3, offer_id: 3, date: None });
-        b.apply_add(0, 0, Entry { price: 98.0, qty: 1, agent: 3, offer_id: 4, date: None });
+        b.apply_add(Side::Bid, 0, Entry { price: 99.0, qty: 1, agent: 3, offer_id: 3, date: None });
+        b.apply_add(Side::Bid, 0, Entry { price: 98.0, qty: 1, agent: 3, offer_id: 4, date: None });
         // current buys: [best idx0, ..., worst]
         // nPosition=1 => idx = len-1-1 = len-2; truncate(idx) removes idx..end
         let len = b.buys.len();
-        b.apply_delete_from(0, 1);
+        b.apply_delete_from(Side::Bid, 1);
         assert_eq!(b.buys.len(), len- (len - (len-2))); // remain up to idx-1
     }
 
+    #[test]
+    fn builder_assembles_full_book_per_side() {
+        let mk = |p: f64| entry(p as i64, p, 1);
+        let mut b = BookBuilder::from_book(Book { buys: vec![mk(1.0)], sells: vec![mk(2.0)] });
+        assert!(!b.apply(&full_book(Some(block(&[mk(10.0)], false)), Some(block(&[mk(11.0)], true)))).unwrap());
+        // Asks replaced at once, bids kept until their last packet
+        assert_eq!((b.book().buys[0].price, b.book().sells[0].price), (1.0, 11.0));
+        assert!(!b.is_consistent());
+        assert!(b.apply(&full_book(Some(block(&[mk(9.0)], true)), None)).unwrap());
+        assert_eq!(b.book().buys.iter().map(|e| e.price).collect::<Vec<_>>(), [10.0, 9.0]);
+
+        // A gap drops the partial snapshot
+        assert!(!b.apply(&full_book(Some(block(&[mk(5.0)], false)), None)).unwrap());
+        b.abort_full_book();
+        assert!(b.is_consistent());
+        assert!(b.apply(&full_book(Some(block(&[mk(6.0)], true)), None)).unwrap());
+        assert_eq!(b.book().buys, [mk(6.0)]);
+
+        b.apply_assembled(Some(vec![mk(7.0)]), None, false);
+        assert!(!b.is_consistent());
+        assert!(!b.apply(&EventKind::State { state_type: 0, value: 0 }).unwrap());
+    }
+
+    #[test]
+    fn empty_first_packet_keeps_full_book_in_progress() {
+        let mut b = BookBuilder::from_book(Book { buys: vec![entry(1, 1.0, 1)], sells: vec![] });
+        assert!(!b.apply(&full_book(Some(block(&[], false)), None)).unwrap());
+        assert!(!b.is_consistent());
+        assert_eq!(b.book().buys.len(), 1);
+        assert!(b.apply(&full_book(Some(block(&[entry(2, 2.0, 1)], true)), None)).unwrap());
+        assert_eq!(b.book().buys, [entry(2, 2.0, 1)]);
+    }
+
+    #[test]
+    fn decodes_actions_and_counts_anomalies() {
+        let ob = |n_action: i32, n_side: i32| EventKind::OfferBookV2 {
+            n_action, n_position: 0, n_side, n_qtd: 5, n_agent: 7, n_offer_id: 9, d_price: 10.5,
+            has_price: true, has_qtd: false, has_date: true, has_offer_id: false, has_agent: true,
+            date_str: None, array_sell: None, array_buy: None,
+        };
+        fn decode(kind: &EventKind) -> Result<Option<BookAction<'_>>, BookAnomaly> { BookAction::decode(KindView::from(kind)) }
+        let edit = EntryEdit { price: Some(10.5), qty: None, agent: Some(7), offer_id: None, date: Some(None) };
+        assert_eq!(decode(&ob(1, 1)), Ok(Some(BookAction::Edit { side: Side::Ask, position: 0, edit })));
+        assert_eq!(decode(&ob(4, 9)), Ok(Some(BookAction::FullBook { bids: None, asks: None })));
+        assert_eq!(decode(&ob(7, 0)), Err(BookAnomaly::UnknownAction(7)));
+        assert_eq!(decode(&ob(2, 2)), Err(BookAnomaly::UnknownSide { action: 2, side: 2 }));
+        assert_eq!(decode(&EventKind::State { state_type: 0, value: 0 }), Ok(None));
+
+        let mut b = BookBuilder::new();
+        for kind in [ob(0, 0), ob(7, 0), ob(0, 2), ob(3, -1)] { assert!(!b.apply(&kind).unwrap()); }
+        assert_eq!(b.book().buys.len(), 1);
+        assert_eq!(b.anomalies(), AnomalyCounts { unknown_action: 1, unknown_side: 2 });
+        b.reset(Book::default());
+        assert_eq!(b.anomalies().total(), 3);
+    }
+
     #[test]
     fn edge_cases_out_of_range_and_empty() {
         let mut b = Book::default();
         // delete_from on empty should not panic
-        b.apply_delete_from(0, 0);
+        b.apply_delete_from(Side::Bid, 0);
         assert!(b.buys.is_empty());
 
         // edit out-of-range should do nothing
-        b.apply_edit(0, 5, Entry { price: 1.0, qty: 1, agent: 1, offer_id: 1, date: None }, true, true, true, true, true);
+        b.apply_edit(Side::Bid, 5, EntryEdit { price: Some(1.0), qty: Some(1), agent: Some(1), offer_id: Some(1), date: Some(None) });
         assert!(b.buys.is_empty());
 
         // add with out-of-range nPosition should append at end
-        b.apply_add(0, 99, Entry { price: 10.0, qty: 1, agent: 1, offer_id: 1, date: None });
+        b.apply_add(Side::Bid, 99, Entry { price: 10.0, qty: 1, agent: 1, offer_id: 1, date: None });
         assert_eq!(b.buys.len(), 1);
         assert!((b.buys[0].price - 10.0).abs() < 1e-9);
 
         // delete out-of-range should do nothing
-        b.apply_delete(0, 99);
+        b.apply_delete(Side::Bid, 99);
         assert_eq!(b.buys.len(), 1);
     }
 }
@@ -0,0 +1,681 @@
+//! Columnar export of captures as Arrow record batches.
+//!
+//! [`Exporter`] is fed the frames of a raw or normalized capture and
+//! accumulates up to three tables (see [`Table`]): trades from `NewTrade` and
+//! `HistoryTrade`, L3 book events with their fields decoded, and optionally
+//! top-N snapshots of the reconstructed book after every book event. Raw
+//! events are decoded with [`Normalizer`] and books are rebuilt with one
+//! [`Replayer`] per instrument, so rows match what the player shows; the
+//! rows themselves come from [`crate::rows`], as in the player's CSV and
+//! JSON Lines output.
+//!
+//! Times are `timestamp[ns, UTC]`: `recv_time` from `recv_unix_ns`, and
+//! `server_time` parsed from the DLL's date strings (null if unparseable).
+//!
+//! [`Batches`] drives an exporter over a whole capture and yields record
+//! batches of a bounded size, for analytics in the same process;
+//! [`write_ipc`] streams one table in the Arrow IPC stream format, which
+//! DataFusion, Polars or pandas (pyarrow) read from a pipe or a file. With the
+//! `parquet` feature, [`write_parquet`] writes each table to a Parquet file.
+use crate::normalize::{NormalizedEvent, Normalizer};
+use crate::record::{CaptureReader, FileHeader, GapMarker, RecordFrame, ResumeMarker};
+use crate::replay::Replayer;
+use crate::rows::{side_name, BookEventRow, RowKey, SnapshotRow, TradeRow};
+use anyhow::Result;
+use arrow_array::builder::{ArrayBuilder, BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, StringBuilder, TimestampNanosecondBuilder, UInt32Builder, UInt64Builder, UInt8Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Default number of rows per record batch.
+pub const DEFAULT_BATCH_ROWS: usize = 65_536;
+
+/// Table produced by an [`Exporter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Table {
+    /// One row per `NewTrade`/`HistoryTrade`.
+    Trades,
+    /// One row per book action; a FullBook is one row with its side sizes.
+    BookEvents,
+    /// Top-N bids and asks after every book event of an instrument whose
+    /// book is reliable (not stale and not mid-FullBook).
+    Snapshots,
+}
+
+impl Table {
+    /// File stem and display name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Table::Trades => "trades",
+            Table::BookEvents => "book_events",
+            Table::Snapshots => "snapshots",
+        }
+    }
+}
+
+impl std::str::FromStr for Table {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "trades" => Ok(Table::Trades),
+            "book_events" | "book-events" => Ok(Table::BookEvents),
+            "snapshots" => Ok(Table::Snapshots),
+            _ => Err(format!("unknown table {s:?} (expected trades, book_events or snapshots)")),
+        }
+    }
+}
+
+fn timestamp(name: &str, nullable: bool) -> Field {
+    Field::new(name, DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())), nullable)
+}
+
+/// Columns shared by every table.
+fn key_fields() -> Vec<Field> {
+    vec![
+        Field::new("seq", DataType::UInt64, false),
+        Field::new("instrument", DataType::UInt32, false),
+        Field::new("ticker", DataType::Utf8, false),
+        timestamp("recv_time", false),
+    ]
+}
+
+/// Schema of [`Table::Trades`].
+pub fn trades_schema() -> SchemaRef {
+    let mut fields = key_fields();
+    fields.extend([
+        timestamp("server_time", true),
+        Field::new("date_str", DataType::Utf8, false),
+        Field::new("history", DataType::Boolean, false),
+        Field::new("trade_number", DataType::UInt32, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("volume", DataType::Float64, false),
+        Field::new("qty", DataType::Int32, false),
+        Field::new("buy_agent", DataType::Int32, false),
+        Field::new("sell_agent", DataType::Int32, false),
+        Field::new("trade_type", DataType::Int32, false),
+        Field::new("edit_flag", DataType::UInt8, false),
+    ]);
+    Arc::new(Schema::new(fields))
+}
+
+/// Schema of [`Table::BookEvents`]. `action` is one of `add`, `edit`,
+/// `delete`, `delete_from`, `full_book`; fields an action does not carry
+/// are null.
+pub fn book_events_schema() -> SchemaRef {
+    let mut fields = key_fields();
+    fields.extend([
+        Field::new("action", DataType::Utf8, false),
+        Field::new("side", DataType::Utf8, true),
+        Field::new("position", DataType::Int32, true),
+        Field::new("price", DataType::Float64, true),
+        Field::new("qty", DataType::Int64, true),
+        Field::new("agent", DataType::Int32, true),
+        Field::new("offer_id", DataType::Int64, true),
+        Field::new("date", DataType::Utf8, true),
+        timestamp("server_time", true),
+        Field::new("bids", DataType::UInt32, true),
+        Field::new("asks", DataType::UInt32, true),
+        Field::new("complete", DataType::Boolean, true),
+    ]);
+    Arc::new(Schema::new(fields))
+}
+
+/// Schema of [`Table::Snapshots`] with `top` levels per side:
+/// `bid_price_0`, `bid_qty_0`, ... `ask_qty_{top-1}` (null past the book's
+/// depth). Levels are individual offers, best first.
+pub fn snapshots_schema(top: usize) -> SchemaRef {
+    let mut fields = key_fields();
+    for side in ["bid", "ask"] {
+        for i in 0..top {
+            fields.push(Field::new(format!("{side}_price_{i}"), DataType::Float64, true));
+            fields.push(Field::new(format!("{side}_qty_{i}"), DataType::Int64, true));
+        }
+    }
+    Arc::new(Schema::new(fields))
+}
+
+/// Builders of the columns from [`key_fields`].
+#[derive(Debug, Default)]
+struct KeyColumns {
+    seq: UInt64Builder,
+    instrument: UInt32Builder,
+    ticker: StringBuilder,
+    recv_time: TimestampNanosecondBuilder,
+}
+
+impl KeyColumns {
+    fn push(&mut self, key: &RowKey) {
+        self.seq.append_value(key.seq);
+        self.instrument.append_value(key.instrument);
+        self.ticker.append_value(&key.ticker);
+        self.recv_time.append_value(key.recv_unix_ns as i64);
+    }
+
+    fn len(&self) -> usize { self.seq.len() }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.seq.finish()),
+            Arc::new(self.instrument.finish()),
+            Arc::new(self.ticker.finish()),
+            Arc::new(self.recv_time.finish().with_timezone("UTC")),
+        ]
+    }
+}
+
+#[derive(Debug, Default)]
+struct TradeColumns {
+    key: KeyColumns,
+    server_time: TimestampNanosecondBuilder,
+    date_str: StringBuilder,
+    history: BooleanBuilder,
+    trade_number: UInt32Builder,
+    price: Float64Builder,
+    volume: Float64Builder,
+    qty: Int32Builder,
+    buy_agent: Int32Builder,
+    sell_agent: Int32Builder,
+    trade_type: Int32Builder,
+    edit_flag: UInt8Builder,
+}
+
+impl TradeColumns {
+    fn push(&mut self, row: &TradeRow) {
+        let t = row.trade;
+        self.key.push(&row.key);
+        self.server_time.append_option(t.server_unix_ns.map(|ns| ns as i64));
+        self.date_str.append_value(&t.date_str);
+        self.history.append_value(t.history);
+        self.trade_number.append_value(t.trade_number);
+        self.price.append_value(t.price);
+        self.volume.append_value(t.volume);
+        self.qty.append_value(t.qty);
+        self.buy_agent.append_value(t.buy_agent);
+        self.sell_agent.append_value(t.sell_agent);
+        self.trade_type.append_value(t.trade_type);
+        self.edit_flag.append_value(t.edit_flag);
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        let mut cols = self.key.finish();
+        cols.extend([
+            Arc::new(self.server_time.finish().with_timezone("UTC")) as ArrayRef,
+            Arc::new(self.date_str.finish()),
+            Arc::new(self.history.finish()),
+            Arc::new(self.trade_number.finish()),
+            Arc::new(self.price.finish()),
+            Arc::new(self.volume.finish()),
+            Arc::new(self.qty.finish()),
+            Arc::new(self.buy_agent.finish()),
+            Arc::new(self.sell_agent.finish()),
+            Arc::new(self.trade_type.finish()),
+            Arc::new(self.edit_flag.finish()),
+        ]);
+        cols
+    }
+}
+
+#[derive(Debug, Default)]
+struct BookEventColumns {
+    key: KeyColumns,
+    action: StringBuilder,
+    side: StringBuilder,
+    position: Int32Builder,
+    price: Float64Builder,
+    qty: Int64Builder,
+    agent: Int32Builder,
+    offer_id: Int64Builder,
+    date: StringBuilder,
+    server_time: TimestampNanosecondBuilder,
+    bids: UInt32Builder,
+    asks: UInt32Builder,
+    complete: BooleanBuilder,
+}
+
+impl BookEventColumns {
+    fn push(&mut self, row: &BookEventRow) {
+        self.key.push(&row.key);
+        self.action.append_value(row.action);
+        self.side.append_option(row.side.map(side_name));
+        self.position.append_option(row.position);
+        self.price.append_option(row.price);
+        self.qty.append_option(row.qty);
+        self.agent.append_option(row.agent);
+        self.offer_id.append_option(row.offer_id);
+        self.date.append_option(row.date.as_deref());
+        self.server_time.append_option(row.server_unix_ns.map(|ns| ns as i64));
+        self.bids.append_option(row.bids);
+        self.asks.append_option(row.asks);
+        self.complete.append_option(row.complete);
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        let mut cols = self.key.finish();
+        cols.extend([
+            Arc::new(self.action.finish()) as ArrayRef,
+            Arc::new(self.side.finish()),
+            Arc::new(self.position.finish()),
+            Arc::new(self.price.finish()),
+            Arc::new(self.qty.finish()),
+            Arc::new(self.agent.finish()),
+            Arc::new(self.offer_id.finish()),
+            Arc::new(self.date.finish()),
+            Arc::new(self.server_time.finish().with_timezone("UTC")),
+            Arc::new(self.bids.finish()),
+            Arc::new(self.asks.finish()),
+            Arc::new(self.complete.finish()),
+        ]);
+        cols
+    }
+}
+
+#[derive(Debug)]
+struct SnapshotColumns {
+    key: KeyColumns,
+    /// Price and quantity builders of each level, bids then asks.
+    levels: Vec<(Float64Builder, Int64Builder)>,
+}
+
+impl SnapshotColumns {
+    fn new(top: usize) -> Self {
+        SnapshotColumns { key: KeyColumns::default(), levels: (0..2 * top).map(|_| Default::default()).collect() }
+    }
+
+    fn push(&mut self, row: &SnapshotRow) {
+        self.key.push(&row.key);
+        let (bids, asks) = self.levels.split_at_mut(row.top);
+        for (side, builders) in [(&row.book.buys, bids), (&row.book.sells, asks)] {
+            for (i, (price, qty)) in builders.iter_mut().enumerate() {
+                price.append_option(side.get(i).map(|e| e.price));
+                qty.append_option(side.get(i).map(|e| e.qty));
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        let mut cols = self.key.finish();
+        for (price, qty) in &mut self.levels {
+            cols.push(Arc::new(price.finish()));
+            cols.push(Arc::new(qty.finish()));
+        }
+        cols
+    }
+}
+
+/// Accumulates the rows of a capture's tables; see the module docs.
+#[derive(Debug)]
+pub struct Exporter {
+    normalizer: Normalizer,
+    utc_offset_secs: i32,
+    replayers: BTreeMap<u32, Replayer>,
+    tickers: BTreeMap<u32, String>,
+    trades: TradeColumns,
+    book_events: BookEventColumns,
+    snapshots: Option<(usize, SnapshotColumns)>,
+}
+
+impl Exporter {
+    /// Exporter for the capture with `header`, reading server times at
+    /// `utc_offset_secs` from UTC. `top` enables [`Table::Snapshots`] with
+    /// that many levels per side.
+    pub fn new(header: &FileHeader, utc_offset_secs: i32, top: Option<usize>) -> Self {
+        Exporter {
+            normalizer: Normalizer::new(utc_offset_secs),
+            utc_offset_secs,
+            replayers: BTreeMap::new(),
+            tickers: header.instruments().into_iter().map(|i| (i.id, i.ticker)).collect(),
+            trades: TradeColumns::default(),
+            book_events: BookEventColumns::default(),
+            snapshots: top.map(|n| (n, SnapshotColumns::new(n))),
+        }
+    }
+
+    /// Tables this exporter produces.
+    pub fn tables(&self) -> Vec<Table> {
+        let mut tables = vec![Table::Trades, Table::BookEvents];
+        if self.snapshots.is_some() { tables.push(Table::Snapshots); }
+        tables
+    }
+
+    /// Schema of `table`.
+    pub fn schema(&self, table: Table) -> SchemaRef {
+        match table {
+            Table::Trades => trades_schema(),
+            Table::BookEvents => book_events_schema(),
+            Table::Snapshots => snapshots_schema(self.snapshots.as_ref().map_or(0, |(n, _)| *n)),
+        }
+    }
+
+    /// Rows of `table` accumulated since its last batch.
+    pub fn rows(&self, table: Table) -> usize {
+        match table {
+            Table::Trades => self.trades.key.len(),
+            Table::BookEvents => self.book_events.key.len(),
+            Table::Snapshots => self.snapshots.as_ref().map_or(0, |(_, s)| s.key.len()),
+        }
+    }
+
+    /// Book events skipped for an unknown action or side so far.
+    pub fn unknown(&self) -> u64 { self.normalizer.unknown() }
+
+    /// Feed one frame of the capture, in file order.
+    pub fn push(&mut self, frame: &RecordFrame) -> Result<()> {
+        match frame {
+            RecordFrame::Event(ev) => {
+                if let Some(ev) = self.normalizer.apply(ev)? { self.push_normalized(&ev); }
+            }
+            RecordFrame::Normalized(ev) => self.push_normalized(ev),
+            RecordFrame::Checkpoint(cp) => {
+                self.replayers.entry(cp.instrument).or_insert_with(|| Replayer::for_instrument(cp.instrument)).apply_checkpoint(cp);
+            }
+            RecordFrame::Gap(GapMarker { scope, .. }) | RecordFrame::Resumed(ResumeMarker { scope, .. }) => {
+                self.normalizer.apply_gap(scope);
+                for (_, r) in self.replayers.iter_mut().filter(|(id, _)| scope.covers(**id)) { r.apply_gap(); }
+            }
+            RecordFrame::Instrument(i) => { self.tickers.insert(i.id, i.ticker.clone()); }
+            RecordFrame::Header(_) | RecordFrame::Trailer(_) => {}
+        }
+        Ok(())
+    }
+
+    fn push_normalized(&mut self, ev: &NormalizedEvent) {
+        let ticker = self.tickers.get(&ev.instrument).map(String::as_str).unwrap_or("");
+        if let Some(row) = TradeRow::new(ev, ticker) {
+            self.trades.push(&row);
+            return;
+        }
+        let Some(row) = BookEventRow::new(ev, ticker, self.utc_offset_secs) else { return };
+        self.book_events.push(&row);
+        let replay = self.replayers.entry(ev.instrument).or_insert_with(|| Replayer::for_instrument(ev.instrument));
+        replay.apply_normalized(ev);
+        if let Some((top, snapshots)) = &mut self.snapshots
+            && replay.is_consistent()
+            && !replay.is_stale()
+        {
+            snapshots.push(&SnapshotRow::new(ev, ticker, replay.book(), *top));
+        }
+    }
+
+    /// Take the rows of `table` accumulated so far as one batch.
+    pub fn take_batch(&mut self, table: Table) -> Result<RecordBatch> {
+        let schema = self.schema(table);
+        let columns = match table {
+            Table::Trades => self.trades.finish(),
+            Table::BookEvents => self.book_events.finish(),
+            Table::Snapshots => match &mut self.snapshots {
+                Some((_, s)) => s.finish(),
+                None => return Ok(RecordBatch::new_empty(schema)),
+            },
+        };
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+/// Record batches of a whole capture, in the order their tables fill up.
+///
+/// Every frame is fed to an [`Exporter`]; a batch is yielded as soon as its
+/// table holds `batch_rows` rows, and the remaining rows of each table once
+/// the capture ends. Each item is a table and a non-empty batch.
+pub struct Batches<R: std::io::Read> {
+    reader: CaptureReader<R>,
+    exporter: Exporter,
+    batch_rows: usize,
+    ready: std::collections::VecDeque<(Table, RecordBatch)>,
+    done: bool,
+}
+
+impl<R: std::io::Read> Batches<R> {
+    /// Batches of the capture read by `reader`, of up to `batch_rows` rows.
+    /// See [`Exporter::new`] for `utc_offset_secs` and `top`.
+    pub fn new(reader: CaptureReader<R>, utc_offset_secs: i32, top: Option<usize>, batch_rows: usize) -> Self {
+        let exporter = Exporter::new(reader.header(), utc_offset_secs, top);
+        Batches { reader, exporter, batch_rows: batch_rows.max(1), ready: Default::default(), done: false }
+    }
+
+    /// Exporter being fed, for its tables, schemas and counters.
+    pub fn exporter(&self) -> &Exporter { &self.exporter }
+
+    /// Queue a batch of every table with at least `min_rows` rows.
+    fn take_full(&mut self, min_rows: usize) -> Result<()> {
+        for table in self.exporter.tables() {
+            let rows = self.exporter.rows(table);
+            if rows > 0 && rows >= min_rows { self.ready.push_back((table, self.exporter.take_batch(table)?)); }
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self) -> Result<Option<(Table, RecordBatch)>> {
+        while self.ready.is_empty() && !self.done {
+            match self.reader.next() {
+                Some(frame) => {
+                    self.exporter.push(&frame?)?;
+                    self.take_full(self.batch_rows)?;
+                }
+                None => {
+                    self.done = true;
+                    self.take_full(1)?;
+                }
+            }
+        }
+        Ok(self.ready.pop_front())
+    }
+}
+
+impl<R: std::io::Read> Iterator for Batches<R> {
+    type Item = Result<(Table, RecordBatch)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(batch) => batch.map(Ok),
+            Err(e) => {
+                self.done = true;
+                self.ready.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Stream `table` of the capture read by `reader` to `out` in the Arrow IPC
+/// stream format, in batches of up to `batch_rows` rows. The stream is
+/// finished (end-of-stream marker written) before returning the number of
+/// rows. See [`Exporter::new`] for `utc_offset_secs` and `top`; the snapshots
+/// table needs `top`.
+pub fn write_ipc<R: std::io::Read, W: std::io::Write>(
+    reader: CaptureReader<R>,
+    out: W,
+    table: Table,
+    utc_offset_secs: i32,
+    top: Option<usize>,
+    batch_rows: usize,
+) -> Result<u64> {
+    if table == Table::Snapshots && top.is_none() { anyhow::bail!("the snapshots table needs a number of levels (top)"); }
+    let batches = Batches::new(reader, utc_offset_secs, top, batch_rows);
+    let mut w = arrow_ipc::writer::StreamWriter::try_new(out, &batches.exporter().schema(table))?;
+    let mut rows = 0u64;
+    for item in batches {
+        let (t, batch) = item?;
+        if t != table { continue; }
+        rows += batch.num_rows() as u64;
+        w.write(&batch)?;
+    }
+    w.finish()?;
+    Ok(rows)
+}
+
+/// Rows written per table by [`write_parquet`].
+#[cfg(feature = "parquet")]
+#[derive(Debug, Clone, Default)]
+pub struct ExportReport {
+    pub rows: BTreeMap<Table, u64>,
+    /// Output file of each table.
+    pub files: BTreeMap<Table, std::path::PathBuf>,
+    /// Book events skipped for an unknown action or side.
+    pub unknown: u64,
+}
+
+/// Export every frame of `reader` to `<dir>/<table>.parquet` (zstd
+/// compressed), in row groups of up to `batch_rows` rows. See
+/// [`Exporter::new`] for `utc_offset_secs` and `top`.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<R: std::io::Read>(
+    reader: crate::record::CaptureReader<R>,
+    dir: &std::path::Path,
+    utc_offset_secs: i32,
+    top: Option<usize>,
+    batch_rows: usize,
+) -> Result<ExportReport> {
+    use anyhow::Context;
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::{Compression, ZstdLevel};
+    use parquet::file::properties::WriterProperties;
+
+    std::fs::create_dir_all(dir).with_context(|| format!("create {:?}", dir))?;
+    let batch_rows = batch_rows.max(1);
+    let mut batches = Batches::new(reader, utc_offset_secs, top, batch_rows);
+    let ex = batches.exporter();
+    let props = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .set_max_row_group_size(batch_rows)
+        .build();
+    let mut report = ExportReport::default();
+    let mut writers = BTreeMap::new();
+    for table in ex.tables() {
+        let path = dir.join(format!("{}.parquet", table.name()));
+        let file = std::fs::File::create(&path).with_context(|| format!("create {:?}", path))?;
+        writers.insert(table, ArrowWriter::try_new(file, ex.schema(table), Some(props.clone()))?);
+        report.files.insert(table, path);
+        report.rows.insert(table, 0);
+    }
+    for item in &mut batches {
+        let (table, batch) = item?;
+        writers.get_mut(&table).expect("writer per table").write(&batch)?;
+        *report.rows.entry(table).or_default() += batch.num_rows() as u64;
+    }
+    for w in writers.into_values() { w.close()?; }
+    report.unknown = batches.exporter().unknown();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::testing::price_block;
+    use crate::record::testing::{full_book, header, offer_book};
+    use crate::record::{EventKind, EventRecord, RawArrayBlock};
+    use arrow_array::cast::AsArray;
+    use arrow_array::Array;
+    use arrow_array::types::{Float64Type, TimestampNanosecondType};
+
+    fn ev(seq: u64, kind: EventKind) -> RecordFrame {
+        RecordFrame::Event(EventRecord { seq, instrument: 0, recv_unix_ns: 1_000 + seq as u128, recv_mono_ns_from_start: 0, kind })
+    }
+
+    fn add(seq: u64, n_side: i32, price: f64) -> RecordFrame { ev(seq, offer_book(0, n_side, 0, seq as i64, price)) }
+
+    fn full(seq: u64, buy: Option<RawArrayBlock>, sell: Option<RawArrayBlock>) -> RecordFrame { ev(seq, full_book(buy, sell)) }
+
+    #[test]
+    fn builds_trade_book_and_snapshot_tables() {
+        let h = header("WINFUT");
+        let mut ex = Exporter::new(&h, 0, Some(2));
+        // The FullBook's asks complete one packet after its bids: no snapshot in between
+        let frames = vec![
+            full(0, Some(price_block(&[9.0], true)), Some(price_block(&[12.0], false))),
+            full(1, None, Some(price_block(&[], true))),
+            add(2, 0, 10.0),
+            add(3, 1, 11.0),
+            ev(4, EventKind::NewTrade {
+                date_str: "04/09/2025 10:15:30.250".into(), trade_number: 7, price: 10.5, volume: 21.0, qty: 2,
+                buy_agent: 1, sell_agent: 2, trade_type: 1, edit_flag: 0,
+            }),
+        ];
+        for f in &frames { ex.push(f).unwrap(); }
+        assert_eq!((ex.rows(Table::Trades), ex.rows(Table::BookEvents), ex.rows(Table::Snapshots)), (1, 4, 3));
+
+        let trades = ex.take_batch(Table::Trades).unwrap();
+        let server = trades.column_by_name("server_time").unwrap().as_primitive::<TimestampNanosecondType>();
+        assert_eq!(server.value(0), 1_756_980_930_250_000_000);
+        assert_eq!(trades.column_by_name("ticker").unwrap().as_string::<i32>().value(0), "WINFUT");
+
+        let book = ex.take_batch(Table::BookEvents).unwrap();
+        let actions: Vec<_> = book.column_by_name("action").unwrap().as_string::<i32>().iter().flatten().collect();
+        assert_eq!(actions, ["full_book", "full_book", "add", "add"]);
+        assert!(book.column_by_name("side").unwrap().is_null(0));
+
+        let snaps = ex.take_batch(Table::Snapshots).unwrap();
+        assert_eq!(snaps.schema().fields().len(), 4 + 8);
+        let best_bid = snaps.column_by_name("bid_price_0").unwrap().as_primitive::<Float64Type>();
+        assert_eq!(best_bid.value(0), 9.0);
+        let second_bid = snaps.column_by_name("bid_price_1").unwrap().as_primitive::<Float64Type>();
+        assert!(second_bid.is_null(0));
+        assert_eq!(second_bid.value(1), 10.0);
+        assert_eq!(ex.rows(Table::Trades), 0);
+    }
+
+    #[test]
+    fn no_snapshots_before_the_first_full_book() {
+        // The capture starts mid-stream: the adds before the FullBook leave
+        // the book unknown
+        let mut ex = Exporter::new(&header("WINFUT"), 0, Some(1));
+        let frames = [add(0, 0, 10.0), add(1, 1, 11.0), full(2, Some(price_block(&[9.0], true)), Some(price_block(&[], true))), add(3, 0, 8.0)];
+        for f in &frames { ex.push(f).unwrap(); }
+        assert_eq!((ex.rows(Table::BookEvents), ex.rows(Table::Snapshots)), (4, 2));
+    }
+
+    #[test]
+    fn ipc_stream_reads_back() {
+        use crate::record::CaptureWriter;
+        use arrow_ipc::reader::StreamReader;
+
+        let h = header("WINFUT");
+        let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+        for seq in 0..5 {
+            let RecordFrame::Event(e) = add(seq, 1, 100.0 + seq as f64) else { unreachable!() };
+            w.write_event(e).unwrap();
+        }
+        w.write_trailer().unwrap();
+        let src = w.finish().unwrap();
+
+        let batches: Vec<_> = Batches::new(CaptureReader::new(src.as_slice()).unwrap(), 0, None, 2).map(Result::unwrap).collect();
+        let sizes: Vec<_> = batches.iter().map(|(t, b)| (*t, b.num_rows())).collect();
+        assert_eq!(sizes, [(Table::BookEvents, 2), (Table::BookEvents, 2), (Table::BookEvents, 1)]);
+
+        let mut out = Vec::new();
+        let rows = write_ipc(CaptureReader::new(src.as_slice()).unwrap(), &mut out, Table::BookEvents, 0, None, 2).unwrap();
+        assert_eq!(rows, 5);
+        let reader = StreamReader::try_new(out.as_slice(), None).unwrap();
+        assert_eq!(reader.schema(), book_events_schema());
+        let read: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[2].column_by_name("price").unwrap().as_primitive::<Float64Type>().value(0), 104.0);
+        assert!(write_ipc(CaptureReader::new(src.as_slice()).unwrap(), Vec::new(), Table::Snapshots, 0, None, 2).is_err());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_files_read_back() {
+        use crate::record::{CaptureReader, CaptureWriter};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let h = header("WINFUT");
+        let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+        for seq in 0..5 {
+            let frame = if seq == 0 { full(0, Some(price_block(&[100.0], true)), Some(price_block(&[], true))) } else { add(seq, 0, 100.0 - seq as f64) };
+            let RecordFrame::Event(e) = frame else { unreachable!() };
+            w.write_event(e).unwrap();
+        }
+        w.write_trailer().unwrap();
+        let src = w.finish().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let report = write_parquet(CaptureReader::new(src.as_slice()).unwrap(), dir.path(), 0, Some(1), 2).unwrap();
+        assert_eq!(report.rows[&Table::BookEvents], 5);
+        let file = std::fs::File::open(&report.files[&Table::Snapshots]).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 3);
+        let rows: usize = reader.build().unwrap().map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows, 5);
+    }
+}
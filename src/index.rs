@@ -0,0 +1,234 @@
+//! Seek index sidecar for captures.
+//!
+//! A [`CaptureIndex`] maps event `seq` and `recv_unix_ns` to frame byte
+//! offsets so readers can jump into the middle of a capture with
+//! [`CaptureReader::seek_to`] instead of replaying it from the start.
+//!
+//! Entries are recorded every `every` frames plus at every *sync point*: a
+//! frame where replay of one instrument's book can start from an empty book
+//! (the first packet of an Offer Book V2 FullBook, or a
+//! [`RecordFrame::Checkpoint`]; in a normalized file, a FullBook carrying
+//! both sides). To reconstruct a book at some event, seek to
+//! that instrument's last sync point at or before it and replay forward.
+//!
+//! The index is stored next to the capture as `<capture>.idx` and can always
+//! be rebuilt from the capture alone with [`CaptureIndex::build`]; the
+//! recorder can also produce it while writing (see
+//! [`CaptureWriter::enable_index`]).
+//!
+//! [`CaptureWriter::enable_index`]: crate::record::CaptureWriter::enable_index
+use crate::normalize::NormalizedKind;
+use crate::record::{CaptureError, CaptureReader, EventKind, GapMarker, RecordFrame, ResumeMarker};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of an index sidecar.
+const INDEX_MAGIC: &[u8; 4] = b"MDIX";
+/// Sidecar layout version.
+const INDEX_VERSION: u16 = 2;
+
+/// Default number of frames between regular index entries.
+pub const DEFAULT_INDEX_EVERY: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Frame index in the capture (the header is frame 0).
+    pub frame: u64,
+    /// Byte offset where the frame starts.
+    pub offset: u64,
+    /// `EventRecord.seq` of the frame. For a checkpoint, the seq of the
+    /// first event after it (`checkpoint.seq + 1`).
+    pub seq: u64,
+    /// `EventRecord.recv_unix_ns` of the frame (for a checkpoint, of the
+    /// last event it includes).
+    pub recv_unix_ns: u128,
+    /// Instrument of the event or checkpoint.
+    pub instrument: u32,
+    /// Replay of `instrument` may start here with an empty book.
+    pub sync: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureIndex {
+    /// Frames between regular entries.
+    pub every: u32,
+    /// Capture size in bytes when the index was built; used to detect a
+    /// stale sidecar.
+    pub capture_len: u64,
+    /// Entries in frame order.
+    pub entries: Vec<IndexEntry>,
+}
+
+impl CaptureIndex {
+    /// Build an index by scanning `reader` to the end.
+    pub fn build<R: Read>(mut reader: CaptureReader<R>, every: u32) -> Result<Self, CaptureError> {
+        let mut b = IndexBuilder::new(every);
+        loop {
+            let (frame, offset) = (reader.frames_read(), reader.offset());
+            match reader.read_frame()? {
+                Some(f) => b.observe(frame, offset, &f),
+                None => break,
+            }
+        }
+        Ok(b.finish(reader.offset()))
+    }
+
+    /// Last sync point of `instrument` with `seq <= seq`.
+    pub fn sync_at_or_before_seq(&self, instrument: u32, seq: u64) -> Option<&IndexEntry> {
+        self.entries.iter().rev().find(|e| e.sync && e.instrument == instrument && e.seq <= seq)
+    }
+
+    /// Last sync point of `instrument` with `recv_unix_ns <= ts`.
+    pub fn sync_at_or_before_time(&self, instrument: u32, ts: u128) -> Option<&IndexEntry> {
+        self.entries.iter().rev().find(|e| e.sync && e.instrument == instrument && e.recv_unix_ns <= ts)
+    }
+
+    /// Sidecar path for `capture` (`<capture>.idx`).
+    pub fn sidecar_path(capture: impl AsRef<Path>) -> PathBuf {
+        let mut s = capture.as_ref().as_os_str().to_owned();
+        s.push(".idx");
+        PathBuf::from(s)
+    }
+
+    /// Write the index to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CaptureError> {
+        let mut w = std::io::BufWriter::new(std::fs::File::create(path)?);
+        w.write_all(INDEX_MAGIC)?;
+        w.write_all(&INDEX_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut w, self).map_err(CaptureError::Encode)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Read an index written by [`CaptureIndex::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CaptureError> {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() < 6 || &bytes[0..4] != INDEX_MAGIC {
+            return Err(CaptureError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a capture index")));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != INDEX_VERSION { return Err(CaptureError::UnsupportedVersion { version }); }
+        bincode::deserialize(&bytes[6..]).map_err(|source| CaptureError::Decode { frame: 0, offset: 6, source })
+    }
+
+    /// Load the sidecar of `capture` if it exists and matches the capture's
+    /// current size.
+    pub fn load_fresh(capture: impl AsRef<Path>) -> Option<Self> {
+        let len = std::fs::metadata(capture.as_ref()).ok()?.len();
+        let idx = Self::load(Self::sidecar_path(capture)).ok()?;
+        (idx.capture_len == len).then_some(idx)
+    }
+}
+
+/// Incremental index construction, fed one frame at a time in file order.
+#[derive(Debug, Clone)]
+pub struct IndexBuilder {
+    every: u32,
+    entries: Vec<IndexEntry>,
+    /// Instruments whose previous book event was a FullBook packet (the
+    /// next one continues it).
+    in_full_book: BTreeSet<u32>,
+}
+
+impl IndexBuilder {
+    pub fn new(every: u32) -> Self {
+        IndexBuilder { every: every.max(1), entries: Vec::new(), in_full_book: BTreeSet::new() }
+    }
+
+    /// Record `frame` (index `frame_no`, starting at byte `offset`).
+    pub fn observe(&mut self, frame_no: u64, offset: u64, frame: &RecordFrame) {
+        if let RecordFrame::Checkpoint(cp) = frame {
+            self.entries.push(IndexEntry { frame: frame_no, offset, seq: cp.seq + 1, recv_unix_ns: cp.recv_unix_ns, instrument: cp.instrument, sync: true });
+            return;
+        }
+        if let RecordFrame::Gap(GapMarker { scope, .. }) | RecordFrame::Resumed(ResumeMarker { scope, .. }) = frame {
+            // A FullBook cut short by lost events does not continue after them
+            self.in_full_book.retain(|id| !scope.covers(*id));
+            return;
+        }
+        if let RecordFrame::Normalized(ev) = frame {
+            let sync = matches!(ev.kind, NormalizedKind::FullBook { bids: Some(_), asks: Some(_), complete: true });
+            if sync || frame_no.is_multiple_of(self.every as u64) {
+                self.entries.push(IndexEntry { frame: frame_no, offset, seq: ev.seq, recv_unix_ns: ev.recv_unix_ns, instrument: ev.instrument, sync });
+            }
+            return;
+        }
+        let RecordFrame::Event(ev) = frame else { return };
+        let full_book = matches!(ev.kind, EventKind::OfferBookV2 { n_action: 4, .. });
+        let sync = full_book && !self.in_full_book.contains(&ev.instrument);
+        if matches!(ev.kind, EventKind::OfferBookV2 { .. }) {
+            if full_book { self.in_full_book.insert(ev.instrument); } else { self.in_full_book.remove(&ev.instrument); }
+        }
+        if sync || frame_no.is_multiple_of(self.every as u64) {
+            self.entries.push(IndexEntry { frame: frame_no, offset, seq: ev.seq, recv_unix_ns: ev.recv_unix_ns, instrument: ev.instrument, sync });
+        }
+    }
+
+    /// Finish the index for a capture of `capture_len` bytes.
+    pub fn finish(self, capture_len: u64) -> CaptureIndex {
+        CaptureIndex { every: self.every, capture_len, entries: self.entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::testing::{header, offer_book};
+    use crate::record::{CaptureWriter, EventRecord};
+
+    fn ev(seq: u64, n_action: i32) -> EventRecord {
+        EventRecord { seq, instrument: 0, recv_unix_ns: 1_000 + seq as u128 * 10, recv_mono_ns_from_start: 0, kind: offer_book(n_action, 0, 0, seq as i64, 1.0) }
+    }
+
+    fn capture() -> Vec<u8> {
+        let mut w = CaptureWriter::new(Vec::new(), header("T")).unwrap();
+        w.enable_index(4);
+        for seq in 0..20 {
+            // FullBook bursts (two packets) at seq 0..2 and 10..12
+            let action = if (0..2).contains(&seq) || (10..12).contains(&seq) { 4 } else { 0 };
+            w.write_event(ev(seq, action)).unwrap();
+        }
+        let idx = w.take_index().unwrap();
+        let bytes = w.finish().unwrap();
+        assert_eq!(idx, CaptureIndex::build(CaptureReader::new(bytes.as_slice()).unwrap(), 4).unwrap());
+        bytes
+    }
+
+    #[test]
+    fn sync_points_and_lookup() {
+        let bytes = capture();
+        let idx = CaptureIndex::build(CaptureReader::new(bytes.as_slice()).unwrap(), 4).unwrap();
+        assert_eq!(idx.capture_len, bytes.len() as u64);
+        let syncs: Vec<u64> = idx.entries.iter().filter(|e| e.sync).map(|e| e.seq).collect();
+        assert_eq!(syncs, vec![0, 10]);
+        assert_eq!(idx.sync_at_or_before_seq(0, 15).unwrap().seq, 10);
+        assert_eq!(idx.sync_at_or_before_time(0, 1_000 + 9 * 10).unwrap().seq, 0);
+    }
+
+    #[test]
+    fn seek_resumes_reading_at_entry() {
+        let bytes = capture();
+        let idx = CaptureIndex::build(CaptureReader::new(bytes.as_slice()).unwrap(), 4).unwrap();
+        let entry = idx.sync_at_or_before_seq(0, 12).unwrap().clone();
+        let mut r = CaptureReader::new(std::io::Cursor::new(bytes)).unwrap();
+        r.seek_to(entry.frame, entry.offset).unwrap();
+        let seqs: Vec<u64> = r.map(|f| match f.unwrap() { RecordFrame::Event(e) => e.seq, _ => unreachable!() }).collect();
+        assert_eq!(seqs, (10..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sidecar_roundtrip_and_staleness() {
+        let dir = tempfile::tempdir().unwrap();
+        let cap = dir.path().join("c.bin");
+        std::fs::write(&cap, capture()).unwrap();
+        let idx = CaptureIndex::build(CaptureReader::open(&cap).unwrap(), 4).unwrap();
+        idx.save(CaptureIndex::sidecar_path(&cap)).unwrap();
+        assert_eq!(CaptureIndex::load_fresh(&cap), Some(idx));
+        // Appending to the capture invalidates the sidecar
+        let mut f = std::fs::OpenOptions::new().append(true).open(&cap).unwrap();
+        f.write_all(&[0]).unwrap();
+        assert_eq!(CaptureIndex::load_fresh(&cap), None);
+    }
+}
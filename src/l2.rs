@@ -0,0 +1,198 @@
+//! Price-level (L2) view of a Level-3 [`Book`].
+//!
+//! [`L2Book`] groups the offers of each side by price into [`Level`]s with
+//! the total quantity, the number of offers and the agents behind them. It
+//! is kept up to date one L3 action at a time: a [`BookBuilder`] created with
+//! [`BookBuilder::with_levels`] passes it the offers each action adds, edits
+//! or removes (see [`L2Book::apply_change`]) so only their levels change, and
+//! a side is rebuilt only when a FullBook replaces it. [`L2Book::from_book`]
+//! computes the same view from scratch.
+//!
+//! [`BookBuilder`]: crate::book::BookBuilder
+//! [`BookBuilder::with_levels`]: crate::book::BookBuilder::with_levels
+use crate::book::{Book, Entry, OfferChange, Side};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Price as a map key, ordered with [`f64::total_cmp`].
+#[derive(Debug, Clone, Copy)]
+struct PriceKey(f64);
+
+impl PartialEq for PriceKey {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for PriceKey {}
+
+impl PartialOrd for PriceKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for PriceKey {
+    fn cmp(&self, other: &Self) -> Ordering { self.0.total_cmp(&other.0) }
+}
+
+/// All offers of one side at one price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub price: f64,
+    /// Sum of the offers' quantities.
+    pub qty: i64,
+    /// Number of offers.
+    pub orders: u32,
+    /// Offers per agent.
+    agents: BTreeMap<i32, u32>,
+}
+
+impl Level {
+    fn new(price: f64) -> Self { Level { price, qty: 0, orders: 0, agents: BTreeMap::new() } }
+
+    /// Distinct agents with an offer at this price, in ascending id order.
+    pub fn agents(&self) -> impl Iterator<Item = i32> + '_ { self.agents.keys().copied() }
+
+    /// Number of distinct agents.
+    pub fn agent_count(&self) -> usize { self.agents.len() }
+}
+
+/// Cumulative size of the levels within some distance of the best price.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Depth {
+    pub levels: u32,
+    pub orders: u32,
+    pub qty: i64,
+}
+
+/// Price levels of both sides of a book.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct L2Book {
+    bids: BTreeMap<PriceKey, Level>,
+    asks: BTreeMap<PriceKey, Level>,
+}
+
+impl L2Book {
+    pub fn new() -> Self { Self::default() }
+
+    /// Levels of `book`, computed from scratch.
+    pub fn from_book(book: &Book) -> Self {
+        let mut l2 = L2Book::new();
+        l2.replace_side(Side::Bid, &book.buys);
+        l2.replace_side(Side::Ask, &book.sells);
+        l2
+    }
+
+    fn side(&self, side: Side) -> &BTreeMap<PriceKey, Level> {
+        match side { Side::Bid => &self.bids, Side::Ask => &self.asks }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<PriceKey, Level> {
+        match side { Side::Bid => &mut self.bids, Side::Ask => &mut self.asks }
+    }
+
+    /// Count one more offer.
+    pub fn add(&mut self, side: Side, e: &Entry) {
+        let level = self.side_mut(side).entry(PriceKey(e.price)).or_insert_with(|| Level::new(e.price));
+        level.qty += e.qty;
+        level.orders += 1;
+        *level.agents.entry(e.agent).or_default() += 1;
+    }
+
+    /// Count one offer less; `e` must have been added before. A level left
+    /// without offers is removed.
+    pub fn remove(&mut self, side: Side, e: &Entry) {
+        let levels = self.side_mut(side);
+        let Some(level) = levels.get_mut(&PriceKey(e.price)) else { return };
+        level.qty -= e.qty;
+        level.orders = level.orders.saturating_sub(1);
+        if let Some(n) = level.agents.get_mut(&e.agent) {
+            *n -= 1;
+            if *n == 0 { level.agents.remove(&e.agent); }
+        }
+        if level.orders == 0 { levels.remove(&PriceKey(e.price)); }
+    }
+
+    /// Follow one change of the L3 book.
+    pub fn apply_change(&mut self, change: &OfferChange) {
+        match *change {
+            OfferChange::Added { side, entry, .. } => self.add(side, entry),
+            OfferChange::Edited { side, old, new, .. } => {
+                self.remove(side, old);
+                self.add(side, new);
+            }
+            OfferChange::Removed { side, entry, .. } => self.remove(side, entry),
+            OfferChange::Replaced { side, new, .. } => self.replace_side(side, new),
+        }
+    }
+
+    /// Rebuild one side from its offers.
+    pub fn replace_side(&mut self, side: Side, entries: &[Entry]) {
+        self.side_mut(side).clear();
+        for e in entries { self.add(side, e); }
+    }
+
+    /// Levels of `side`, best first: highest bid, lowest ask.
+    pub fn levels(&self, side: Side) -> Box<dyn Iterator<Item = &Level> + '_> {
+        match side {
+            Side::Bid => Box::new(self.bids.values().rev()),
+            Side::Ask => Box::new(self.asks.values()),
+        }
+    }
+
+    /// Best level of `side`.
+    pub fn best(&self, side: Side) -> Option<&Level> { self.levels(side).next() }
+
+    /// The `n` best levels of `side`.
+    pub fn top(&self, side: Side, n: usize) -> Vec<&Level> { self.levels(side).take(n).collect() }
+
+    /// Level of `side` at exactly `price`.
+    pub fn level(&self, side: Side, price: f64) -> Option<&Level> { self.side(side).get(&PriceKey(price)) }
+
+    /// Number of levels of `side`.
+    pub fn len(&self, side: Side) -> usize { self.side(side).len() }
+
+    pub fn is_empty(&self) -> bool { self.bids.is_empty() && self.asks.is_empty() }
+
+    /// Levels of `side` priced within `distance` of its best price, best
+    /// included (e.g. bids down to `best - distance`).
+    pub fn depth(&self, side: Side, distance: f64) -> Depth {
+        let Some(best) = self.best(side).map(|l| l.price) else { return Depth::default() };
+        self.levels(side)
+            .take_while(|l| (l.price - best).abs() <= distance)
+            .fold(Depth::default(), |d, l| Depth { levels: d.levels + 1, orders: d.orders + l.orders, qty: d.qty + l.qty })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::{BookAction, BookBuilder, EntryEdit};
+
+    fn e(price: f64, qty: i64, agent: i32) -> Entry { Entry { price, qty, agent, offer_id: 0, date: None } }
+
+    #[test]
+    fn incremental_levels_match_rebuild() {
+        let mut b = BookBuilder::from_book(Book {
+            buys: vec![e(10.0, 5, 1), e(10.0, 3, 2), e(9.5, 1, 1)],
+            sells: vec![e(10.5, 2, 3), e(11.0, 4, 3)],
+        })
+        .with_levels();
+        let actions = [
+            BookAction::Add { side: Side::Bid, position: 0, entry: e(9.5, 7, 4) },
+            BookAction::Edit { side: Side::Bid, position: 3, edit: EntryEdit { qty: Some(1), agent: Some(2), ..Default::default() } },
+            BookAction::Edit { side: Side::Ask, position: 1, edit: EntryEdit { price: Some(10.75), ..Default::default() } },
+            BookAction::Delete { side: Side::Bid, position: 2 },
+            BookAction::Add { side: Side::Ask, position: 0, entry: e(11.0, 1, 5) },
+            BookAction::DeleteFrom { side: Side::Ask, position: 0 },
+        ];
+        for a in actions {
+            b.apply_action(a).unwrap();
+            assert_eq!(b.levels(), Some(&L2Book::from_book(b.book())));
+        }
+        let l2 = b.levels().unwrap();
+        let bid = l2.best(Side::Bid).unwrap();
+        assert_eq!((bid.price, bid.qty, bid.orders, bid.agents().collect::<Vec<_>>()), (10.0, 1, 1, vec![2]));
+        assert_eq!(l2.top(Side::Ask, 5).iter().map(|l| l.price).collect::<Vec<_>>(), [10.75, 11.0]);
+        assert_eq!(l2.depth(Side::Bid, 0.5), Depth { levels: 2, orders: 3, qty: 9 });
+        assert_eq!(l2.depth(Side::Ask, 0.1), Depth { levels: 1, orders: 1, qty: 2 });
+        assert_eq!(l2.level(Side::Bid, 9.5).map(|l| l.agent_count()), Some(2));
+    }
+}
@@ -3,13 +3,44 @@
 //! This crate provides the core types and logic used by the `market-data`
 //! recorder binary and the `player` tool:
 //!
-//! - `record`: durable on-disk schema (frames, events, raw array blocks)
+//! - `record`: durable on-disk schema (frames, events, raw array blocks) plus
+//!   [`record::CaptureWriter`] and [`record::CaptureReader`], the shared
+//!   implementation of the framing used by every tool
+//! - `index`: seek index sidecar mapping event seq/time to frame offsets
+//! - `rotate`: splitting a recording into parts and reading the parts back
+//!   as one stream
+//! - `merge`: interleaving several captures into one by receive time
+//! - `slice`: cutting a time or seq window out of a capture
+//! - `normalize`: deriving a capture of pre-decoded events from a raw one
+//! - `rows`: flat trade, book event and snapshot rows, written as CSV or
+//!   JSON Lines
+//! - `export` (feature `arrow`): trades, book events and book snapshots as
+//!   Arrow record batches, and Parquet files with feature `parquet`
 //! - `book`: Level-3 order book model and parsers to reconstruct state from
 //!   ProfitDLL Offer Book V2 raw array blocks, including multi-packet full
 //!   book handling and nPosition-from-end semantics
+//! - `l2`: price-level view of a book, maintained incrementally from L3
+//!   actions
+//! - `orders`: offer lookup by id and per-order lifecycle histories
+//! - `queue`: queue position of a hypothetical resting order, drained by
+//!   later book changes and trades
+//! - `replay`: applies capture frames to a `Book`, including checkpoint
+//!   restore and verification
 //!
-//! The binaries in this repository (`src/main.rs` and `src/bin/player.rs`)
-//! use these modules to write and read capture files with strong framing
+//! The binaries in this repository (`src/main.rs`, `src/bin/player.rs` and
+//! `src/bin/capture.rs`) use these modules to write and read capture files with strong framing
 //! and CRC integrity checks.
 pub mod record;
 pub mod book;
+pub mod l2;
+pub mod orders;
+pub mod queue;
+pub mod index;
+pub mod replay;
+pub mod rotate;
+pub mod merge;
+pub mod slice;
+pub mod normalize;
+pub mod rows;
+#[cfg(feature = "arrow")]
+pub mod export;
@@ -6,24 +6,39 @@
 //! - Copy raw array blocks immediately inside callbacks and forward them to a
 //!   background writer thread via a bounded channel.
 //! - Persist framed records with length + CRC32 and a bincode payload.
+//! - Record how the capture was produced (recorder version, DLL hash, host,
+//!   options without credentials, clocks) in the header's provenance.
 //! - Compute a best-effort server clock offset and choose a default output
-//!   file name `captures/TICKER_YYYY_MM_DD.bin`.
+//!   file name `captures/TICKER_YYYY_MM_DD.bin` (`MULTI_...` when several
+//!   tickers are recorded into one file).
+//! - Tag every event with an instrument id from the header's instrument
+//!   table; assets first seen in a callback get a new id and an instrument
+//!   frame.
+//! - Optionally rotate the output into numbered parts by size, time or
+//!   session boundary (see [`market_data::rotate`]).
+//! - With `--resume`, continue an existing output file after a restart
+//!   instead of overwriting it: its tail is checked, a partial last frame is
+//!   cut, a resume marker is written and seq numbering carries on.
 //! - Graceful shutdown on Ctrl+C: unsubscribe, stop enqueuing, drain/flush,
-//!   join writer, and finalize the DLL.
+//!   write the trailer frame, join writer, and finalize the DLL.
 mod ffi;
 mod profitdll;
-mod record;
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use dotenvy::dotenv;
-use crc32fast::Hasher as Crc32;
 use crossbeam_channel::{bounded, Sender};
 use once_cell::sync::OnceCell;
-use record::{EventKind, EventRecord, FileHeader, RawArrayBlock, RecordFrame};
-use std::fs::{self, File, OpenOptions};
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use sha2::Digest;
+use market_data::index::DEFAULT_INDEX_EVERY;
+use market_data::record::{
+    Codec, Encoding, EventKind, EventRecord, FileHeader, GapScope, HeaderExtension, Instrument, Provenance, RawArrayBlock, RecordFrame, ResumeMarker,
+    ResumeReport, ServerClock, FORMAT_VERSION, NO_INSTRUMENT,
+};
+use market_data::replay::Checkpointer;
+use market_data::rotate::{RotatingWriter, RotationPolicy};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
@@ -32,7 +47,10 @@ use widestring::U16CStr;
 use crate::ffi::*;
 use crate::profitdll::{copy_array_block, to_pwstr, ProfitDll};
 
-#[derive(Debug, Parser)]
+/// Capacity of the callback-to-writer event queue, in frames.
+const CHANNEL_CAPACITY: usize = 8192;
+
+#[derive(Debug, Clone, Parser)]
 #[command(version, about = "L3 OfferBook + Trades recorder (ProfitDLL)")]
 struct Args {
     /// Path to ProfitDLL.dll
@@ -51,17 +69,106 @@ struct Args {
     #[arg(long, env = "PASSWORD")]
     password: String,
 
-    /// Ticker symbol (e.g., WINFUT)
-    #[arg(long, env = "TICKER")]
-    ticker: String,
+    /// Ticker symbols to record into one file (comma-separated, e.g. WINFUT,WDOFUT)
+    #[arg(long, env = "TICKER", value_delimiter = ',', required = true)]
+    ticker: Vec<String>,
 
-    /// Exchange code (e.g., F for BMF, B for Bovespa)
-    #[arg(long, env = "EXCHANGE")]
-    exchange: String,
+    /// Exchange code (e.g., F for BMF, B for Bovespa): one for all tickers, or one per ticker
+    #[arg(long, env = "EXCHANGE", value_delimiter = ',', required = true)]
+    exchange: Vec<String>,
 
     /// Output file path (.bin); defaults to captures/TICKER_YYYY_MM_DD.bin
     #[arg(long, env = "OUT_FILE")]
     out: Option<PathBuf>,
+
+    /// Frame compression codec (none, zstd, lz4)
+    #[arg(long, env = "COMPRESSION", default_value_t = Codec::None)]
+    compression: Codec,
+
+    /// Event encoding (bincode, or fixed for in-place reads at replay)
+    #[arg(long, env = "ENCODING", default_value_t = Encoding::Bincode)]
+    encoding: Encoding,
+
+    /// Chain a SHA-256 digest through every frame so later edits can be detected (`capture verify`)
+    #[arg(long, env = "HASH_CHAIN", default_value_t = false)]
+    hash_chain: bool,
+
+    /// Write a seek index sidecar (`<out>.idx`) with an entry every N frames; 0 disables
+    #[arg(long, env = "INDEX_EVERY", default_value_t = DEFAULT_INDEX_EVERY)]
+    index_every: u32,
+
+    /// Write a reconstructed-book checkpoint frame every N seconds of capture; 0 disables
+    #[arg(long, env = "CHECKPOINT_SECS", default_value_t = 0)]
+    checkpoint_secs: u64,
+
+    /// Start a new output part once the current one reaches N bytes; 0 disables
+    #[arg(long, env = "ROTATE_BYTES", default_value_t = 0)]
+    rotate_bytes: u64,
+
+    /// Start a new output part every N seconds; 0 disables
+    #[arg(long, env = "ROTATE_SECS", default_value_t = 0)]
+    rotate_secs: u64,
+
+    /// Start a new output part at these local times of day (comma-separated HH:MM, e.g. 09:00,18:30)
+    #[arg(long, env = "ROTATE_AT", value_delimiter = ',', value_parser = parse_time_of_day)]
+    rotate_at: Vec<time::Time>,
+
+    /// Append to an existing output file (or the last part of its set) instead of overwriting it
+    #[arg(long, env = "RESUME", default_value_t = false)]
+    resume: bool,
+}
+
+fn parse_time_of_day(s: &str) -> Result<time::Time, String> {
+    let (h, m) = s.trim().split_once(':').ok_or_else(|| format!("expected HH:MM, got {s:?}"))?;
+    let (h, m) = (h.parse::<u8>().map_err(|e| e.to_string())?, m.parse::<u8>().map_err(|e| e.to_string())?);
+    time::Time::from_hms(h, m, 0).map_err(|e| e.to_string())
+}
+
+/// Host name from the environment (`COMPUTERNAME` on Windows), or empty.
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname").map(|s| s.trim().to_string()))
+        .unwrap_or_default()
+}
+
+/// Provenance header section for this run. Credentials are blanked out of
+/// the recorded options.
+fn provenance(args: &Args, server_clock: ServerClock) -> Provenance {
+    let mut shown = args.clone();
+    for secret in [&mut shown.activation, &mut shown.user, &mut shown.password] {
+        *secret = "<redacted>".to_string();
+    }
+    let dll_sha256 = match std::fs::read(&args.dll) {
+        Ok(bytes) => Some(sha2::Sha256::digest(&bytes).into()),
+        Err(e) => {
+            eprintln!("Cannot hash {:?} for the header: {e}", args.dll);
+            None
+        }
+    };
+    Provenance {
+        recorder_version: env!("CARGO_PKG_VERSION").to_string(),
+        dll_path: args.dll.clone(),
+        dll_sha256,
+        hostname: hostname(),
+        options: format!("{shown:?}"),
+        utc_offset_secs: time::UtcOffset::current_local_offset().ok().map(|o| o.whole_seconds()),
+        server_clock,
+        channel_capacity: CHANNEL_CAPACITY as u32,
+    }
+}
+
+/// Output options for [`writer_thread`].
+struct WriterConfig {
+    /// Seek index entry every N frames; 0 disables the sidecar.
+    index_every: u32,
+    /// Checkpoint interval in seconds; 0 disables checkpoints.
+    checkpoint_secs: u64,
+    rotation: RotationPolicy,
+    /// Events the callbacks discarded because shutdown had begun or the
+    /// writer had stopped, reported in each trailer. A full queue blocks the
+    /// callbacks instead, so nothing is discarded for lack of space.
+    discarded: &'static AtomicU64,
 }
 
 fn now_unix_ns() -> u128 {
@@ -71,66 +178,120 @@ fn now_unix_ns() -> u128 {
         .as_nanos()
 }
 
-fn write_frame(w: &mut BufWriter<File>, frame: &RecordFrame) -> Result<()> {
-    let payload = bincode::serialize(frame)?;
-    let mut hasher = Crc32::new();
-    hasher.update(&payload);
-    let crc = hasher.finalize();
-
-    let len = payload.len() as u32;
-    w.write_all(&len.to_le_bytes())?;
-    w.write_all(&crc.to_le_bytes())?;
-    w.write_all(&payload)?;
-    Ok(())
-}
-
 /// Background writer that serializes frames and writes them with len+CRC32 framing.
-/// Reacts to a shutdown signal by draining the queue, flushing, then exiting.
+/// The output is opened by [`open_output`] before the thread starts; the
+/// channel only carries event frames. Reacts to a shutdown signal by draining the queue,
+/// writing the trailer, flushing, writing the seek index sidecar (if
+/// enabled), then exiting. On a write error it exits without a trailer, so
+/// the file reads as unclean.
+///
+/// With `checkpoint_secs > 0` the thread also replays each instrument's book
+/// and appends a checkpoint frame after the first consistent event of each
+/// interval, and at the start of every rotated part so each part replays on
+/// its own.
 fn writer_thread(
-    out: PathBuf,
+    mut w: RotatingWriter,
+    cfg: WriterConfig,
     rx: crossbeam_channel::Receiver<RecordFrame>,
     sd_rx: crossbeam_channel::Receiver<()>,
 ) -> Result<()> {
-    if let Some(parent) = out.parent() {
-        if !parent.as_os_str().is_empty() {
-            fs::create_dir_all(parent).ok();
+    let mut checkpoints = (cfg.checkpoint_secs > 0).then(|| Checkpointer::new(Duration::from_secs(cfg.checkpoint_secs)));
+    let mut write = |w: &mut RotatingWriter, frame: RecordFrame| -> Result<()> {
+        w.set_dropped_events(cfg.discarded.load(Ordering::Relaxed));
+        if let RecordFrame::Event(ev) = &frame && w.rotate_if_due(ev.recv_unix_ns)? {
+            eprintln!("Rotated output to {:?}", w.path());
+            for cp in checkpoints.as_ref().map(Checkpointer::checkpoints).unwrap_or_default() {
+                w.write_frame(&RecordFrame::Checkpoint(cp))?;
+            }
         }
-    }
-    let file = OpenOptions::new().create(true).write(true).truncate(true).open(&out)?;
-    let mut w = BufWriter::with_capacity(1 << 20, file); // 1 MiB buffer
+        w.write_frame(&frame)?;
+        if let (Some(cps), RecordFrame::Event(ev)) = (&mut checkpoints, &frame) {
+            match cps.apply_event(ev) {
+                Ok(Some(cp)) => w.write_frame(&RecordFrame::Checkpoint(cp))?,
+                Ok(None) => {}
+                Err(e) => {
+                    // Never lose capture data over a replay problem; just stop checkpointing
+                    eprintln!("checkpoints disabled at seq={}: {e:#}", ev.seq);
+                    checkpoints = None;
+                }
+            }
+        }
+        Ok(())
+    };
     loop {
         crossbeam_channel::select! {
             recv(rx) -> msg => match msg {
                 Ok(frame) => {
-                    write_frame(&mut w, &frame)?;
+                    write(&mut w, frame)?;
                 }
                 Err(_) => {
                     // Sender(s) dropped; flush and exit
-                    w.flush()?;
                     break;
                 }
             },
             recv(sd_rx) -> _ => {
-                // Shutdown requested: drain remaining frames, flush, and exit
+                // Shutdown requested: drain remaining frames, then flush and exit
                 while let Ok(frame) = rx.try_recv() {
-                    write_frame(&mut w, &frame)?;
+                    write(&mut w, frame)?;
                 }
-                w.flush()?;
                 break;
             }
         }
     }
+    w.set_dropped_events(cfg.discarded.load(Ordering::Relaxed));
+    let last = w.path().to_path_buf();
+    w.finish().with_context(|| format!("finish {:?}", last))?;
     Ok(())
 }
 
+/// Create the output, or with `resume` continue an existing one: the file's
+/// codec, encoding, hash chain and instrument table are kept, a resume
+/// marker carrying the new header's extensions is written, and the returned
+/// report gives the seq to continue from.
+fn open_output(out: &Path, header: FileHeader, cfg: &WriterConfig, resume: bool) -> Result<(RotatingWriter, Option<ResumeReport>)> {
+    if !(resume && out.exists()) {
+        if resume { eprintln!("Nothing to resume at {:?}; starting a new capture.", out); }
+        let w = RotatingWriter::create(out, header, cfg.rotation.clone(), cfg.index_every).with_context(|| format!("create {:?}", out))?;
+        return Ok((w, None));
+    }
+    let (mut w, report) = RotatingWriter::resume(out, cfg.rotation.clone(), cfg.index_every)
+        .with_context(|| format!("resume {:?} (move it away, or run `capture repair` on it)", out))?;
+    eprintln!(
+        "Resuming {:?} after seq={:?}: {} frames kept, {} partial bytes cut, {}.",
+        w.path(), report.last_seq, report.frames, report.truncated_bytes,
+        if report.trailer.is_some() { "trailer removed" } else { "no trailer (unclean shutdown)" }
+    );
+    let existing = w.header();
+    if (existing.codec(), existing.encoding(), existing.hash_chain()) != (header.codec(), header.encoding(), header.hash_chain()) {
+        eprintln!("Keeping the file's settings: codec={} encoding={} hash_chain={}.", existing.codec(), existing.encoding(), existing.hash_chain());
+    }
+    w.write_frame(&RecordFrame::Resumed(ResumeMarker {
+        resumed_unix_ns: header.created_unix_ns,
+        last_seq: report.last_seq,
+        truncated_bytes: report.truncated_bytes,
+        server_clock_offset_ms: header.server_clock_offset_ms,
+        scope: GapScope::All,
+        extensions: header.extensions,
+    }))?;
+    Ok((w, Some(report)))
+}
+
 fn main() -> Result<()> {
     // Load environment variables from .env if present
     let _ = dotenv();
     let args = Args::parse();
+    if args.exchange.len() != 1 && args.exchange.len() != args.ticker.len() {
+        anyhow::bail!("--exchange takes one code for all tickers or one per ticker ({} tickers, {} exchanges)", args.ticker.len(), args.exchange.len());
+    }
+    let instruments: Vec<Instrument> = args.ticker.iter().enumerate().map(|(i, t)| Instrument {
+        id: i as u32,
+        ticker: t.clone(),
+        exchange: args.exchange.get(i).unwrap_or(&args.exchange[0]).clone(),
+        feed: 0,
+    }).collect();
     let dll = ProfitDll::load(&args.dll).with_context(|| "Load ProfitDLL.dll")?;
 
-    let (tx, rx) = bounded::<RecordFrame>(8192);
-    let tx_writer = tx.clone();
+    let (tx, rx) = bounded::<RecordFrame>(CHANNEL_CAPACITY);
     // Shutdown signal channel for writer
     let (sd_tx, sd_rx) = bounded::<()>(1);
 
@@ -141,12 +302,13 @@ fn main() -> Result<()> {
     // get server clock offset (best-effort using local timezone)
     // Also capture server Y-M-D if provided for filename default
     let mut server_date: Option<(i32, u8, u8)> = None;
-    let server_offset_ms = unsafe {
+    let (server_offset_ms, server_clock) = unsafe {
         use time::{Date, Month, Time as TmTime, UtcOffset, PrimitiveDateTime};
         let mut out = 0i64;
         let mut dt = 0f64; // epoch seconds (if provided)
         let (mut y, mut mo, mut d, mut h, mut mi, mut s, mut ms) = (0, 0, 0, 0, 0, 0, 0);
         let r = (dll.get_server_clock)(&mut dt, &mut y, &mut mo, &mut d, &mut h, &mut mi, &mut s, &mut ms);
+        let clock = ServerClock { result: r, date: dt, year: y, month: mo, day: d, hour: h, minute: mi, second: s, millisecond: ms };
         if r == NL_OK && y > 0 && (1..=12).contains(&mo) && d > 0 {
             let month = Month::try_from(mo as u8).unwrap_or(Month::January);
             if let (Ok(date), Ok(time)) = (
@@ -163,7 +325,7 @@ fn main() -> Result<()> {
                 }
             }
         }
-        out
+        (out, clock)
     };
 
     // Resolve output path
@@ -183,15 +345,51 @@ fn main() -> Result<()> {
             let d = odt.date();
             (d.year(), d.month() as u8, d.day())
         };
-        let fname = format!("{}_{}_{:02}_{:02}.bin", args.ticker.to_uppercase(), yy, mm, dd);
+        let name = if instruments.len() == 1 { instruments[0].ticker.to_uppercase() } else { "MULTI".to_string() };
+        let fname = format!("{}_{}_{:02}_{:02}.bin", name, yy, mm, dd);
         let mut p = PathBuf::from("captures");
         p.push(fname);
         p
     };
 
+    let mut header = FileHeader {
+        version: FORMAT_VERSION,
+        created_unix_ns,
+        ticker: instruments[0].ticker.clone(),
+        exchange: instruments[0].exchange.clone(),
+        server_clock_offset_ms: server_offset_ms,
+        extensions: Vec::new(),
+    };
+    header.set_codec(args.compression);
+    header.set_encoding(args.encoding);
+    header.set_hash_chain(args.hash_chain);
+    header.set_instruments(instruments.clone());
+    header.extensions.push(HeaderExtension::Provenance(provenance(&args, server_clock)));
+    let discarded_static: &'static AtomicU64 = Box::leak(Box::new(AtomicU64::new(0)));
+    let cfg = WriterConfig {
+        index_every: args.index_every,
+        checkpoint_secs: args.checkpoint_secs,
+        rotation: RotationPolicy {
+            max_bytes: (args.rotate_bytes > 0).then_some(args.rotate_bytes),
+            interval: (args.rotate_secs > 0).then(|| Duration::from_secs(args.rotate_secs)),
+            session_times: args.rotate_at.clone(),
+            utc_offset: time::UtcOffset::current_local_offset().ok(),
+        },
+        discarded: discarded_static,
+    };
+
+    let (writer, resumed) = open_output(&out_path, header, &cfg, args.resume)?;
+    let (first_seq, table) = match &resumed {
+        Some(r) => {
+            discarded_static.store(r.trailer.as_ref().map_or(0, |t| t.dropped_events), Ordering::Relaxed);
+            (r.last_seq.map_or(0, |s| s + 1), r.instruments.clone())
+        }
+        None => (0, instruments.clone()),
+    };
+
     // spawn writer thread that drains frames and flushes on shutdown
     let writer_jh = std::thread::spawn(move || {
-        if let Err(e) = writer_thread(out_path, rx, sd_rx) {
+        if let Err(e) = writer_thread(writer, cfg, rx, sd_rx) {
             eprintln!("writer thread error: {e:#}");
         }
     });
@@ -200,54 +398,92 @@ fn main() -> Result<()> {
     let writer_mutex: &'static Mutex<Option<std::thread::JoinHandle<()>>> = Box::leak(Box::new(Mutex::new(Some(writer_jh))));
     WRITER_JH_CELL.set(writer_mutex).ok();
 
-    let header = RecordFrame::Header(FileHeader {
-        version: 1,
-        created_unix_ns,
-        ticker: args.ticker.clone(),
-        exchange: args.exchange.clone(),
-        server_clock_offset_ms: server_offset_ms,
-    });
-    tx_writer.send(header).ok();
-
     // Static state used by callbacks
     static TX_CELL: OnceCell<&'static Sender<RecordFrame>> = OnceCell::new();
     static SEQ_CELL: OnceCell<&'static AtomicU64> = OnceCell::new();
     static START_CELL: OnceCell<Instant> = OnceCell::new();
     static FREE_TX_CELL: OnceCell<&'static Sender<(usize, i32)>> = OnceCell::new();
     static SHUTDOWN_CELL: OnceCell<&'static AtomicBool> = OnceCell::new();
+    static DISCARDED_CELL: OnceCell<&'static AtomicU64> = OnceCell::new();
+    static INSTRUMENTS_CELL: OnceCell<Mutex<Vec<Instrument>>> = OnceCell::new();
 
     // Leak small singletons to get 'static references safely
     let tx_static: &'static Sender<RecordFrame> = Box::leak(Box::new(tx.clone()));
-    let seq_static: &'static AtomicU64 = Box::leak(Box::new(AtomicU64::new(0)));
+    let seq_static: &'static AtomicU64 = Box::leak(Box::new(AtomicU64::new(first_seq)));
     TX_CELL.set(tx_static).ok();
     SEQ_CELL.set(seq_static).ok();
     START_CELL.set(start_instant).ok();
     let shut_static: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
     SHUTDOWN_CELL.set(shut_static).ok();
+    DISCARDED_CELL.set(discarded_static).ok();
+    INSTRUMENTS_CELL.set(Mutex::new(table)).ok();
+
+    /// Wide ticker, wide exchange and instrument id of a resolved asset.
+    type KnownAsset = (Vec<u16>, Vec<u16>, u32);
+    thread_local! {
+        /// Assets this callback thread has already resolved.
+        static KNOWN_ASSETS: RefCell<Vec<KnownAsset>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Instrument id of a callback's asset. An asset missing from the table
+    /// gets the next id, announced to the writer with an instrument frame
+    /// ahead of its first event. Known assets are found in a per-thread
+    /// cache without allocating or locking the table.
+    fn instrument_id(asset: TAssetIDRec) -> u32 {
+        // Copy out of the packed struct before use
+        let (pwc_ticker, pwc_bolsa, feed) = (asset.pwcTicker, asset.pwcBolsa, asset.nFeed);
+        let wide = |p: PWideChar| if p.is_null() { &[][..] } else { unsafe { U16CStr::from_ptr_str(p).as_slice() } };
+        let (ticker, exchange) = (wide(pwc_ticker), wide(pwc_bolsa));
+        let lower = |c: &u16| u8::try_from(*c).map_or(*c, |b| b.to_ascii_lowercase() as u16);
+        let same = |a: &[u16], b: &[u16]| a.len() == b.len() && a.iter().map(lower).eq(b.iter().map(lower));
+        let cached = KNOWN_ASSETS.with_borrow(|known| known.iter().find(|(t, e, _)| same(t, ticker) && same(e, exchange)).map(|k| k.2));
+        if let Some(id) = cached { return id; }
+        let id = {
+            let (ticker, exchange) = (String::from_utf16_lossy(ticker), String::from_utf16_lossy(exchange));
+            let Some(Ok(mut table)) = INSTRUMENTS_CELL.get().map(Mutex::lock) else { return NO_INSTRUMENT };
+            match table.iter().find(|i| i.ticker.eq_ignore_ascii_case(&ticker) && i.exchange.eq_ignore_ascii_case(&exchange)) {
+                Some(i) => i.id,
+                None => {
+                    let inst = Instrument { id: table.len() as u32, ticker, exchange, feed };
+                    // Sent under the lock so no event with this id can be queued before it
+                    if let Some(tx) = TX_CELL.get() { let _ = tx.send(RecordFrame::Instrument(inst.clone())); }
+                    table.push(inst);
+                    table.len() as u32 - 1
+                }
+            }
+        };
+        KNOWN_ASSETS.with_borrow_mut(|known| known.push((ticker.to_vec(), exchange.to_vec(), id)));
+        id
+    }
 
     /// Package and send an [`EventRecord`] to the writer (no DLL calls here).
-    fn push_event(kind: EventKind) {
+    /// Blocks while the queue is full; events arriving after shutdown began
+    /// or after the writer stopped are discarded and counted for the trailer.
+    fn push_event(instrument: u32, kind: EventKind) {
+        let count_discarded = || if let Some(d) = DISCARDED_CELL.get() { d.fetch_add(1, Ordering::Relaxed); };
         if let (Some(tx), Some(seq), Some(start)) = (TX_CELL.get(), SEQ_CELL.get(), START_CELL.get()) {
             if let Some(sh) = SHUTDOWN_CELL.get() {
-                if sh.load(Ordering::Relaxed) { return; }
+                if sh.load(Ordering::Relaxed) { count_discarded(); return; }
             }
             let n = seq.fetch_add(1, Ordering::Relaxed);
             let ev = EventRecord {
                 seq: n,
+                instrument,
                 recv_unix_ns: now_unix_ns(),
                 recv_mono_ns_from_start: start.elapsed().as_nanos(),
                 kind,
             };
-            let _ = tx.send(RecordFrame::Event(ev));
+            if tx.send(RecordFrame::Event(ev)).is_err() { count_discarded(); }
         }
     }
 
     unsafe extern "system" fn cb_state(n_type: i32, value: i32) {
-    push_event(EventKind::State { state_type: n_type, value });
+    // Connection state is not tied to an asset
+    push_event(NO_INSTRUMENT, EventKind::State { state_type: n_type, value });
     }
 
     unsafe extern "system" fn cb_trade(
-        _asset: TAssetIDRec,
+        asset: TAssetIDRec,
         pwc_date: PWideChar,
         trade_number: u32,
         price: f64,
@@ -263,7 +499,7 @@ fn main() -> Result<()> {
         } else {
             String::new()
         };
-    push_event(EventKind::NewTrade {
+    push_event(instrument_id(asset), EventKind::NewTrade {
             date_str: date,
             trade_number,
             price,
@@ -277,7 +513,7 @@ fn main() -> Result<()> {
     }
 
     unsafe extern "system" fn cb_hist_trade(
-        _asset: TAssetIDRec,
+        asset: TAssetIDRec,
         pwc_date: PWideChar,
         trade_number: u32,
         price: f64,
@@ -292,7 +528,7 @@ fn main() -> Result<()> {
         } else {
             String::new()
         };
-    push_event(EventKind::HistoryTrade {
+    push_event(instrument_id(asset), EventKind::HistoryTrade {
             date_str: date,
             trade_number,
             price,
@@ -306,7 +542,7 @@ fn main() -> Result<()> {
 
     /// Offer Book V2 callback. Copies raw array blocks and enqueues an event.
     unsafe extern "system" fn cb_offerbook_v2(
-        _asset: TAssetIDRec,
+        asset: TAssetIDRec,
         n_action: i32,
         n_position: i32,
         n_side: i32,
@@ -353,7 +589,7 @@ fn main() -> Result<()> {
             }
         }
 
-    push_event(EventKind::OfferBookV2 {
+    push_event(instrument_id(asset), EventKind::OfferBookV2 {
             n_action,
             n_position,
             n_side,
@@ -413,12 +649,13 @@ fn main() -> Result<()> {
         if ret != NL_OK { eprintln!("DLLInitializeMarketLogin returned {}", ret); }
     }
 
-    // Subscribe to ticker and Level-3 Offer Book stream
-    let ticker = to_pwstr(&args.ticker);
-    let exch = to_pwstr(&args.exchange);
-    unsafe {
-        (dll.subscribe_ticker)(ticker.as_ptr(), exch.as_ptr());
-        (dll.subscribe_offer_book)(ticker.as_ptr(), exch.as_ptr());
+    // Subscribe to each ticker and its Level-3 Offer Book stream
+    let subscriptions: Vec<_> = instruments.iter().map(|i| (to_pwstr(&i.ticker), to_pwstr(&i.exchange))).collect();
+    for (ticker, exch) in &subscriptions {
+        unsafe {
+            (dll.subscribe_ticker)(ticker.as_ptr(), exch.as_ptr());
+            (dll.subscribe_offer_book)(ticker.as_ptr(), exch.as_ptr());
+        }
     }
 
     // Run until Ctrl+C; graceful shutdown unsubscribes and drains writer
@@ -427,16 +664,16 @@ fn main() -> Result<()> {
     let unsub_ob = dll.unsubscribe_offer_book;
     let unsub_tk = dll.unsubscribe_ticker;
     let finalize = dll.dll_finalize;
-    let tkr_for_shutdown = to_pwstr(&args.ticker);
-    let exc_for_shutdown = to_pwstr(&args.exchange);
     // shutdown flag reference
     let sh_flag = SHUTDOWN_CELL.get().cloned();
     let sd_tx2 = sd_tx.clone();
     ctrlc::set_handler(move || {
         if let Some(f) = &sh_flag { f.store(true, Ordering::Relaxed); }
-        unsafe {
-            (unsub_ob)(tkr_for_shutdown.as_ptr(), exc_for_shutdown.as_ptr());
-            (unsub_tk)(tkr_for_shutdown.as_ptr(), exc_for_shutdown.as_ptr());
+        for (ticker, exch) in &subscriptions {
+            unsafe {
+                (unsub_ob)(ticker.as_ptr(), exch.as_ptr());
+                (unsub_tk)(ticker.as_ptr(), exch.as_ptr());
+            }
         }
         // Allow in-flight events to enqueue
         std::thread::sleep(Duration::from_millis(100));
@@ -0,0 +1,286 @@
+//! Merging several captures into one stream ordered by receive time.
+//!
+//! [`merge`] interleaves the events of N captures by `recv_unix_ns`, always
+//! taking the earliest head event (ties go to the earlier source). Each
+//! source is consumed in file order, so its own events, checkpoints and gaps
+//! keep their relative order even if its clock stepped backwards, and every
+//! instrument's book replays exactly as it did from the source file.
+//!
+//! Events keep their original `seq`, so seqs in a merged file are only
+//! ordered within one source. Instruments are renumbered per source with
+//! [`merged_instrument`]: two files that both recorded WINFUT as id 0 yield
+//! two instruments, and [`source_of`] tells which file an event came from.
+//! Events of [`NO_INSTRUMENT`] keep that id, whatever their source.
+//! The sources themselves are listed in the header's
+//! [`HeaderExtension::Sources`] extension. Gap and resume markers of a
+//! source are scoped to that source's instruments (see [`GapScope`]), so
+//! events lost in one file do not make the books of the others stale.
+//!
+//! Only raw captures can be merged; normalized ones are rejected.
+use crate::record::{
+    CaptureError, CaptureReader, CaptureWriter, EventRecord, FileHeader, GapScope, HeaderExtension, RecordFrame, SourceFile, FORMAT_VERSION,
+    NO_INSTRUMENT,
+};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+use std::io::{Read, Write};
+
+/// Instrument ids of a source must stay below this to be renumbered.
+const MAX_SOURCE_INSTRUMENTS: u32 = 1 << 16;
+
+/// Id in a merged file of instrument `instrument` of source `source`.
+pub fn merged_instrument(source: u32, instrument: u32) -> u32 { (source << 16) | instrument }
+
+/// Source index and original instrument id of a merged instrument id.
+pub fn source_of(instrument: u32) -> (u32, u32) { (instrument >> 16, instrument & (MAX_SOURCE_INSTRUMENTS - 1)) }
+
+/// Summary of a [`merge`] run.
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    /// Events copied from each source, in source order.
+    pub events: Vec<u64>,
+    /// Indices of sources that ended without a trailer.
+    pub unclean: Vec<usize>,
+}
+
+/// One input being merged.
+struct Source<R> {
+    index: u32,
+    reader: CaptureReader<R>,
+    /// Next event of this source, not yet written.
+    head: Option<EventRecord>,
+    /// Merged ids of the instruments seen so far.
+    instruments: BTreeSet<u32>,
+}
+
+impl<R: Read> Source<R> {
+    /// Copy frames up to the next event into `w` and keep that event as the
+    /// head. Trailers are summed into `dropped` instead of being copied.
+    fn advance<W: Write>(&mut self, w: &mut CaptureWriter<W>, dropped: &mut u64) -> Result<(), CaptureError> {
+        self.head = None;
+        while let Some(frame) = self.reader.read_frame()? {
+            match frame {
+                RecordFrame::Event(mut ev) => {
+                    if ev.instrument != NO_INSTRUMENT {
+                        ev.instrument = self.renumber(ev.instrument)?;
+                        self.instruments.insert(ev.instrument);
+                    }
+                    self.head = Some(ev);
+                    return Ok(());
+                }
+                RecordFrame::Checkpoint(mut cp) => {
+                    cp.instrument = self.renumber(cp.instrument)?;
+                    self.instruments.insert(cp.instrument);
+                    w.write_frame(&RecordFrame::Checkpoint(cp))?;
+                }
+                RecordFrame::Instrument(mut i) => {
+                    i.id = self.renumber(i.id)?;
+                    self.instruments.insert(i.id);
+                    w.write_frame(&RecordFrame::Instrument(i))?;
+                }
+                RecordFrame::Gap(mut g) => {
+                    g.scope = self.rescope(&g.scope)?;
+                    w.write_frame(&RecordFrame::Gap(g))?;
+                }
+                RecordFrame::Resumed(mut m) => {
+                    m.scope = self.rescope(&m.scope)?;
+                    w.write_frame(&RecordFrame::Resumed(m))?;
+                }
+                RecordFrame::Trailer(t) => *dropped += t.dropped_events,
+                frame => w.write_frame(&frame)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn renumber(&self, instrument: u32) -> Result<u32, CaptureError> {
+        if instrument >= MAX_SOURCE_INSTRUMENTS {
+            return Err(CaptureError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("source {} uses instrument id {instrument}; merge supports ids below {MAX_SOURCE_INSTRUMENTS}", self.index),
+            )));
+        }
+        Ok(merged_instrument(self.index, instrument))
+    }
+
+    /// Scope in the merged file of a marker of this source. A marker for the
+    /// whole source covers the instruments seen so far; those defined later
+    /// have no book to lose yet.
+    fn rescope(&self, scope: &GapScope) -> Result<GapScope, CaptureError> {
+        Ok(GapScope::Instruments(match scope {
+            GapScope::All => self.instruments.iter().copied().collect(),
+            GapScope::Instruments(ids) => ids.iter().map(|&i| self.renumber(i)).collect::<Result<_, _>>()?,
+        }))
+    }
+}
+
+/// Merge `sources` (display name and reader of each capture) into one
+/// capture written to `out`, ordered by receive time. The output header
+/// takes its codec, encoding, hash chain, ticker and clock offset from the
+/// first source; the trailer reports the dropped events of all sources.
+/// Returns the flushed output writer and a report.
+pub fn merge<R: Read, W: Write>(sources: Vec<(String, CaptureReader<R>)>, out: W) -> Result<(W, MergeReport), CaptureError> {
+    if sources.is_empty() || sources.len() > MAX_SOURCE_INSTRUMENTS as usize {
+        return Err(CaptureError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("merge takes 1 to {MAX_SOURCE_INSTRUMENTS} sources, got {}", sources.len()),
+        )));
+    }
+    let first = sources[0].1.header();
+    let mut header = FileHeader {
+        version: FORMAT_VERSION,
+        created_unix_ns: sources.iter().map(|(_, r)| r.header().created_unix_ns).min().unwrap_or_default(),
+        ticker: first.ticker.clone(),
+        exchange: first.exchange.clone(),
+        server_clock_offset_ms: first.server_clock_offset_ms,
+        extensions: Vec::new(),
+    };
+    header.set_codec(first.codec());
+    header.set_encoding(first.encoding());
+    header.set_hash_chain(first.hash_chain());
+    let mut instruments = Vec::new();
+    let mut files = Vec::new();
+    let mut inputs = Vec::new();
+    for (index, (name, reader)) in sources.into_iter().enumerate() {
+        let h = reader.header().clone();
+        if h.normalized().is_some() {
+            return Err(CaptureError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("source {index} ({name}) is normalized; merge takes raw captures"),
+            )));
+        }
+        let mut source = Source { index: index as u32, reader, head: None, instruments: BTreeSet::new() };
+        for mut i in h.instruments() {
+            i.id = source.renumber(i.id)?;
+            source.instruments.insert(i.id);
+            instruments.push(i);
+        }
+        files.push(SourceFile { name, created_unix_ns: h.created_unix_ns, server_clock_offset_ms: h.server_clock_offset_ms });
+        inputs.push(source);
+    }
+    header.set_instruments(instruments);
+    header.extensions.push(HeaderExtension::Sources(files));
+
+    let mut w = CaptureWriter::new(out, header)?;
+    let mut report = MergeReport { events: vec![0; inputs.len()], unclean: Vec::new() };
+    let mut dropped = 0;
+    // Sources by the receive time of their head event
+    let mut heads = BinaryHeap::new();
+    for s in &mut inputs {
+        s.advance(&mut w, &mut dropped)?;
+        if let Some(ev) = &s.head { heads.push(Reverse((ev.recv_unix_ns, s.index))); }
+    }
+    while let Some(Reverse((_, index))) = heads.pop() {
+        let s = &mut inputs[index as usize];
+        if let Some(ev) = s.head.take() {
+            w.write_event(ev)?;
+            report.events[index as usize] += 1;
+        }
+        s.advance(&mut w, &mut dropped)?;
+        if let Some(ev) = &s.head { heads.push(Reverse((ev.recv_unix_ns, s.index))); }
+    }
+    for s in &inputs {
+        if s.reader.trailer().is_none() { report.unclean.push(s.index as usize); }
+    }
+    w.set_dropped_events(dropped);
+    w.write_trailer()?;
+    Ok((w.finish()?, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::testing::price_block;
+    use crate::record::testing::{full_book, header, offer_book};
+    use crate::record::{EventKind, GapMarker};
+
+    fn capture(ticker: &str, times: &[u128], dropped: u64) -> Vec<u8> {
+        let h = FileHeader { created_unix_ns: times[0], ..header(ticker) };
+        let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+        for (seq, &t) in times.iter().enumerate() {
+            w.write_event(EventRecord { seq: seq as u64, instrument: 0, recv_unix_ns: t, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 0, value: 0 } }).unwrap();
+        }
+        w.set_dropped_events(dropped);
+        w.write_trailer().unwrap();
+        w.finish().unwrap()
+    }
+
+    #[test]
+    fn interleaves_by_time_and_keeps_source_order() {
+        // The second source's clock steps back at seq 2; its order must hold
+        let a = capture("WINFUT", &[10, 20, 30], 1);
+        let b = capture("WDOFUT", &[15, 25, 5, 40], 2);
+        let sources = vec![("a.bin".to_string(), CaptureReader::new(a.as_slice()).unwrap()), ("b.bin".to_string(), CaptureReader::new(b.as_slice()).unwrap())];
+        let (out, report) = merge(sources, Vec::new()).unwrap();
+        assert_eq!(report.events, vec![3, 4]);
+        assert!(report.unclean.is_empty());
+
+        let mut r = CaptureReader::new(out.as_slice()).unwrap();
+        let tickers: Vec<_> = r.header().instruments().into_iter().map(|i| (i.id, i.ticker)).collect();
+        assert_eq!(tickers, vec![(0, "WINFUT".to_string()), (merged_instrument(1, 0), "WDOFUT".to_string())]);
+        assert_eq!(r.header().sources()[1].name, "b.bin");
+        let events: Vec<_> = r.by_ref().filter_map(|f| match f.unwrap() {
+            RecordFrame::Event(ev) => Some((source_of(ev.instrument).0, ev.seq, ev.recv_unix_ns)),
+            _ => None,
+        }).collect();
+        assert_eq!(events, vec![(0, 0, 10), (1, 0, 15), (0, 1, 20), (1, 1, 25), (1, 2, 5), (0, 2, 30), (1, 3, 40)]);
+        assert_eq!(r.trailer().unwrap().dropped_events, 3);
+    }
+
+    #[test]
+    fn gap_in_one_source_leaves_the_others_books_intact() {
+        // Each source starts with a FullBook; the first loses events after
+        // seq 1, once both books are in sync
+        let book = |ticker: &str, times: &[u128], gap_after: Option<u64>| {
+            let mut w = CaptureWriter::new(Vec::new(), header(ticker)).unwrap();
+            for (seq, &t) in (0..).zip(times) {
+                let kind = if seq == 0 { full_book(Some(price_block(&[10.0], true)), Some(price_block(&[], true))) } else { offer_book(0, 0, 0, seq as i64, 9.0) };
+                w.write_event(EventRecord { seq, instrument: 0, recv_unix_ns: t, recv_mono_ns_from_start: 0, kind }).unwrap();
+                if gap_after == Some(seq) {
+                    w.write_frame(&RecordFrame::Gap(GapMarker { offset: 0, skipped_bytes: 1, last_seq: Some(seq), next_seq: None, scope: GapScope::All })).unwrap();
+                }
+            }
+            w.write_trailer().unwrap();
+            w.finish().unwrap()
+        };
+        let a = book("WINFUT", &[10, 20, 30, 40], Some(1));
+        let b = book("WDOFUT", &[15, 25, 35], None);
+        let sources = vec![("a.bin".to_string(), CaptureReader::new(a.as_slice()).unwrap()), ("b.bin".to_string(), CaptureReader::new(b.as_slice()).unwrap())];
+        let (out, _) = merge(sources, Vec::new()).unwrap();
+        let gaps: Vec<_> = CaptureReader::new(out.as_slice()).unwrap().filter_map(|f| match f.unwrap() {
+            RecordFrame::Gap(g) => Some(g.scope),
+            _ => None,
+        }).collect();
+        assert_eq!(gaps, vec![GapScope::Instruments(vec![merged_instrument(0, 0)])]);
+
+        // Only the first source's book goes stale: no checkpoints after its gap
+        let (out, _) = crate::replay::insert_checkpoints(CaptureReader::new(out.as_slice()).unwrap(), Vec::new(), std::time::Duration::from_nanos(1)).unwrap();
+        let checkpoints: Vec<_> = CaptureReader::new(out.as_slice()).unwrap().filter_map(|f| match f.unwrap() {
+            RecordFrame::Checkpoint(cp) => Some((source_of(cp.instrument).0, cp.seq)),
+            _ => None,
+        }).collect();
+        assert_eq!(checkpoints, vec![(0, 1), (1, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn events_of_no_instrument_keep_their_id() {
+        let mut w = CaptureWriter::new(Vec::new(), header("WINFUT")).unwrap();
+        w.write_event(EventRecord { seq: 0, instrument: NO_INSTRUMENT, recv_unix_ns: 1, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 0, value: 0 } }).unwrap();
+        let a = w.finish().unwrap();
+        let sources = vec![("a.bin".to_string(), CaptureReader::new(a.as_slice()).unwrap()), ("b.bin".to_string(), CaptureReader::new(a.as_slice()).unwrap())];
+        let (out, _) = merge(sources, Vec::new()).unwrap();
+        let ids: Vec<_> = CaptureReader::new(out.as_slice()).unwrap().filter_map(|f| match f.unwrap() {
+            RecordFrame::Event(ev) => Some(ev.instrument),
+            _ => None,
+        }).collect();
+        assert_eq!(ids, vec![NO_INSTRUMENT, NO_INSTRUMENT]);
+    }
+
+    #[test]
+    fn rejects_normalized_sources() {
+        let raw = capture("WINFUT", &[10, 20], 0);
+        let (normalized, _) = crate::normalize::normalize(CaptureReader::new(raw.as_slice()).unwrap(), Vec::new(), "a.bin", 0).unwrap();
+        let sources = vec![("a.bin".to_string(), CaptureReader::new(raw.as_slice()).unwrap()), ("n.bin".to_string(), CaptureReader::new(normalized.as_slice()).unwrap())];
+        assert!(matches!(merge(sources, Vec::new()), Err(CaptureError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidInput));
+    }
+}
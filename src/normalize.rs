@@ -0,0 +1,329 @@
+//! Normalized captures: events decoded once, for downstream tools.
+//!
+//! Raw captures keep Offer Book V2 callbacks as the DLL delivered them:
+//! integer action and side codes, entries packed in raw array blocks,
+//! FullBook snapshots split across packets, and trade times as server
+//! strings. Every reader has to redo that work. [`normalize`] does it once
+//! and writes a capture of [`RecordFrame::Normalized`] frames instead:
+//! book actions with a typed [`Side`] and `has_*` flags folded into
+//! `Option`s, FullBooks assembled into complete sides, and trades with their
+//! server time parsed (see [`parse_server_time`]).
+//!
+//! The output uses the same framing, codec and hash chain as any capture, so
+//! [`CaptureReader`], the index and `capture info`/`verify` work unchanged;
+//! checkpoints, gaps, resume markers and instrument frames are copied
+//! through. [`crate::replay::Replayer::apply_normalized`] replays it to the
+//! same books as the source. The raw capture stays the source of truth: the
+//! header names it in a [`HeaderExtension::Normalized`] entry and the file can
+//! always be regenerated from it.
+use crate::book::{AnomalyCounts, BookAction, Entry, EntryEdit, PendingFullBook, Side};
+use crate::record::{CaptureReader, CaptureWriter, Encoding, EventKind, EventRecord, GapMarker, GapScope, HeaderExtension, KindView, RecordFrame, ResumeMarker};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// UTC offset of the B3 server clock that stamps trades (Brasília time,
+/// without daylight saving since 2019).
+pub const SERVER_UTC_OFFSET_SECS: i32 = -3 * 3600;
+
+/// One decoded event of a normalized capture. Its layout is part of the
+/// capture schema (see [`crate::record::FORMAT_VERSION`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizedEvent {
+    /// Seq of the raw event; for a FullBook, of the packet that completed it.
+    pub seq: u64,
+    pub instrument: u32,
+    pub recv_unix_ns: u128,
+    pub recv_mono_ns_from_start: u128,
+    pub kind: NormalizedKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NormalizedKind {
+    /// Insert `entry` at `position` (`nPosition`, counted from the worst end).
+    Add { side: Side, position: i32, entry: Entry },
+    /// Change the fields that are `Some` of the entry at `position`.
+    /// `date: Some(None)` clears the entry's date.
+    Edit {
+        side: Side,
+        position: i32,
+        price: Option<f64>,
+        qty: Option<i64>,
+        agent: Option<i32>,
+        offer_id: Option<i64>,
+        date: Option<Option<String>>,
+    },
+    /// Remove the entry at `position`.
+    Delete { side: Side, position: i32 },
+    /// Remove the entry at `position` and every worse one.
+    DeleteFrom { side: Side, position: i32 },
+    /// FullBook packets assembled: each side present replaces that side of
+    /// the book. `complete` is `false` while the other side is still
+    /// arriving.
+    FullBook { bids: Option<Vec<Entry>>, asks: Option<Vec<Entry>>, complete: bool },
+    Trade(Trade),
+    State { state_type: i32, value: i32 },
+}
+
+impl NormalizedKind {
+    /// Entry action of an `Add`, `Edit`, `Delete` or `DeleteFrom`.
+    pub fn action(&self) -> Option<BookAction<'static>> {
+        Some(match self {
+            NormalizedKind::Add { side, position, entry } => BookAction::Add { side: *side, position: *position, entry: entry.clone() },
+            NormalizedKind::Edit { side, position, price, qty, agent, offer_id, date } => BookAction::Edit {
+                side: *side,
+                position: *position,
+                edit: EntryEdit { price: *price, qty: *qty, agent: *agent, offer_id: *offer_id, date: date.clone() },
+            },
+            NormalizedKind::Delete { side, position } => BookAction::Delete { side: *side, position: *position },
+            NormalizedKind::DeleteFrom { side, position } => BookAction::DeleteFrom { side: *side, position: *position },
+            NormalizedKind::FullBook { .. } | NormalizedKind::Trade(_) | NormalizedKind::State { .. } => return None,
+        })
+    }
+}
+
+/// `NewTrade` or `HistoryTrade` with its server time parsed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    /// `true` for a `HistoryTrade`.
+    pub history: bool,
+    /// Server trade time as UNIX nanoseconds, or `None` if `date_str` could
+    /// not be parsed.
+    pub server_unix_ns: Option<i128>,
+    /// Server time as sent by the DLL.
+    pub date_str: String,
+    pub trade_number: u32,
+    pub price: f64,
+    pub volume: f64,
+    pub qty: i32,
+    pub buy_agent: i32,
+    pub sell_agent: i32,
+    pub trade_type: i32,
+    /// Always 0 for history trades.
+    pub edit_flag: u8,
+}
+
+/// Parse a ProfitDLL server time, `DD/MM/YYYY HH:MM:SS[.fff]`, read at
+/// `utc_offset_secs` from UTC, into UNIX nanoseconds.
+pub fn parse_server_time(s: &str, utc_offset_secs: i32) -> Option<i128> {
+    let (date, clock) = s.trim().split_once(' ')?;
+    let mut d = date.split('/');
+    let (day, month, year) = (d.next()?.parse::<u8>().ok()?, d.next()?.parse::<u8>().ok()?, d.next()?.parse::<i32>().ok()?);
+    if d.next().is_some() { return None; }
+    let (hms, frac) = clock.split_once('.').unwrap_or((clock, ""));
+    let mut t = hms.split(':');
+    let (h, m, sec) = (t.next()?.parse::<u8>().ok()?, t.next()?.parse::<u8>().ok()?, t.next()?.parse::<u8>().ok()?);
+    if t.next().is_some() || frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) { return None; }
+    let nanos = if frac.is_empty() { 0 } else { frac.parse::<u32>().ok()? * 10u32.pow(9 - frac.len() as u32) };
+    let date = time::Date::from_calendar_date(year, time::Month::try_from(month).ok()?, day).ok()?;
+    let clock = time::Time::from_hms_nano(h, m, sec, nanos).ok()?;
+    let offset = time::UtcOffset::from_whole_seconds(utc_offset_secs).ok()?;
+    Some(date.with_time(clock).assume_offset(offset).unix_timestamp_nanos())
+}
+
+/// Raw event decoder. Keeps the FullBook packets of each instrument until a
+/// side completes.
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    utc_offset_secs: i32,
+    /// Packets of the FullBook in progress, per instrument.
+    pending: BTreeMap<u32, PendingFullBook>,
+    /// Book events with an action or side the DLL does not define.
+    anomalies: AnomalyCounts,
+    /// Trades whose server time could not be parsed.
+    unparsed_times: u64,
+}
+
+impl Normalizer {
+    /// Decoder reading trade times at `utc_offset_secs` from UTC.
+    pub fn new(utc_offset_secs: i32) -> Self {
+        Normalizer { utc_offset_secs, pending: BTreeMap::new(), anomalies: AnomalyCounts::default(), unparsed_times: 0 }
+    }
+
+    /// Book events skipped so far because of an unknown action or side
+    /// (replay ignores them too).
+    pub fn unknown(&self) -> u64 { self.anomalies.total() }
+
+    /// [`Self::unknown`] by reason.
+    pub fn anomalies(&self) -> AnomalyCounts { self.anomalies }
+
+    /// Trades so far whose `server_unix_ns` is `None`.
+    pub fn unparsed_times(&self) -> u64 { self.unparsed_times }
+
+    /// Decode one raw event. Returns `None` for a FullBook packet that
+    /// completes no side and for skipped events.
+    pub fn apply(&mut self, ev: &EventRecord) -> Result<Option<NormalizedEvent>> {
+        let action = match BookAction::decode(KindView::from(&ev.kind)) {
+            Ok(action) => action,
+            Err(anomaly) => {
+                self.anomalies.record(anomaly);
+                return Ok(None);
+            }
+        };
+        let kind = match (action, &ev.kind) {
+            (Some(BookAction::FullBook { bids, asks }), _) => {
+                let pending = self.pending.entry(ev.instrument).or_default();
+                let (bids, asks) = pending.push(bids, asks).with_context(|| format!("FullBook packet at seq {}", ev.seq))?;
+                let complete = pending.is_empty();
+                if complete { self.pending.remove(&ev.instrument); }
+                if bids.is_none() && asks.is_none() && !complete { return Ok(None); }
+                NormalizedKind::FullBook { bids, asks, complete }
+            }
+            (Some(BookAction::Add { side, position, entry }), _) => NormalizedKind::Add { side, position, entry },
+            (Some(BookAction::Edit { side, position, edit }), _) => {
+                let EntryEdit { price, qty, agent, offer_id, date } = edit;
+                NormalizedKind::Edit { side, position, price, qty, agent, offer_id, date }
+            }
+            (Some(BookAction::Delete { side, position }), _) => NormalizedKind::Delete { side, position },
+            (Some(BookAction::DeleteFrom { side, position }), _) => NormalizedKind::DeleteFrom { side, position },
+            (None, EventKind::OfferBookV2 { .. }) => unreachable!("Offer Book V2 events decode to a book action"),
+            (None, EventKind::NewTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag }) => {
+                NormalizedKind::Trade(self.trade(false, date_str, *trade_number, *price, *volume, *qty, *buy_agent, *sell_agent, *trade_type, *edit_flag))
+            }
+            (None, EventKind::HistoryTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type }) => {
+                NormalizedKind::Trade(self.trade(true, date_str, *trade_number, *price, *volume, *qty, *buy_agent, *sell_agent, *trade_type, 0))
+            }
+            (None, EventKind::State { state_type, value }) => NormalizedKind::State { state_type: *state_type, value: *value },
+        };
+        Ok(Some(NormalizedEvent { seq: ev.seq, instrument: ev.instrument, recv_unix_ns: ev.recv_unix_ns, recv_mono_ns_from_start: ev.recv_mono_ns_from_start, kind }))
+    }
+
+    /// Events of the instruments in `scope` were lost: their FullBooks in
+    /// progress will not complete.
+    pub fn apply_gap(&mut self, scope: &GapScope) { self.pending.retain(|id, _| !scope.covers(*id)); }
+
+    #[allow(clippy::too_many_arguments)]
+    fn trade(&mut self, history: bool, date_str: &str, trade_number: u32, price: f64, volume: f64, qty: i32, buy_agent: i32, sell_agent: i32, trade_type: i32, edit_flag: u8) -> Trade {
+        let server_unix_ns = parse_server_time(date_str, self.utc_offset_secs);
+        if server_unix_ns.is_none() { self.unparsed_times += 1; }
+        Trade { history, server_unix_ns, date_str: date_str.to_string(), trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag }
+    }
+}
+
+/// Summary of a [`normalize`] run.
+#[derive(Debug, Clone, Default)]
+pub struct NormalizeReport {
+    /// Raw events read.
+    pub events: u64,
+    /// Normalized events written.
+    pub written: u64,
+    /// FullBook packets folded into assembled sides.
+    pub full_book_packets: u64,
+    /// Book events skipped for an unknown action or side.
+    pub unknown: u64,
+    /// Trades whose server time could not be parsed.
+    pub unparsed_times: u64,
+}
+
+/// Decode every event of the raw capture `reader` (named `source` in the
+/// output header) into a normalized capture written to `out`, reading trade
+/// times at `utc_offset_secs` from UTC. The output keeps the source's codec
+/// and hash chain and gets a fresh trailer. Returns the flushed output
+/// writer and a report.
+pub fn normalize<R: Read, W: Write>(reader: CaptureReader<R>, out: W, source: &str, utc_offset_secs: i32) -> Result<(W, NormalizeReport)> {
+    let mut header = reader.header().clone();
+    if header.normalized().is_some() { bail!("the input is already normalized"); }
+    header.set_encoding(Encoding::Bincode);
+    header.extensions.push(HeaderExtension::Normalized {
+        source: source.to_string(),
+        normalized_unix_ns: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos(),
+        server_utc_offset_secs: utc_offset_secs,
+    });
+    let mut w = CaptureWriter::new(out, header)?;
+    let mut n = Normalizer::new(utc_offset_secs);
+    let mut report = NormalizeReport::default();
+    for frame in reader {
+        match frame? {
+            RecordFrame::Event(ev) => {
+                report.events += 1;
+                if let EventKind::OfferBookV2 { n_action: 4, .. } = ev.kind { report.full_book_packets += 1; }
+                if let Some(ev) = n.apply(&ev)? {
+                    w.write_frame(&RecordFrame::Normalized(ev))?;
+                    report.written += 1;
+                }
+            }
+            RecordFrame::Trailer(t) => {
+                w.set_dropped_events(t.dropped_events);
+                w.write_trailer()?;
+            }
+            frame => {
+                if let RecordFrame::Gap(GapMarker { scope, .. }) | RecordFrame::Resumed(ResumeMarker { scope, .. }) = &frame { n.apply_gap(scope); }
+                w.write_frame(&frame)?;
+            }
+        }
+    }
+    report.unknown = n.unknown();
+    report.unparsed_times = n.unparsed_times();
+    Ok((w.finish()?, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::testing::price_block;
+    use crate::record::testing::{full_book, header, offer_book};
+    use crate::replay::Replayer;
+
+    fn ev(seq: u64, kind: EventKind) -> EventRecord {
+        EventRecord { seq, instrument: 0, recv_unix_ns: seq as u128, recv_mono_ns_from_start: 0, kind }
+    }
+
+    /// Entry action carrying only a price and a quantity.
+    fn ob(seq: u64, n_action: i32, n_side: i32, n_position: i32) -> EventRecord {
+        let mut kind = offer_book(n_action, n_side, n_position, 100 + seq as i64, 50.0 + seq as f64);
+        if let EventKind::OfferBookV2 { has_offer_id, has_agent, .. } = &mut kind { (*has_offer_id, *has_agent) = (false, false); }
+        ev(seq, kind)
+    }
+
+    #[test]
+    fn parses_server_times() {
+        // 2025-09-04 10:15:30.250 at UTC-3 is 13:15:30.250 UTC
+        assert_eq!(parse_server_time("04/09/2025 10:15:30.250", SERVER_UTC_OFFSET_SECS), Some(1_756_991_730_250_000_000));
+        assert_eq!(parse_server_time("04/09/2025 10:15:30", 0), Some(1_756_980_930_000_000_000));
+        assert_eq!(parse_server_time("2025-09-04 10:15:30", 0), None);
+        assert_eq!(parse_server_time("", 0), None);
+    }
+
+    #[test]
+    fn normalized_capture_replays_like_the_raw_one() {
+        let mut w = CaptureWriter::new(Vec::new(), header("T")).unwrap();
+        let events = vec![
+            ev(0, full_book(Some(price_block(&[10.0, 9.0], false)), Some(price_block(&[11.0], true)))),
+            ev(1, full_book(Some(price_block(&[8.0], true)), None)),
+            ob(2, 0, 0, 0),
+            ob(3, 1, 1, 0),
+            ob(4, 0, 5, 0),
+            ob(5, 9, 0, 0),
+            ev(6, EventKind::NewTrade {
+                date_str: "04/09/2025 10:15:30.250".into(), trade_number: 1, price: 10.0, volume: 20.0, qty: 2,
+                buy_agent: 1, sell_agent: 2, trade_type: 3, edit_flag: 0,
+            }),
+            ob(7, 3, 0, 1),
+        ];
+        for e in &events { w.write_event(e.clone()).unwrap(); }
+        w.write_trailer().unwrap();
+        let src = w.finish().unwrap();
+
+        let (out, report) = normalize(CaptureReader::new(src.as_slice()).unwrap(), Vec::new(), "src.bin", SERVER_UTC_OFFSET_SECS).unwrap();
+        assert_eq!((report.events, report.written, report.full_book_packets, report.unknown, report.unparsed_times), (8, 6, 2, 2, 0));
+
+        let mut raw = Replayer::new();
+        for e in &events { raw.apply_event(e).unwrap(); }
+        let mut r = CaptureReader::new(out.as_slice()).unwrap();
+        assert_eq!(r.header().normalized().map(|(s, _)| s), Some("src.bin"));
+        let mut norm = Replayer::new();
+        let mut kinds = Vec::new();
+        for f in r.by_ref() {
+            let RecordFrame::Normalized(ev) = f.unwrap() else { continue };
+            norm.apply_normalized(&ev);
+            kinds.push(ev.kind);
+        }
+        assert_eq!(norm.book(), raw.book());
+        assert!(matches!(&kinds[0], NormalizedKind::FullBook { bids: None, asks: Some(a), complete: false } if a.len() == 1));
+        assert!(matches!(&kinds[1], NormalizedKind::FullBook { bids: Some(b), asks: None, complete: true } if b.len() == 3));
+        assert!(matches!(&kinds[3], NormalizedKind::Edit { side: Side::Ask, price: Some(_), agent: None, date: None, .. }));
+        assert!(matches!(&kinds[4], NormalizedKind::Trade(t) if t.server_unix_ns == Some(1_756_991_730_250_000_000)));
+        assert_eq!(r.trailer().unwrap().counts.offer_book, 5);
+    }
+}
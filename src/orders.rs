@@ -0,0 +1,350 @@
+//! Offer lookup by id and order lifecycle tracking.
+//!
+//! Offer Book V2 addresses entries by position, so finding an offer by its
+//! id means scanning the book. [`OfferIndex`] maps each `offer_id` to its
+//! index and is kept in sync with every add, edit, delete and FullBook by a
+//! [`BookBuilder`] created with [`BookBuilder::with_offer_index`].
+//!
+//! [`OrderTracker`] follows the same changes (see [`OfferChange`]) with the
+//! time of each event and keeps a history per order: insertion, edits,
+//! partial reductions and removal. Orders already resting when replay
+//! starts, or that reappear after a gap, are first seen in a FullBook and
+//! have no known insertion time. Histories are kept in memory for the whole
+//! replay; [`OrderTracker::stats`] summarizes them, including the
+//! distribution of order lifetimes.
+//!
+//! [`BookBuilder`]: crate::book::BookBuilder
+//! [`BookBuilder::with_offer_index`]: crate::book::BookBuilder::with_offer_index
+use crate::book::{Book, Entry, OfferChange, Side};
+use std::collections::{BTreeMap, HashMap};
+
+/// `offer_id` to index (from the best end) of each side of a book.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OfferIndex {
+    bids: HashMap<i64, usize>,
+    asks: HashMap<i64, usize>,
+}
+
+impl OfferIndex {
+    /// Index of the offers of `book`.
+    pub fn from_book(book: &Book) -> Self {
+        let mut index = OfferIndex::default();
+        index.reindex(Side::Bid, &book.buys, 0);
+        index.reindex(Side::Ask, &book.sells, 0);
+        index
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut HashMap<i64, usize> {
+        match side { Side::Bid => &mut self.bids, Side::Ask => &mut self.asks }
+    }
+
+    /// Record the indexes of `entries[from..]`, which moved.
+    fn reindex(&mut self, side: Side, entries: &[Entry], from: usize) {
+        let map = self.side_mut(side);
+        for (i, e) in entries.iter().enumerate().skip(from) { map.insert(e.offer_id, i); }
+    }
+
+    /// Follow one change of `book`, which already reflects it.
+    pub fn apply_change(&mut self, change: &OfferChange, book: &Book) {
+        match *change {
+            OfferChange::Added { side, index, .. } => self.reindex(side, book.side(side), index),
+            OfferChange::Edited { side, index, old, new } => {
+                if old.offer_id != new.offer_id {
+                    let map = self.side_mut(side);
+                    if map.get(&old.offer_id) == Some(&index) { map.remove(&old.offer_id); }
+                    map.insert(new.offer_id, index);
+                }
+            }
+            OfferChange::Removed { side, index, entry, .. } => {
+                let map = self.side_mut(side);
+                if map.get(&entry.offer_id) == Some(&index) { map.remove(&entry.offer_id); }
+                self.reindex(side, book.side(side), index);
+            }
+            OfferChange::Replaced { side, new, .. } => {
+                self.side_mut(side).clear();
+                self.reindex(side, new, 0);
+            }
+        }
+    }
+
+    /// Side and index of `offer_id`, bids first.
+    pub fn get(&self, offer_id: i64) -> Option<(Side, usize)> {
+        if let Some(&i) = self.bids.get(&offer_id) { return Some((Side::Bid, i)); }
+        self.asks.get(&offer_id).map(|&i| (Side::Ask, i))
+    }
+
+    /// Number of offers indexed.
+    pub fn len(&self) -> usize { self.bids.len() + self.asks.len() }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+/// Why an order left the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RemoveReason {
+    /// `atDelete`.
+    Delete,
+    /// `atDeleteFrom`, with the entries behind it.
+    DeleteFrom,
+    /// Missing from a FullBook that replaced its side.
+    Snapshot,
+    /// An edit gave its entry another offer id.
+    Renamed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderEventKind {
+    /// Added by an `atAdd`.
+    Inserted { price: f64, qty: i64 },
+    /// First seen resting: in a FullBook, or edited or renamed without
+    /// having been seen before.
+    Seen { price: f64, qty: i64 },
+    /// Price changed, or quantity increased.
+    Edited { price: f64, qty: i64 },
+    /// Quantity reduced at the same price: a partial fill or cancel.
+    Reduced { qty: i64 },
+    Removed(RemoveReason),
+}
+
+/// One step of an order's life, at the event that caused it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderEvent {
+    pub seq: u64,
+    pub recv_unix_ns: u128,
+    pub kind: OrderEventKind,
+}
+
+/// Life of one order, oldest event first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderHistory {
+    pub offer_id: i64,
+    pub side: Side,
+    pub agent: i32,
+    pub events: Vec<OrderEvent>,
+}
+
+impl OrderHistory {
+    /// `false` once the order was removed.
+    pub fn is_open(&self) -> bool { !matches!(self.events.last(), Some(OrderEvent { kind: OrderEventKind::Removed(_), .. })) }
+
+    /// Receive time from insertion to removal, if both were observed.
+    pub fn lifetime_ns(&self) -> Option<u128> {
+        let (first, last) = (self.events.first()?, self.events.last()?);
+        match (first.kind, last.kind) {
+            (OrderEventKind::Inserted { .. }, OrderEventKind::Removed(_)) => Some(last.recv_unix_ns.saturating_sub(first.recv_unix_ns)),
+            _ => None,
+        }
+    }
+
+    /// Number of events of the kinds `pred` accepts.
+    fn count(&self, pred: impl Fn(&OrderEventKind) -> bool) -> u64 { self.events.iter().filter(|e| pred(&e.kind)).count() as u64 }
+}
+
+/// Order lifetime distribution, in nanoseconds of receive time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lifetimes {
+    /// Orders whose insertion and removal were both observed.
+    pub count: u64,
+    pub min_ns: u128,
+    pub p50_ns: u128,
+    pub p90_ns: u128,
+    pub p99_ns: u128,
+    pub max_ns: u128,
+    pub mean_ns: u128,
+}
+
+/// Summary of an [`OrderTracker`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderStats {
+    /// Orders tracked, open or removed.
+    pub orders: u64,
+    /// Orders still in the book.
+    pub open: u64,
+    pub edits: u64,
+    pub reductions: u64,
+    /// Removed orders, by reason.
+    pub removed: BTreeMap<RemoveReason, u64>,
+    /// `None` if no order was both inserted and removed.
+    pub lifetimes: Option<Lifetimes>,
+}
+
+/// Order histories built from the changes of a book (see the module docs).
+#[derive(Debug, Default, Clone)]
+pub struct OrderTracker {
+    open: HashMap<(Side, i64), OrderHistory>,
+    closed: Vec<OrderHistory>,
+}
+
+impl OrderTracker {
+    pub fn new() -> Self { Self::default() }
+
+    fn event(seq: u64, recv_unix_ns: u128, kind: OrderEventKind) -> OrderEvent { OrderEvent { seq, recv_unix_ns, kind } }
+
+    /// Start the history of `e`. An id reused while its order is open
+    /// closes the old history as it is.
+    fn start(&mut self, side: Side, e: &Entry, first: OrderEvent) {
+        let h = OrderHistory { offer_id: e.offer_id, side, agent: e.agent, events: vec![first] };
+        if let Some(old) = self.open.insert((side, e.offer_id), h) { self.closed.push(old); }
+    }
+
+    fn close(&mut self, side: Side, offer_id: i64, ev: OrderEvent) {
+        if let Some(mut h) = self.open.remove(&(side, offer_id)) {
+            h.events.push(ev);
+            self.closed.push(h);
+        }
+    }
+
+    /// Record a change of `old` into `new` (same offer id) on `side`.
+    fn edit(&mut self, side: Side, old: &Entry, new: &Entry, seq: u64, recv_unix_ns: u128) {
+        let kind = if new.price == old.price && new.qty < old.qty {
+            OrderEventKind::Reduced { qty: new.qty }
+        } else if new.price != old.price || new.qty != old.qty {
+            OrderEventKind::Edited { price: new.price, qty: new.qty }
+        } else {
+            return;
+        };
+        if !self.open.contains_key(&(side, old.offer_id)) {
+            self.start(side, old, Self::event(seq, recv_unix_ns, OrderEventKind::Seen { price: old.price, qty: old.qty }));
+        }
+        let h = self.open.get_mut(&(side, old.offer_id)).expect("history started above");
+        h.agent = new.agent;
+        h.events.push(Self::event(seq, recv_unix_ns, kind));
+    }
+
+    /// Record one change made by the event with `seq` received at
+    /// `recv_unix_ns`.
+    pub fn record(&mut self, seq: u64, recv_unix_ns: u128, change: &OfferChange) {
+        let ev = |kind| Self::event(seq, recv_unix_ns, kind);
+        match *change {
+            OfferChange::Added { side, entry, .. } => self.start(side, entry, ev(OrderEventKind::Inserted { price: entry.price, qty: entry.qty })),
+            OfferChange::Edited { side, old, new, .. } if old.offer_id != new.offer_id => {
+                self.close(side, old.offer_id, ev(OrderEventKind::Removed(RemoveReason::Renamed)));
+                self.start(side, new, ev(OrderEventKind::Seen { price: new.price, qty: new.qty }));
+            }
+            OfferChange::Edited { side, old, new, .. } => self.edit(side, old, new, seq, recv_unix_ns),
+            OfferChange::Removed { side, entry, cascade, .. } => {
+                let reason = if cascade { RemoveReason::DeleteFrom } else { RemoveReason::Delete };
+                self.close(side, entry.offer_id, ev(OrderEventKind::Removed(reason)));
+            }
+            OfferChange::Replaced { side, old, new } => {
+                let kept: HashMap<i64, &Entry> = new.iter().map(|e| (e.offer_id, e)).collect();
+                for e in old.iter().filter(|e| !kept.contains_key(&e.offer_id)) {
+                    self.close(side, e.offer_id, ev(OrderEventKind::Removed(RemoveReason::Snapshot)));
+                }
+                let before: HashMap<i64, &Entry> = old.iter().map(|e| (e.offer_id, e)).collect();
+                for e in new {
+                    match before.get(&e.offer_id) {
+                        Some(prev) if self.open.contains_key(&(side, e.offer_id)) => self.edit(side, prev, e, seq, recv_unix_ns),
+                        _ => self.start(side, e, ev(OrderEventKind::Seen { price: e.price, qty: e.qty })),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Histories of `offer_id` on either side, removed ones first.
+    pub fn history(&self, offer_id: i64) -> Vec<&OrderHistory> {
+        let open = [Side::Bid, Side::Ask].into_iter().filter_map(|side| self.open.get(&(side, offer_id)));
+        self.closed.iter().filter(|h| h.offer_id == offer_id).chain(open).collect()
+    }
+
+    /// Orders still in the book, in no particular order.
+    pub fn open(&self) -> impl Iterator<Item = &OrderHistory> { self.open.values() }
+
+    /// Removed orders, in removal order.
+    pub fn closed(&self) -> &[OrderHistory] { &self.closed }
+
+    /// Lifetimes of the removed orders whose insertion was observed, sorted.
+    pub fn lifetimes_ns(&self) -> Vec<u128> {
+        let mut l: Vec<u128> = self.closed.iter().filter_map(OrderHistory::lifetime_ns).collect();
+        l.sort_unstable();
+        l
+    }
+
+    pub fn stats(&self) -> OrderStats {
+        let mut stats = OrderStats { open: self.open.len() as u64, ..Default::default() };
+        for h in self.closed.iter().chain(self.open.values()) {
+            stats.orders += 1;
+            stats.edits += h.count(|k| matches!(k, OrderEventKind::Edited { .. }));
+            stats.reductions += h.count(|k| matches!(k, OrderEventKind::Reduced { .. }));
+            if let Some(OrderEvent { kind: OrderEventKind::Removed(reason), .. }) = h.events.last() {
+                *stats.removed.entry(*reason).or_default() += 1;
+            }
+        }
+        let l = self.lifetimes_ns();
+        if let (Some(&min_ns), Some(&max_ns)) = (l.first(), l.last()) {
+            // Nearest-rank percentile
+            let pct = |p: usize| l[(l.len() * p).div_ceil(100).saturating_sub(1)];
+            stats.lifetimes = Some(Lifetimes {
+                count: l.len() as u64,
+                min_ns,
+                p50_ns: pct(50),
+                p90_ns: pct(90),
+                p99_ns: pct(99),
+                max_ns,
+                mean_ns: l.iter().sum::<u128>() / l.len() as u128,
+            });
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::testing::entry;
+    use crate::book::{BookAction, BookBuilder, EntryEdit};
+
+    #[test]
+    fn index_and_histories_follow_the_book() {
+        let mut b = BookBuilder::new().with_offer_index();
+        let mut t = OrderTracker::new();
+        b.apply_assembled_observed(Some(vec![entry(1, 10.0, 5), entry(2, 9.0, 5)]), Some(vec![entry(3, 11.0, 5)]), true, &mut |c| t.record(0, 0, c));
+        let steps = [
+            BookAction::Add { side: Side::Bid, position: 1, entry: entry(4, 10.5, 2) },
+            BookAction::Edit { side: Side::Bid, position: 1, edit: EntryEdit { qty: Some(3), ..Default::default() } },
+            BookAction::Edit { side: Side::Bid, position: 1, edit: EntryEdit { price: Some(9.5), ..Default::default() } },
+            BookAction::Edit { side: Side::Bid, position: 0, edit: EntryEdit { qty: Some(4), ..Default::default() } },
+            BookAction::Delete { side: Side::Bid, position: 2 },
+            BookAction::Add { side: Side::Ask, position: 0, entry: entry(5, 12.0, 1) },
+            BookAction::DeleteFrom { side: Side::Bid, position: 0 },
+        ];
+        for (seq, action) in (1..).zip(steps) {
+            b.apply_observed(action, &mut |c| t.record(seq, seq as u128 * 1_000, c)).unwrap();
+            // The index agrees with a scan of the book
+            for side in [Side::Bid, Side::Ask] {
+                for (i, entry) in b.book().side(side).iter().enumerate() {
+                    assert_eq!(b.find_offer(entry.offer_id).map(|p| (p.side, p.index)), Some((side, i)), "seq {}", seq);
+                }
+            }
+        }
+        assert_eq!(b.find_offer(2), None);
+        assert_eq!(b.find_offer(5).map(|p| (p.side, p.index, p.n_position)), Some((Side::Ask, 1, 0)));
+        b.apply_assembled_observed(Some(vec![entry(4, 9.5, 1)]), Some(vec![entry(5, 12.0, 1)]), true, &mut |c| t.record(8, 8_000, c));
+        b.apply_observed(BookAction::Delete { side: Side::Ask, position: 0 }, &mut |c| t.record(9, 9_000, c)).unwrap();
+
+        let kinds = |id: i64| t.history(id)[0].events.iter().map(|e| (e.seq, e.kind)).collect::<Vec<_>>();
+        assert_eq!(kinds(4), [
+            (1, OrderEventKind::Inserted { price: 10.5, qty: 2 }),
+            (2, OrderEventKind::Edited { price: 10.5, qty: 3 }),
+            (3, OrderEventKind::Edited { price: 9.5, qty: 3 }),
+            (8, OrderEventKind::Reduced { qty: 1 }),
+        ]);
+        assert_eq!(kinds(2), [
+            (0, OrderEventKind::Seen { price: 9.0, qty: 5 }),
+            (4, OrderEventKind::Reduced { qty: 4 }),
+            (7, OrderEventKind::Removed(RemoveReason::DeleteFrom)),
+        ]);
+        assert_eq!(kinds(3).last(), Some(&(8, OrderEventKind::Removed(RemoveReason::Snapshot))));
+        assert!(t.history(4)[0].is_open());
+        assert_eq!(t.history(1)[0].lifetime_ns(), None);
+        assert_eq!(t.history(5)[0].lifetime_ns(), Some(3_000));
+
+        let stats = t.stats();
+        assert_eq!((stats.orders, stats.open, stats.edits, stats.reductions), (5, 1, 2, 2));
+        let removed: Vec<_> = stats.removed.into_iter().collect();
+        assert_eq!(removed, [(RemoveReason::Delete, 2), (RemoveReason::DeleteFrom, 1), (RemoveReason::Snapshot, 1)]);
+        let l = stats.lifetimes.unwrap();
+        assert_eq!((l.count, l.p50_ns, l.max_ns), (1, 3_000, 3_000));
+    }
+}
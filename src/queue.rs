@@ -0,0 +1,180 @@
+//! Queue-position estimation for hypothetical resting orders.
+//!
+//! A [`VirtualOrder`] is placed at a price on one side of a [`Book`] as if it
+//! had been sent at that moment: it joins the back of the queue at its price,
+//! behind the offers already resting there in the book's L3 order. After
+//! every book event [`VirtualOrder::observe_book`] looks up which of those
+//! offers are still ahead of it, so cancels, reductions and fills of the
+//! offers in front drain the queue. Offers that later arrive at the same
+//! price queue behind it, unless the book places them in front of an offer
+//! that is ahead. An order priced at or through the best offer on the other
+//! side would trade at once instead of resting, so it is not placed.
+//!
+//! Trades at the order's price ([`VirtualOrder::observe_trade`]) drain the
+//! queue too, since the feed may report them before the book catches up; the
+//! queue ahead only ever shrinks. Volume traded at the price beyond the
+//! queue ahead fills the virtual order, and a trade through its price fills
+//! it completely. The order never affects the book or other orders.
+//!
+//! Trades are attributed by price alone, not by aggressor: a trade at the
+//! order's price is assumed to have hit the order's side. That holds in
+//! continuous trading, where the other side rests at worse prices; auction
+//! prints and direct (cross) trades at the price drain the queue too, so the
+//! estimate may be optimistic around them.
+use crate::book::{Book, Side};
+use std::collections::HashSet;
+
+/// State of the queue at one event that changed it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueSample {
+    pub seq: u64,
+    pub recv_unix_ns: u128,
+    /// Quantity ahead of the order.
+    pub ahead_qty: i64,
+    /// Offers ahead of the order, as the book shows them.
+    pub ahead_orders: usize,
+    /// Quantity of the order filled so far.
+    pub filled_qty: i64,
+}
+
+/// Order that rests in the queue without being in the book (see the module
+/// docs).
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualOrder {
+    side: Side,
+    price: f64,
+    qty: i64,
+    /// Offers ahead, by id.
+    ahead_ids: HashSet<i64>,
+    ahead_qty: i64,
+    filled_qty: i64,
+    /// Seq and receive time of the event that completed the fill.
+    filled_at: Option<(u64, u128)>,
+    /// First sample at placement, then one per change.
+    samples: Vec<QueueSample>,
+}
+
+impl VirtualOrder {
+    /// Place `qty` at `price` on `side` of `book`, after the event with `seq`
+    /// received at `recv_unix_ns`. `None` if `price` crosses the book.
+    pub fn place(book: &Book, side: Side, price: f64, qty: i64, seq: u64, recv_unix_ns: u128) -> Option<Self> {
+        let crosses = match side {
+            Side::Bid => book.side(Side::Ask).first().is_some_and(|e| price >= e.price),
+            Side::Ask => book.side(Side::Bid).first().is_some_and(|e| price <= e.price),
+        };
+        if crosses { return None; }
+        let ahead: Vec<_> = book.side(side).iter().filter(|e| e.price == price).collect();
+        let mut order = VirtualOrder {
+            side,
+            price,
+            qty,
+            ahead_ids: ahead.iter().map(|e| e.offer_id).collect(),
+            ahead_qty: ahead.iter().map(|e| e.qty).sum(),
+            filled_qty: 0,
+            filled_at: None,
+            samples: Vec::new(),
+        };
+        order.sample(seq, recv_unix_ns);
+        Some(order)
+    }
+
+    pub fn side(&self) -> Side { self.side }
+
+    pub fn price(&self) -> f64 { self.price }
+
+    pub fn qty(&self) -> i64 { self.qty }
+
+    /// Quantity ahead of the order.
+    pub fn ahead_qty(&self) -> i64 { self.ahead_qty }
+
+    /// Offers ahead of the order.
+    pub fn ahead_orders(&self) -> usize { self.ahead_ids.len() }
+
+    pub fn filled_qty(&self) -> i64 { self.filled_qty }
+
+    /// Seq and receive time of the event that filled the order completely.
+    pub fn filled_at(&self) -> Option<(u64, u128)> { self.filled_at }
+
+    pub fn is_filled(&self) -> bool { self.filled_at.is_some() }
+
+    /// How the queue drained, oldest first.
+    pub fn samples(&self) -> &[QueueSample] { &self.samples }
+
+    fn sample(&mut self, seq: u64, recv_unix_ns: u128) {
+        let s = QueueSample { seq, recv_unix_ns, ahead_qty: self.ahead_qty, ahead_orders: self.ahead_ids.len(), filled_qty: self.filled_qty };
+        let same = |l: &QueueSample| (l.ahead_qty, l.ahead_orders, l.filled_qty) == (s.ahead_qty, s.ahead_orders, s.filled_qty);
+        if !self.samples.last().is_some_and(same) { self.samples.push(s); }
+    }
+
+    /// Follow `book` after the event with `seq` changed it. The offers ahead
+    /// are those at the order's price up to the last one that was ahead.
+    pub fn observe_book(&mut self, book: &Book, seq: u64, recv_unix_ns: u128) {
+        if self.is_filled() || self.ahead_ids.is_empty() { return; }
+        let level: Vec<_> = book.side(self.side).iter().filter(|e| e.price == self.price).collect();
+        let end = level.iter().rposition(|e| self.ahead_ids.contains(&e.offer_id)).map_or(0, |i| i + 1);
+        let ahead = &level[..end];
+        self.ahead_ids = ahead.iter().map(|e| e.offer_id).collect();
+        self.ahead_qty = self.ahead_qty.min(ahead.iter().map(|e| e.qty).sum());
+        if self.ahead_qty == 0 { self.ahead_ids.clear(); }
+        self.sample(seq, recv_unix_ns);
+    }
+
+    /// Follow a trade of `qty` at `price`.
+    pub fn observe_trade(&mut self, price: f64, qty: i64, seq: u64, recv_unix_ns: u128) {
+        if self.is_filled() { return; }
+        let through = match self.side { Side::Bid => price < self.price, Side::Ask => price > self.price };
+        if through {
+            self.ahead_qty = 0;
+            self.ahead_ids.clear();
+            self.filled_qty = self.qty;
+        } else if price == self.price {
+            let drained = qty.min(self.ahead_qty);
+            self.ahead_qty -= drained;
+            if self.ahead_qty == 0 { self.ahead_ids.clear(); }
+            self.filled_qty = (self.filled_qty + qty - drained).min(self.qty);
+        }
+        if self.filled_qty >= self.qty { self.filled_at = Some((seq, recv_unix_ns)); }
+        self.sample(seq, recv_unix_ns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::testing::entry;
+    use crate::book::{BookAction, BookBuilder, EntryEdit};
+
+    #[test]
+    fn queue_drains_until_filled() {
+        let mut b = BookBuilder::from_book(Book { buys: vec![entry(1, 10.0, 5), entry(2, 10.0, 3), entry(3, 9.5, 4)], sells: vec![entry(5, 10.5, 1)] });
+        // At or through the best offer on the other side it would not rest
+        assert!(VirtualOrder::place(b.book(), Side::Bid, 10.5, 4, 0, 0).is_none());
+        assert!(VirtualOrder::place(b.book(), Side::Ask, 10.0, 4, 0, 0).is_none());
+        let mut q = VirtualOrder::place(b.book(), Side::Bid, 10.0, 4, 0, 0).unwrap();
+        assert_eq!((q.ahead_qty(), q.ahead_orders()), (8, 2));
+        let steps = [
+            // Joins behind the virtual order: index 2, after offer 2
+            BookAction::Add { side: Side::Bid, position: 1, entry: entry(4, 10.0, 6) },
+            // Offer 1 reduced to 2
+            BookAction::Edit { side: Side::Bid, position: 3, edit: EntryEdit { qty: Some(2), ..Default::default() } },
+            // Offer 2 deleted
+            BookAction::Delete { side: Side::Bid, position: 2 },
+        ];
+        for (seq, action) in (1..).zip(steps) {
+            b.apply_action(action).unwrap();
+            q.observe_book(b.book(), seq, seq as u128);
+        }
+        assert_eq!((q.ahead_qty(), q.ahead_orders()), (2, 1));
+        // Trades at a worse price, then at the price: 2 drain offer 1, 1 fills
+        q.observe_trade(10.5, 9, 4, 4);
+        q.observe_trade(10.0, 3, 5, 5);
+        assert_eq!((q.ahead_qty(), q.ahead_orders(), q.filled_qty(), q.is_filled()), (0, 0, 1, false));
+        // The book catching up with the trade changes nothing
+        b.apply_action(BookAction::Delete { side: Side::Bid, position: 2 }).unwrap();
+        q.observe_book(b.book(), 6, 6);
+        q.observe_trade(9.5, 1, 7, 7);
+        assert_eq!((q.filled_qty(), q.filled_at()), (4, Some((7, 7))));
+        let drained: Vec<_> = q.samples().iter().map(|s| (s.seq, s.ahead_qty, s.filled_qty)).collect();
+        assert_eq!(drained, [(0, 8, 0), (2, 5, 0), (3, 2, 0), (5, 0, 1), (7, 0, 4)]);
+    }
+}
@@ -6,21 +6,314 @@
 //! - CRC32 is computed over `payload` only (little-endian fields).
 //!
 //! The first frame is always [`RecordFrame::Header`], with basic metadata
-//! and an estimate of server clock offset versus local time.
+//! and an estimate of server clock offset versus local time. Its `version`
+//! field selects the schema used to decode the rest of the file; see
+//! [`FORMAT_VERSION`] and the frozen [`v1`] to [`v4`] schemas.
 //! Subsequent frames are [`RecordFrame::Event`] variants carrying raw
-//! L3 Offer Book V2 array blocks and Time & Sales events.
+//! L3 Offer Book V2 array blocks and Time & Sales events, each tagged with an
+//! instrument from the file's instrument table (see
+//! [`FileHeader::instruments`] and [`RecordFrame::Instrument`]), optionally
+//! interleaved with [`RecordFrame::Checkpoint`] book snapshots and, in
+//! files produced by [`repair`], [`RecordFrame::Gap`] markers. A file closed
+//! cleanly ends with a [`RecordFrame::Trailer`]; one without a trailer was
+//! cut short (crash, kill, full disk). A recorder restarted on the same file
+//! continues it with [`resume`], leaving a [`RecordFrame::Resumed`] marker.
+//! Normalized files derived from a capture hold [`RecordFrame::Normalized`]
+//! events instead of raw ones (see [`crate::normalize`]).
 //!
 //! This schema is intentionally simple, self-describing, and append-friendly.
 //! It allows exact reconstruction and integrity checks during playback.
+//!
+//! Frame payloads after the header may be compressed with the [`Codec`]
+//! declared in the header (see [`HeaderExtension::Compression`]); the CRC
+//! always covers the bytes as stored.
+//!
+//! Events may instead use a fixed layout that is read in place (see
+//! [`Encoding`] and [`CaptureReader::read_view`]); the header says which.
+//!
+//! Files recorded with [`HeaderExtension::HashChain`] end every frame with a
+//! running SHA-256 over the previous link and the frame's data, so edits
+//! after recording are detected (see [`CaptureError::ChainMismatch`]).
+//!
+//! [`CaptureWriter`] and [`CaptureReader`] are the single implementation of
+//! this framing: the recorder, tests and offline tools all go through them so
+//! equal frames always produce byte-identical files. Damaged input is
+//! reported as a structured [`CaptureError`].
+use crate::normalize::{NormalizedEvent, NormalizedKind};
 use serde::{Deserialize, Serialize};
 
+mod chain;
+mod compress;
+mod fixed;
+mod repair;
+mod resume;
+pub mod v1;
+pub mod v2;
+pub mod v3;
+pub mod v4;
+
+pub use chain::DIGEST_LEN;
+pub use fixed::{BlockView, EventView, KindView};
+pub use repair::{repair, RepairReport};
+pub use resume::{resume, ResumeReport};
+
+/// Schema version written by [`CaptureWriter`].
+///
+/// Version history:
+/// - 1: initial schema (header without extensions).
+/// - 2: [`FileHeader::extensions`].
+/// - 3: [`EventRecord::instrument`] and [`BookCheckpoint::instrument`].
+/// - 4: [`GapMarker::scope`].
+/// - 5: [`Trailer::chain_digest`].
+///
+/// Bump this whenever the bincode layout of an existing type changes, and
+/// freeze the previous layout in a `vN` module so [`CaptureReader`] can keep
+/// decoding old files. Appending a variant to [`HeaderExtension`] or
+/// [`RecordFrame`] does not change existing layouts and needs no bump.
+pub const FORMAT_VERSION: u16 = 5;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileHeader {
+    /// Schema version of the file; always [`FORMAT_VERSION`] when written by
+    /// [`CaptureWriter`].
     pub version: u16,
     pub created_unix_ns: u128,
     pub ticker: String,
     pub exchange: String,
     pub server_clock_offset_ms: i64, // server_time - local_time at start
+    /// Optional header sections (since v2).
+    pub extensions: Vec<HeaderExtension>,
+}
+
+/// Optional, self-describing header section.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HeaderExtension {
+    /// The file was rewritten from an older schema version by [`upgrade`].
+    UpgradedFrom { version: u16, upgraded_unix_ns: u128 },
+    /// Frames after the header are compressed with this codec.
+    Compression(Codec),
+    /// The file continues a rotated set (see [`crate::rotate`]): `previous`
+    /// is the file name of the preceding part and `last_seq` the seq of the
+    /// last event written to it.
+    Continuation { part: u32, previous: String, last_seq: Option<u64> },
+    /// Instruments recorded in the file. Without this extension the file
+    /// holds one instrument, id 0, described by `ticker`/`exchange`.
+    Instruments(Vec<Instrument>),
+    /// Event payloads use this encoding ([`Encoding::Bincode`] if absent).
+    Encoding(Encoding),
+    /// The file was produced by [`crate::merge::merge`] from these captures,
+    /// in source order (see [`crate::merge::source_of`]).
+    Sources(Vec<SourceFile>),
+    /// Every frame after the header ends with a SHA-256 hash-chain digest.
+    HashChain,
+    /// How the recorder that wrote the file was built, run and configured.
+    Provenance(Provenance),
+    /// The file holds [`RecordFrame::Normalized`] events decoded by
+    /// [`crate::normalize::normalize`] from the raw capture `source` (file
+    /// name), with trade times read at `server_utc_offset_secs` from UTC.
+    Normalized { source: String, normalized_unix_ns: u128, server_utc_offset_secs: i32 },
+}
+
+/// Recording environment of a capture (see [`HeaderExtension::Provenance`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Version of the recorder binary.
+    pub recorder_version: String,
+    /// ProfitDLL path as configured.
+    pub dll_path: String,
+    /// SHA-256 of the DLL file, if it could be read.
+    pub dll_sha256: Option<[u8; DIGEST_LEN]>,
+    pub hostname: String,
+    /// Effective recorder options (flags and environment), credentials removed.
+    pub options: String,
+    /// Local UTC offset at start, in seconds, if it could be determined.
+    pub utc_offset_secs: Option<i32>,
+    /// Raw `GetServerClock` result at start.
+    pub server_clock: ServerClock,
+    /// Capacity of the callback-to-writer event queue, in frames.
+    pub channel_capacity: u32,
+}
+
+/// Outputs of one ProfitDLL `GetServerClock` call, as returned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerClock {
+    /// Return code (`NL_OK` on success).
+    pub result: i32,
+    /// `dtDate`, a Delphi `TDateTime` (days since 1899-12-30).
+    pub date: f64,
+    pub year: i32,
+    pub month: i32,
+    pub day: i32,
+    pub hour: i32,
+    pub minute: i32,
+    pub second: i32,
+    pub millisecond: i32,
+}
+
+/// Capture merged into a file (see [`HeaderExtension::Sources`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFile {
+    /// File name of the source.
+    pub name: String,
+    pub created_unix_ns: u128,
+    /// Clock offset of the machine that recorded it.
+    pub server_clock_offset_ms: i64,
+}
+
+/// On-disk encoding of event payloads. Other frames are always bincode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Encoding {
+    /// bincode [`RecordFrame::Event`], decoded into owned values.
+    #[default]
+    Bincode,
+    /// Hand-laid little-endian layout that can be read in place as an
+    /// [`EventView`], without allocating.
+    Fixed,
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self { Encoding::Bincode => "bincode", Encoding::Fixed => "fixed" })
+    }
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bincode" => Ok(Encoding::Bincode),
+            "fixed" => Ok(Encoding::Fixed),
+            _ => Err(format!("unknown encoding {s:?} (expected bincode or fixed)")),
+        }
+    }
+}
+
+/// Entry of a capture's instrument table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instrument {
+    /// Id used by [`EventRecord::instrument`]; unique within the file.
+    pub id: u32,
+    pub ticker: String,
+    pub exchange: String,
+    /// ProfitDLL `nFeed` of the asset.
+    pub feed: i32,
+}
+
+/// Frame payload compression codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Codec {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self { Codec::None => "none", Codec::Zstd => "zstd", Codec::Lz4 => "lz4" })
+    }
+}
+
+impl std::str::FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Codec::None),
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            _ => Err(format!("unknown codec {s:?} (expected none, zstd or lz4)")),
+        }
+    }
+}
+
+impl FileHeader {
+    /// Codec used for frames after the header ([`Codec::None`] if absent).
+    pub fn codec(&self) -> Codec {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Compression(c) => Some(*c),
+            _ => None,
+        }).unwrap_or_default()
+    }
+
+    /// Instruments declared in the header (see [`HeaderExtension::Instruments`]).
+    /// Instruments added later in the file arrive as [`RecordFrame::Instrument`].
+    pub fn instruments(&self) -> Vec<Instrument> {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Instruments(list) => Some(list.clone()),
+            _ => None,
+        }).unwrap_or_else(|| vec![Instrument { id: 0, ticker: self.ticker.clone(), exchange: self.exchange.clone(), feed: 0 }])
+    }
+
+    /// Declare the instrument table, replacing any previous one.
+    pub fn set_instruments(&mut self, instruments: Vec<Instrument>) {
+        self.extensions.retain(|e| !matches!(e, HeaderExtension::Instruments(_)));
+        self.extensions.push(HeaderExtension::Instruments(instruments));
+    }
+
+    /// Source captures of a merged file, empty otherwise.
+    pub fn sources(&self) -> &[SourceFile] {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Sources(s) => Some(s.as_slice()),
+            _ => None,
+        }).unwrap_or_default()
+    }
+
+    /// Continuation reference, if this file is a later part of a rotated set.
+    pub fn continuation(&self) -> Option<(u32, &str, Option<u64>)> {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Continuation { part, previous, last_seq } => Some((*part, previous.as_str(), *last_seq)),
+            _ => None,
+        })
+    }
+
+    /// Recording environment, if the recorder stored one.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Provenance(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    /// Source capture and server UTC offset of a normalized file.
+    pub fn normalized(&self) -> Option<(&str, i32)> {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Normalized { source, server_utc_offset_secs, .. } => Some((source.as_str(), *server_utc_offset_secs)),
+            _ => None,
+        })
+    }
+
+    /// Encoding of event payloads ([`Encoding::Bincode`] if absent).
+    pub fn encoding(&self) -> Encoding {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Encoding(enc) => Some(*enc),
+            _ => None,
+        }).unwrap_or_default()
+    }
+
+    /// Declare the encoding of event payloads, replacing any previous choice.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.extensions.retain(|e| !matches!(e, HeaderExtension::Encoding(_)));
+        if encoding != Encoding::Bincode { self.extensions.push(HeaderExtension::Encoding(encoding)); }
+    }
+
+    /// `true` if frames after the header carry hash-chain digests.
+    pub fn hash_chain(&self) -> bool {
+        self.extensions.contains(&HeaderExtension::HashChain)
+    }
+
+    /// Turn the hash chain on or off for frames after the header.
+    pub fn set_hash_chain(&mut self, on: bool) {
+        self.extensions.retain(|e| *e != HeaderExtension::HashChain);
+        if on { self.extensions.push(HeaderExtension::HashChain); }
+    }
+
+    /// Declare `codec` for frames after the header, replacing any previous choice.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.extensions.retain(|e| !matches!(e, HeaderExtension::Compression(_)));
+        if codec != Codec::None { self.extensions.push(HeaderExtension::Compression(codec)); }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,10 +372,16 @@ pub enum EventKind {
     State { state_type: i32, value: i32 },
 }
 
+/// [`EventRecord::instrument`] of events that belong to no asset, such as
+/// connection state changes. Never in the instrument table.
+pub const NO_INSTRUMENT: u32 = u32::MAX;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventRecord {
     /// Monotonic sequence per process.
     pub seq: u64,
+    /// Instrument id in the file's instrument table, or [`NO_INSTRUMENT`].
+    pub instrument: u32,
     /// System wall-clock time in nanoseconds since UNIX_EPOCH (capture side).
     pub recv_unix_ns: u128,
     /// Monotonic time since process start, in nanoseconds (capture side).
@@ -90,8 +389,1125 @@ pub struct EventRecord {
     pub kind: EventKind,
 }
 
+/// Reconstructed book state after applying event `seq`.
+///
+/// Written periodically by the recorder or inserted offline, so a replay can
+/// start here instead of waiting for the next FullBook.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookCheckpoint {
+    /// Instrument whose book this is.
+    pub instrument: u32,
+    /// Seq of the last event applied to `book`.
+    pub seq: u64,
+    /// `recv_unix_ns` of that event.
+    pub recv_unix_ns: u128,
+    /// [`crate::book::Book::state_hash`] of `book`.
+    pub hash: u64,
+    pub book: crate::book::Book,
+}
+
+/// Marks bytes dropped by [`repair`] because no valid frame could be read
+/// from them. Events may be missing here, so a replayed book is unreliable
+/// until the next FullBook or checkpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GapMarker {
+    /// Byte offset of the damaged region in the original file.
+    pub offset: u64,
+    /// Length of the damaged region.
+    pub skipped_bytes: u64,
+    /// Seq of the last event before the gap, if any.
+    pub last_seq: Option<u64>,
+    /// Seq of the first event after the gap, if it directly follows it.
+    pub next_seq: Option<u64>,
+    /// Instruments whose events may be missing.
+    pub scope: GapScope,
+}
+
+/// Marks where a restarted recorder continued the file with [`resume`].
+/// Events received while it was down are missing, so the book of every
+/// instrument in `scope` is unreliable until its next FullBook or
+/// checkpoint, and `recv_mono_ns_from_start` restarts from zero after this
+/// frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResumeMarker {
+    pub resumed_unix_ns: u128,
+    /// Seq of the last event before the restart, if any.
+    pub last_seq: Option<u64>,
+    /// Bytes of a partial frame cut off the end of the file.
+    pub truncated_bytes: u64,
+    /// Clock offset measured by the restarted recorder.
+    pub server_clock_offset_ms: i64,
+    /// Instruments whose events may be missing.
+    pub scope: GapScope,
+    /// Extensions of the restarted recorder's own header (e.g. its
+    /// [`HeaderExtension::Provenance`]); the file keeps the header and
+    /// settings of the first run.
+    pub extensions: Vec<HeaderExtension>,
+}
+
+impl ResumeMarker {
+    /// Recording environment of the restarted recorder, if it stored one.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.extensions.iter().find_map(|e| match e {
+            HeaderExtension::Provenance(p) => Some(p),
+            _ => None,
+        })
+    }
+}
+
+/// Instruments a [`GapMarker`] or [`ResumeMarker`] applies to. Markers
+/// written for a whole file cover every instrument; in a merged file (see
+/// [`crate::merge`]) they only cover the instruments of the source they
+/// came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GapScope {
+    #[default]
+    All,
+    Instruments(Vec<u32>),
+}
+
+impl GapScope {
+    pub fn covers(&self, instrument: u32) -> bool {
+        match self {
+            GapScope::All => true,
+            GapScope::Instruments(ids) => ids.contains(&instrument),
+        }
+    }
+}
+
+/// Number of events of each [`EventKind`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventCounts {
+    pub offer_book: u64,
+    pub new_trade: u64,
+    pub history_trade: u64,
+    pub state: u64,
+}
+
+impl EventCounts {
+    pub fn add(&mut self, kind: &EventKind) {
+        match kind {
+            EventKind::OfferBookV2 { .. } => self.offer_book += 1,
+            EventKind::NewTrade { .. } => self.new_trade += 1,
+            EventKind::HistoryTrade { .. } => self.history_trade += 1,
+            EventKind::State { .. } => self.state += 1,
+        }
+    }
+
+    /// Count a normalized event under the raw kind it was decoded from.
+    pub fn add_normalized(&mut self, kind: &NormalizedKind) {
+        match kind {
+            NormalizedKind::Trade(t) if t.history => self.history_trade += 1,
+            NormalizedKind::Trade(_) => self.new_trade += 1,
+            NormalizedKind::State { .. } => self.state += 1,
+            _ => self.offer_book += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 { self.offer_book + self.new_trade + self.history_trade + self.state }
+}
+
+/// Session summary, written as the last frame when a capture is closed
+/// cleanly (see [`CaptureWriter::write_trailer`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trailer {
+    /// Seq of the last event in the file.
+    pub final_seq: Option<u64>,
+    pub counts: EventCounts,
+    /// `recv_unix_ns` of the first event in the file.
+    pub first_recv_unix_ns: Option<u128>,
+    /// `recv_unix_ns` of the last event in the file.
+    pub last_recv_unix_ns: Option<u128>,
+    /// Events the recorder received but discarded: those that arrived after
+    /// shutdown began or after the writer stopped (a full queue blocks the
+    /// callbacks rather than dropping events).
+    pub dropped_events: u64,
+    /// CRC32 of every byte of the file before the trailer frame.
+    pub file_crc32: u32,
+    /// Hash-chain digest of the last frame before the trailer, in files
+    /// with [`HeaderExtension::HashChain`].
+    pub chain_digest: Option<[u8; DIGEST_LEN]>,
+}
+
+impl Trailer {
+    /// Account for `ev` in the running summary.
+    fn record(&mut self, ev: &EventRecord) {
+        self.counts.add(&ev.kind);
+        self.advance(ev.seq, ev.recv_unix_ns);
+    }
+
+    /// Account for a normalized event in the running summary.
+    fn record_normalized(&mut self, ev: &NormalizedEvent) {
+        self.counts.add_normalized(&ev.kind);
+        self.advance(ev.seq, ev.recv_unix_ns);
+    }
+
+    fn advance(&mut self, seq: u64, recv_unix_ns: u128) {
+        self.final_seq = Some(seq);
+        self.first_recv_unix_ns.get_or_insert(recv_unix_ns);
+        self.last_recv_unix_ns = Some(recv_unix_ns);
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RecordFrame {
     Header(FileHeader),
     Event(EventRecord),
+    Checkpoint(BookCheckpoint),
+    Gap(GapMarker),
+    Trailer(Trailer),
+    /// Instrument added to the table after the header.
+    Instrument(Instrument),
+    /// The recorder was restarted and continued the file here.
+    Resumed(ResumeMarker),
+    /// Decoded event of a normalized file (see [`crate::normalize`]).
+    Normalized(NormalizedEvent),
+}
+
+/// Errors produced while reading or writing capture files.
+///
+/// `frame` is the zero-based index of the frame being read (the header is
+/// frame 0) and `offset` is the byte offset where that frame starts.
+#[derive(Debug)]
+pub enum CaptureError {
+    /// Underlying I/O failure.
+    Io(std::io::Error),
+    /// The file is empty or its first frame is not a [`RecordFrame::Header`].
+    MissingHeader,
+    /// The file ends in the middle of a frame (e.g. recorder killed mid-write).
+    Truncated { frame: u64, offset: u64, needed: u64, available: u64 },
+    /// Frame length above [`MAX_FRAME_LEN`] (usually a corrupted prefix).
+    Oversized { frame: u64, offset: u64, len: u64 },
+    /// Payload CRC32 does not match the value stored in the frame.
+    CrcMismatch { frame: u64, offset: u64, expected: u32, actual: u32 },
+    /// Payload passed the CRC check but is not a valid bincode [`RecordFrame`].
+    Decode { frame: u64, offset: u64, source: bincode::Error },
+    /// Payload passed the CRC check but is not a valid fixed-layout event.
+    Layout { frame: u64, offset: u64, reason: &'static str },
+    /// Payload passed the CRC check but could not be decompressed.
+    Decompress { frame: u64, offset: u64, source: std::io::Error },
+    /// A frame could not be serialized.
+    Encode(bincode::Error),
+    /// A [`RecordFrame::Header`] was written after the start of the file.
+    MisplacedHeader { frame: u64 },
+    /// A frame follows the [`RecordFrame::Trailer`], or a trailer was passed
+    /// to [`CaptureWriter::write_frame`].
+    MisplacedTrailer { frame: u64 },
+    /// The trailer's whole-file CRC32 does not match the bytes before it.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The frame's hash-chain digest does not follow from the previous one:
+    /// this frame, or the one before it, was changed after recording.
+    ChainMismatch { frame: u64, offset: u64 },
+    /// The header declares a schema version this build cannot decode.
+    UnsupportedVersion { version: u16 },
+    /// A part of a rotated set does not continue the previous one.
+    BrokenChain { path: std::path::PathBuf, expected_previous: String },
+}
+
+impl std::fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureError::Io(e) => write!(f, "i/o error: {e}"),
+            CaptureError::MissingHeader => write!(f, "capture does not start with a header frame"),
+            CaptureError::Truncated { frame, offset, needed, available } => write!(
+                f,
+                "truncated frame {frame} at offset {offset}: needed {needed} bytes, {available} available"
+            ),
+            CaptureError::Oversized { frame, offset, len } => write!(
+                f,
+                "frame {frame} at offset {offset} declares {len} bytes (limit {MAX_FRAME_LEN})"
+            ),
+            CaptureError::CrcMismatch { frame, offset, expected, actual } => write!(
+                f,
+                "CRC mismatch at frame {frame} (offset {offset}): file={expected:#x}, calc={actual:#x}"
+            ),
+            CaptureError::Decode { frame, offset, source } => {
+                write!(f, "bincode decode failed at frame {frame} (offset {offset}): {source}")
+            }
+            CaptureError::Layout { frame, offset, reason } => {
+                write!(f, "fixed-layout event decode failed at frame {frame} (offset {offset}): {reason}")
+            }
+            CaptureError::Decompress { frame, offset, source } => {
+                write!(f, "decompression failed at frame {frame} (offset {offset}): {source}")
+            }
+            CaptureError::Encode(e) => write!(f, "bincode encode failed: {e}"),
+            CaptureError::MisplacedHeader { frame } => {
+                write!(f, "header frame written at frame {frame}; only frame 0 may be a header")
+            }
+            CaptureError::MisplacedTrailer { frame } => {
+                write!(f, "frame {frame} is misplaced: the trailer must be the last frame")
+            }
+            CaptureError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "whole-file checksum mismatch: trailer={expected:#x}, calc={actual:#x}"
+            ),
+            CaptureError::ChainMismatch { frame, offset } => write!(
+                f,
+                "hash chain broken at frame {frame} (offset {offset})"
+            ),
+            CaptureError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported capture format version {version} (this build reads 1..={FORMAT_VERSION})"
+            ),
+            CaptureError::BrokenChain { path, expected_previous } => write!(
+                f,
+                "{} does not continue {expected_previous}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CaptureError::Io(e) | CaptureError::Decompress { source: e, .. } => Some(e),
+            CaptureError::Decode { source, .. } | CaptureError::Encode(source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(e: std::io::Error) -> Self { CaptureError::Io(e) }
+}
+
+/// Size of the `[len:u32][crc32:u32]` prefix of every frame.
+pub const FRAME_PREFIX_LEN: u64 = 8;
+
+/// Largest payload a frame may declare. Real frames stay far below this;
+/// the limit keeps a corrupted length from triggering a huge allocation.
+pub const MAX_FRAME_LEN: u32 = 64 << 20;
+
+/// Streaming reader over a capture file.
+///
+/// The header frame is read and validated by [`CaptureReader::new`] and is
+/// available through [`CaptureReader::header`]. Iterating yields the remaining
+/// frames in file order. After the first error the iterator is fused.
+///
+/// Files written with an older [`FORMAT_VERSION`] are decoded with their
+/// frozen schema and converted, so callers always see current types.
+pub struct CaptureReader<R> {
+    inner: R,
+    header: FileHeader,
+    /// Schema version declared by the file.
+    version: u16,
+    codec: Codec,
+    encoding: Encoding,
+    /// Payload of the last frame read, reused across reads.
+    buf: Vec<u8>,
+    /// Index of the next frame to be read.
+    frame: u64,
+    /// Byte offset of the next frame to be read.
+    offset: u64,
+    /// CRC32 of the bytes read so far, to check the trailer. `None` after a
+    /// seek, when earlier bytes were skipped.
+    file_crc: Option<crc32fast::Hasher>,
+    /// `true` if frames after the header carry hash-chain digests.
+    chained: bool,
+    /// Digest of the last frame read. `None` after a seek, until the next
+    /// frame supplies one.
+    chain: Option<[u8; DIGEST_LEN]>,
+    trailer: Option<Trailer>,
+    done: bool,
+}
+
+impl CaptureReader<std::io::BufReader<std::fs::File>> {
+    /// Open a capture file with a buffered reader.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, CaptureError> {
+        let file = std::fs::File::open(path)?;
+        Self::new(std::io::BufReader::with_capacity(1 << 20, file))
+    }
+}
+
+impl<R: std::io::Read> CaptureReader<R> {
+    /// Wrap `inner` and read the leading header frame.
+    pub fn new(mut inner: R) -> Result<Self, CaptureError> {
+        let Some((prefix, payload)) = read_payload_at(&mut inner, 0, 0)? else {
+            return Err(CaptureError::MissingHeader);
+        };
+        let mut file_crc = crc32fast::Hasher::new();
+        file_crc.update(&prefix);
+        file_crc.update(&payload);
+        let len = FRAME_PREFIX_LEN + payload.len() as u64;
+        let version = peek_header_version(&payload).ok_or(CaptureError::MissingHeader)?;
+        let header = match decode_frame(version, &payload, 0, 0)? {
+            RecordFrame::Header(h) => h,
+            _ => return Err(CaptureError::MissingHeader),
+        };
+        let (codec, encoding, chained) = (header.codec(), header.encoding(), header.hash_chain());
+        let chain = chained.then(|| chain::seed(&payload));
+        Ok(CaptureReader {
+            inner, header, version, codec, encoding, buf: Vec::new(),
+            frame: 1, offset: len, file_crc: Some(file_crc), chained, chain, trailer: None, done: false,
+        })
+    }
+
+    /// File header (first frame), converted to the current schema.
+    /// `header().version` still reports the version stored in the file.
+    pub fn header(&self) -> &FileHeader { &self.header }
+
+    /// Schema version declared by the file.
+    pub fn version(&self) -> u16 { self.version }
+
+    /// Number of frames read so far, including the header.
+    pub fn frames_read(&self) -> u64 { self.frame }
+
+    /// Byte offset of the next frame.
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// Trailer of the file, once it has been read. A file that reaches EOF
+    /// without one was not closed cleanly.
+    pub fn trailer(&self) -> Option<&Trailer> { self.trailer.as_ref() }
+
+    /// Hash-chain digest of the last frame read, in files with
+    /// [`HeaderExtension::HashChain`]. Once the whole file has been read this
+    /// is its final digest, covering every frame including the trailer.
+    pub fn chain_digest(&self) -> Option<[u8; DIGEST_LEN]> { self.chain }
+
+    /// Consume the reader and return the wrapped source.
+    pub fn into_inner(self) -> R { self.inner }
+
+    /// Read the next frame. Returns `Ok(None)` on a clean end of file
+    /// (EOF exactly at a frame boundary).
+    ///
+    /// When the file was read from the start, the trailer's whole-file CRC32
+    /// is checked as it is read. In a hash-chained file each frame's digest
+    /// is checked against the previous one (from the second frame after a
+    /// seek).
+    pub fn read_frame(&mut self) -> Result<Option<RecordFrame>, CaptureError> {
+        Ok(self.read_view()?.map(FrameView::into_frame))
+    }
+
+    /// Like [`Self::read_frame`], but events of an [`Encoding::Fixed`] file
+    /// are returned in place, borrowing the reader's buffer until the next
+    /// read. Uncompressed fixed-layout files are read without allocating per
+    /// event.
+    pub fn read_view(&mut self) -> Result<Option<FrameView<'_>>, CaptureError> {
+        let (frame, offset) = (self.frame, self.offset);
+        let Some(prefix) = read_payload_into(&mut self.inner, frame, offset, &mut self.buf)? else {
+            return Ok(None);
+        };
+        if self.trailer.is_some() { return Err(CaptureError::MisplacedTrailer { frame }); }
+        let len = FRAME_PREFIX_LEN + self.buf.len() as u64;
+        let crc_before = self.file_crc.as_ref().map(|h| h.clone().finalize());
+        if let Some(h) = &mut self.file_crc {
+            h.update(&prefix);
+            h.update(&self.buf);
+        }
+        let chain_before = self.chain;
+        if self.chained {
+            let digest = chain::split(&mut self.buf).ok_or(CaptureError::ChainMismatch { frame, offset })?;
+            if chain_before.is_some_and(|prev| chain::link(&prev, &self.buf) != digest) {
+                return Err(CaptureError::ChainMismatch { frame, offset });
+            }
+            self.chain = Some(digest);
+        }
+        if self.codec != Codec::None {
+            self.buf = compress::decompress(self.codec, std::mem::take(&mut self.buf))
+                .map_err(|source| CaptureError::Decompress { frame, offset, source })?;
+        }
+        if fixed::is_event(self.encoding, &self.buf) {
+            let view = EventView::parse(&self.buf).map_err(|reason| CaptureError::Layout { frame, offset, reason })?;
+            self.frame += 1;
+            self.offset += len;
+            return Ok(Some(FrameView::Event(view)));
+        }
+        let rec = decode_frame(self.version, &self.buf, frame, offset)?;
+        if let RecordFrame::Trailer(t) = &rec {
+            if let Some(actual) = crc_before
+                && actual != t.file_crc32
+            {
+                return Err(CaptureError::ChecksumMismatch { expected: t.file_crc32, actual });
+            }
+            if chain_before.is_some() && t.chain_digest != chain_before {
+                return Err(CaptureError::ChainMismatch { frame, offset });
+            }
+            self.trailer = Some(t.clone());
+        }
+        self.frame += 1;
+        self.offset += len;
+        Ok(Some(FrameView::Frame(rec)))
+    }
+}
+
+/// Frame returned by [`CaptureReader::read_view`].
+#[derive(Debug)]
+pub enum FrameView<'a> {
+    /// Fixed-layout event, read in place.
+    Event(EventView<'a>),
+    /// Any other frame, including every event of a bincode file.
+    Frame(RecordFrame),
+}
+
+impl FrameView<'_> {
+    /// Owned frame.
+    pub fn into_frame(self) -> RecordFrame {
+        match self {
+            FrameView::Event(view) => RecordFrame::Event(view.to_record()),
+            FrameView::Frame(frame) => frame,
+        }
+    }
+}
+
+impl<R: std::io::Read + std::io::Seek> CaptureReader<R> {
+    /// Position the reader at a known frame boundary (e.g. from a
+    /// [`crate::index::CaptureIndex`] entry). The next read returns frame
+    /// `frame`, which must start at byte `offset`.
+    pub fn seek_to(&mut self, frame: u64, offset: u64) -> Result<(), CaptureError> {
+        self.inner.seek(std::io::SeekFrom::Start(offset))?;
+        self.frame = frame;
+        self.offset = offset;
+        self.file_crc = None;
+        self.chain = None;
+        self.trailer = None;
+        self.done = false;
+        Ok(())
+    }
+}
+
+impl<R: std::io::Read> Iterator for CaptureReader<R> {
+    type Item = Result<RecordFrame, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done { return None; }
+        match self.read_frame() {
+            Ok(Some(f)) => Some(Ok(f)),
+            Ok(None) => { self.done = true; None }
+            Err(e) => { self.done = true; Some(Err(e)) }
+        }
+    }
+}
+
+/// Framed writer for capture files.
+///
+/// [`CaptureWriter::new`] writes the header as frame 0; any later attempt to
+/// write a [`RecordFrame::Header`] is rejected. Frames are buffered by the
+/// wrapped writer, so call [`CaptureWriter::finish`] (or at least
+/// [`CaptureWriter::flush`]) before dropping it.
+///
+/// Frames after the header are compressed with `header.codec()` and, if
+/// `header.hash_chain()`, end with a hash-chain digest.
+pub struct CaptureWriter<W: std::io::Write> {
+    inner: W,
+    header: FileHeader,
+    codec: Codec,
+    encoding: Encoding,
+    /// Number of frames written, including the header.
+    frames: u64,
+    /// Bytes written so far (offset of the next frame).
+    offset: u64,
+    index: Option<crate::index::IndexBuilder>,
+    /// CRC32 of every byte written so far.
+    file_crc: crc32fast::Hasher,
+    /// Digest of the last frame written, in a hash-chained file.
+    chain: Option<[u8; DIGEST_LEN]>,
+    /// Running summary for the trailer (`file_crc32` is filled in at the end).
+    summary: Trailer,
+    closed: bool,
+}
+
+impl CaptureWriter<std::io::BufWriter<std::fs::File>> {
+    /// Create (or truncate) `path`, creating parent directories as needed,
+    /// and write `header` with a 1 MiB write buffer.
+    pub fn create(path: impl AsRef<std::path::Path>, header: FileHeader) -> Result<Self, CaptureError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::File::create(path)?;
+        Self::new(std::io::BufWriter::with_capacity(1 << 20, file), header)
+    }
+}
+
+impl<W: std::io::Write> CaptureWriter<W> {
+    /// Wrap `inner` and write `header` as the first frame. The header's
+    /// `version` is set to [`FORMAT_VERSION`].
+    pub fn new(inner: W, mut header: FileHeader) -> Result<Self, CaptureError> {
+        header.version = FORMAT_VERSION;
+        let (codec, encoding) = (header.codec(), header.encoding());
+        let summary = Trailer {
+            final_seq: None,
+            counts: EventCounts::default(),
+            first_recv_unix_ns: None,
+            last_recv_unix_ns: None,
+            dropped_events: 0,
+            file_crc32: 0,
+            chain_digest: None,
+        };
+        let mut w = CaptureWriter { inner, header, codec, encoding, frames: 0, offset: 0, index: None, file_crc: crc32fast::Hasher::new(), chain: None, summary, closed: false };
+        let payload = bincode::serialize(&RecordFrame::Header(w.header.clone())).map_err(CaptureError::Encode)?;
+        w.write_payload(&payload)?;
+        if w.header.hash_chain() { w.chain = Some(chain::seed(&payload)); }
+        Ok(w)
+    }
+
+    /// File header written as frame 0.
+    pub fn header(&self) -> &FileHeader { &self.header }
+
+    /// Number of frames written so far, including the header.
+    pub fn frames_written(&self) -> u64 { self.frames }
+
+    /// Bytes written so far; also the offset of the next frame.
+    pub fn bytes_written(&self) -> u64 { self.offset }
+
+    /// Build a seek index while writing, with a regular entry every `every`
+    /// frames (see [`crate::index`]). Retrieve it with [`Self::take_index`].
+    pub fn enable_index(&mut self, every: u32) {
+        self.index = Some(crate::index::IndexBuilder::new(every));
+    }
+
+    /// Index of the frames written so far, if enabled. Indexing stops after
+    /// this call.
+    pub fn take_index(&mut self) -> Option<crate::index::CaptureIndex> {
+        self.index.take().map(|b| b.finish(self.offset))
+    }
+
+    /// Serialize and append one frame. Trailers are written with
+    /// [`Self::write_trailer`] instead.
+    pub fn write_frame(&mut self, frame: &RecordFrame) -> Result<(), CaptureError> {
+        match frame {
+            RecordFrame::Header(_) => return Err(CaptureError::MisplacedHeader { frame: self.frames }),
+            RecordFrame::Trailer(_) => return Err(CaptureError::MisplacedTrailer { frame: self.frames }),
+            _ if self.closed => return Err(CaptureError::MisplacedTrailer { frame: self.frames }),
+            RecordFrame::Event(ev) => self.summary.record(ev),
+            RecordFrame::Normalized(ev) => self.summary.record_normalized(ev),
+            _ => {}
+        }
+        if let Some(b) = &mut self.index { b.observe(self.frames, self.offset, frame); }
+        let payload = match frame {
+            RecordFrame::Event(ev) if self.encoding == Encoding::Fixed => {
+                let mut payload = Vec::new();
+                fixed::encode_event(ev, &mut payload);
+                payload
+            }
+            _ => bincode::serialize(frame).map_err(CaptureError::Encode)?,
+        };
+        self.write_stored(&payload)
+    }
+
+    /// Append one event frame.
+    pub fn write_event(&mut self, ev: EventRecord) -> Result<(), CaptureError> {
+        self.write_frame(&RecordFrame::Event(ev))
+    }
+
+    /// Set the number of events dropped before reaching the writer, to be
+    /// reported in the trailer.
+    pub fn set_dropped_events(&mut self, n: u64) { self.summary.dropped_events = n; }
+
+    /// Append the trailer summarizing the frames written so far. It must be
+    /// the last frame: later writes fail with [`CaptureError::MisplacedTrailer`].
+    pub fn write_trailer(&mut self) -> Result<Trailer, CaptureError> {
+        if self.closed { return Err(CaptureError::MisplacedTrailer { frame: self.frames }); }
+        let mut trailer = self.summary.clone();
+        trailer.file_crc32 = self.file_crc.clone().finalize();
+        trailer.chain_digest = self.chain;
+        let payload = bincode::serialize(&RecordFrame::Trailer(trailer.clone())).map_err(CaptureError::Encode)?;
+        self.write_stored(&payload)?;
+        self.closed = true;
+        Ok(trailer)
+    }
+
+    /// Flush buffered frames to the underlying writer.
+    pub fn flush(&mut self) -> Result<(), CaptureError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// Flush and return the wrapped writer.
+    pub fn finish(mut self) -> Result<W, CaptureError> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Compress a frame payload, append its chain digest and write it.
+    fn write_stored(&mut self, payload: &[u8]) -> Result<(), CaptureError> {
+        let stored = compress::compress(self.codec, payload)?;
+        let Some(prev) = &self.chain else { return self.write_payload(&stored) };
+        let digest = chain::link(prev, &stored);
+        let mut stored = stored.into_owned();
+        stored.extend_from_slice(&digest);
+        self.write_payload(&stored)?;
+        self.chain = Some(digest);
+        Ok(())
+    }
+
+    fn write_payload(&mut self, payload: &[u8]) -> Result<(), CaptureError> {
+        if payload.len() > MAX_FRAME_LEN as usize {
+            return Err(CaptureError::Oversized { frame: self.frames, offset: self.offset, len: payload.len() as u64 });
+        }
+        let len = payload.len() as u32;
+        let crc = crc32fast::hash(payload);
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(&crc.to_le_bytes())?;
+        self.inner.write_all(payload)?;
+        self.file_crc.update(&len.to_le_bytes());
+        self.file_crc.update(&crc.to_le_bytes());
+        self.file_crc.update(payload);
+        self.frames += 1;
+        self.offset += FRAME_PREFIX_LEN + payload.len() as u64;
+        Ok(())
+    }
+}
+
+/// Raw `[len:u32][crc32:u32]` frame prefix.
+type FramePrefix = [u8; FRAME_PREFIX_LEN as usize];
+
+/// Read one frame starting at `offset` and check its CRC. Returns the
+/// prefix and the payload, or `None` on a clean EOF.
+fn read_payload_at<R: std::io::Read>(r: &mut R, frame: u64, offset: u64) -> Result<Option<(FramePrefix, Vec<u8>)>, CaptureError> {
+    let mut payload = Vec::new();
+    Ok(read_payload_into(r, frame, offset, &mut payload)?.map(|prefix| (prefix, payload)))
+}
+
+/// [`read_payload_at`] into a reused buffer; returns the prefix.
+fn read_payload_into<R: std::io::Read>(r: &mut R, frame: u64, offset: u64, payload: &mut Vec<u8>) -> Result<Option<FramePrefix>, CaptureError> {
+    let mut prefix: FramePrefix = [0; FRAME_PREFIX_LEN as usize];
+    let got = read_full(r, &mut prefix)?;
+    if got == 0 { return Ok(None); }
+    if got < prefix.len() {
+        return Err(CaptureError::Truncated { frame, offset, needed: FRAME_PREFIX_LEN, available: got as u64 });
+    }
+    let len = u32::from_le_bytes(prefix[0..4].try_into().unwrap());
+    if len > MAX_FRAME_LEN {
+        return Err(CaptureError::Oversized { frame, offset, len: len as u64 });
+    }
+    let len = len as usize;
+    let crc_on_file = u32::from_le_bytes(prefix[4..8].try_into().unwrap());
+    payload.clear();
+    payload.resize(len, 0);
+    let got = read_full(r, payload)?;
+    if got < len {
+        return Err(CaptureError::Truncated {
+            frame,
+            offset,
+            needed: FRAME_PREFIX_LEN + len as u64,
+            available: FRAME_PREFIX_LEN + got as u64,
+        });
+    }
+    let crc_calc = crc32fast::hash(payload);
+    if crc_calc != crc_on_file {
+        return Err(CaptureError::CrcMismatch { frame, offset, expected: crc_on_file, actual: crc_calc });
+    }
+    Ok(Some(prefix))
+}
+
+/// Read the schema version from a header frame payload without decoding the
+/// rest. Every version encodes `Header` as variant 0 with `version: u16` as
+/// its first field: `[tag:u32=0][version:u16]...`.
+fn peek_header_version(payload: &[u8]) -> Option<u16> {
+    if payload.len() < 6 || payload[0..4] != [0, 0, 0, 0] { return None; }
+    Some(u16::from_le_bytes([payload[4], payload[5]]))
+}
+
+/// Decode a frame payload written with schema `version` into current types.
+fn decode_frame(version: u16, payload: &[u8], frame: u64, offset: u64) -> Result<RecordFrame, CaptureError> {
+    let decode_err = |source| CaptureError::Decode { frame, offset, source };
+    match version {
+        1 => bincode::deserialize::<v1::RecordFrame>(payload).map(RecordFrame::from).map_err(decode_err),
+        2 => bincode::deserialize::<v2::RecordFrame>(payload).map(RecordFrame::from).map_err(decode_err),
+        3 => bincode::deserialize::<v3::RecordFrame>(payload).map(RecordFrame::from).map_err(decode_err),
+        4 => bincode::deserialize::<v4::RecordFrame>(payload).map(RecordFrame::from).map_err(decode_err),
+        FORMAT_VERSION => bincode::deserialize::<RecordFrame>(payload).map_err(decode_err),
+        _ => Err(CaptureError::UnsupportedVersion { version }),
+    }
+}
+
+/// Decode a frame payload of a file using `encoding` into an owned frame.
+fn decode_owned(version: u16, encoding: Encoding, payload: &[u8], frame: u64, offset: u64) -> Result<RecordFrame, CaptureError> {
+    if fixed::is_event(encoding, payload) {
+        return EventView::parse(payload)
+            .map(|view| RecordFrame::Event(view.to_record()))
+            .map_err(|reason| CaptureError::Layout { frame, offset, reason });
+    }
+    decode_frame(version, payload, frame, offset)
+}
+
+/// Rewrite a capture of any supported version into [`FORMAT_VERSION`].
+///
+/// The new header keeps the original metadata and records the source version
+/// in a [`HeaderExtension::UpgradedFrom`] entry. Returns the flushed output
+/// writer and the number of frames copied after the header.
+pub fn upgrade<R: std::io::Read, W: std::io::Write>(reader: CaptureReader<R>, out: W) -> Result<(W, u64), CaptureError> {
+    let mut header = reader.header().clone();
+    header.extensions.push(HeaderExtension::UpgradedFrom {
+        version: reader.version(),
+        upgraded_unix_ns: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    });
+    rewrite(reader, out, header)
+}
+
+/// Copy every frame after the header of `reader` into a new capture that
+/// starts with `header` (e.g. to change its codec). A trailer in the source
+/// is recomputed for the new file. Returns the flushed output writer and the
+/// number of frames copied between header and trailer.
+pub fn rewrite<R: std::io::Read, W: std::io::Write>(reader: CaptureReader<R>, out: W, header: FileHeader) -> Result<(W, u64), CaptureError> {
+    let mut w = CaptureWriter::new(out, header)?;
+    let mut copied = 0u64;
+    for frame in reader {
+        match frame? {
+            RecordFrame::Trailer(t) => {
+                w.set_dropped_events(t.dropped_events);
+                w.write_trailer()?;
+            }
+            frame => {
+                w.write_frame(&frame)?;
+                copied += 1;
+            }
+        }
+    }
+    Ok((w.finish()?, copied))
+}
+
+/// Fill `buf` from `r`, stopping early only at EOF. Returns bytes read.
+fn read_full<R: std::io::Read>(r: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// Capture fixtures shared by the crate's tests.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    /// Current-version header for `ticker`, created at time 0.
+    pub(crate) fn header(ticker: &str) -> FileHeader {
+        FileHeader { version: FORMAT_VERSION, created_unix_ns: 0, ticker: ticker.into(), exchange: "X".into(), server_clock_offset_ms: 0, extensions: Vec::new() }
+    }
+
+    /// Offer Book V2 entry action with every field present, qty 1 and agent 1.
+    pub(crate) fn offer_book(n_action: i32, n_side: i32, n_position: i32, n_offer_id: i64, d_price: f64) -> EventKind {
+        EventKind::OfferBookV2 {
+            n_action, n_position, n_side, n_qtd: 1, n_agent: 1, n_offer_id, d_price,
+            has_price: true, has_qtd: true, has_date: false, has_offer_id: true, has_agent: true,
+            date_str: None, array_sell: None, array_buy: None,
+        }
+    }
+
+    /// FullBook packet with the given blocks (see `book::testing::block`).
+    pub(crate) fn full_book(array_buy: Option<RawArrayBlock>, array_sell: Option<RawArrayBlock>) -> EventKind {
+        EventKind::OfferBookV2 {
+            n_action: 4, n_position: 0, n_side: 0, n_qtd: 0, n_agent: 0, n_offer_id: 0, d_price: 0.0,
+            has_price: false, has_qtd: false, has_date: false, has_offer_id: false, has_agent: false,
+            date_str: None, array_sell, array_buy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> FileHeader {
+        FileHeader { created_unix_ns: 7, server_clock_offset_ms: -3, ..testing::header("WINFUT") }
+    }
+
+    fn state(seq: u64) -> EventRecord {
+        EventRecord { seq, instrument: 0, recv_unix_ns: seq as u128, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 0, value: seq as i32 } }
+    }
+
+    #[test]
+    fn writer_reader_roundtrip_in_memory() {
+        let mut w = CaptureWriter::new(Vec::new(), header()).unwrap();
+        for seq in 0..3 { w.write_event(state(seq)).unwrap(); }
+        assert_eq!(w.frames_written(), 4);
+        let written = w.bytes_written();
+        let bytes = w.finish().unwrap();
+        assert_eq!(bytes.len() as u64, written);
+
+        let mut r = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(r.header().ticker, "WINFUT");
+        let seqs: Vec<u64> = r.by_ref().map(|f| match f.unwrap() {
+            RecordFrame::Event(ev) => ev.seq,
+            other => panic!("unexpected frame {other:?}"),
+        }).collect();
+        assert_eq!(seqs, vec![0, 1, 2]);
+        assert_eq!(r.frames_read(), 4);
+        assert_eq!(r.offset(), written);
+    }
+
+    fn frame_bytes(out: &mut Vec<u8>, payload: &[u8]) {
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        out.extend_from_slice(payload);
+    }
+
+    fn v1_capture() -> Vec<u8> {
+        let h = v1::FileHeader { version: 1, created_unix_ns: 7, ticker: "WINFUT".into(), exchange: "F".into(), server_clock_offset_ms: -3 };
+        let mut bytes = Vec::new();
+        frame_bytes(&mut bytes, &bincode::serialize(&v1::RecordFrame::Header(h)).unwrap());
+        frame_bytes(&mut bytes, &bincode::serialize(&v1::RecordFrame::Event(v1_state(5))).unwrap());
+        bytes
+    }
+
+    fn v1_state(seq: u64) -> v1::EventRecord {
+        v1::EventRecord { seq, recv_unix_ns: 0, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 0, value: 0 } }
+    }
+
+    #[test]
+    fn reads_v1_files() {
+        let bytes = v1_capture();
+        let mut r = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(r.version(), 1);
+        assert_eq!(r.header().server_clock_offset_ms, -3);
+        assert!(r.header().extensions.is_empty());
+        match r.next() {
+            Some(Ok(RecordFrame::Event(ev))) => assert_eq!(ev.seq, 5),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(r.next().is_none());
+    }
+
+    #[test]
+    fn reads_v2_files() {
+        let mut h = header();
+        h.version = 2;
+        let mut bytes = Vec::new();
+        frame_bytes(&mut bytes, &bincode::serialize(&v2::RecordFrame::Header(h)).unwrap());
+        frame_bytes(&mut bytes, &bincode::serialize(&v2::RecordFrame::Event(v1_state(3))).unwrap());
+        let cp = v2::BookCheckpoint { seq: 3, recv_unix_ns: 0, hash: 0, book: crate::book::Book::default() };
+        frame_bytes(&mut bytes, &bincode::serialize(&v2::RecordFrame::Checkpoint(cp)).unwrap());
+        let t = v4::Trailer { final_seq: Some(3), counts: EventCounts::default(), first_recv_unix_ns: None, last_recv_unix_ns: None, dropped_events: 1, file_crc32: crc32fast::hash(&bytes) };
+        frame_bytes(&mut bytes, &bincode::serialize(&v2::RecordFrame::Trailer(t)).unwrap());
+        let frames: Vec<_> = CaptureReader::new(bytes.as_slice()).unwrap().map(Result::unwrap).collect();
+        assert!(matches!(frames[..], [
+            RecordFrame::Event(EventRecord { seq: 3, instrument: 0, .. }),
+            RecordFrame::Checkpoint(BookCheckpoint { seq: 3, instrument: 0, .. }),
+            RecordFrame::Trailer(Trailer { dropped_events: 1, chain_digest: None, .. }),
+        ]));
+    }
+
+    #[test]
+    fn reads_v3_gaps_as_covering_every_instrument() {
+        let mut h = header();
+        h.version = 3;
+        let mut bytes = Vec::new();
+        frame_bytes(&mut bytes, &bincode::serialize(&v3::RecordFrame::Header(h)).unwrap());
+        let gap = v3::GapMarker { offset: 1, skipped_bytes: 2, last_seq: Some(3), next_seq: None };
+        frame_bytes(&mut bytes, &bincode::serialize(&v3::RecordFrame::Gap(gap)).unwrap());
+        let frames: Vec<_> = CaptureReader::new(bytes.as_slice()).unwrap().map(Result::unwrap).collect();
+        assert!(matches!(&frames[..], [RecordFrame::Gap(GapMarker { skipped_bytes: 2, scope: GapScope::All, .. })]));
+    }
+
+    #[test]
+    fn upgrade_rewrites_v1_to_current() {
+        let bytes = v1_capture();
+        let (out, copied) = upgrade(CaptureReader::new(bytes.as_slice()).unwrap(), Vec::new()).unwrap();
+        assert_eq!(copied, 1);
+        let r = CaptureReader::new(out.as_slice()).unwrap();
+        assert_eq!(r.version(), FORMAT_VERSION);
+        assert!(matches!(r.header().extensions[..], [HeaderExtension::UpgradedFrom { version: 1, .. }]));
+        assert_eq!(r.count(), 1);
+    }
+
+    #[test]
+    fn rejects_future_versions() {
+        let mut h = header();
+        h.version = FORMAT_VERSION + 1;
+        let mut bytes = Vec::new();
+        frame_bytes(&mut bytes, &bincode::serialize(&RecordFrame::Header(h)).unwrap());
+        match CaptureReader::new(bytes.as_slice()) {
+            Err(CaptureError::UnsupportedVersion { version }) => assert_eq!(version, FORMAT_VERSION + 1),
+            other => panic!("expected unsupported version, got {:?}", other.err()),
+        }
+    }
+
+    fn full_book(seq: u64) -> EventRecord {
+        // Repetitive raw bytes, like consecutive FullBook packets
+        let bytes: Vec<u8> = (0..4096u32).map(|i| (i % 37) as u8).collect();
+        EventRecord {
+            seq,
+            instrument: 0,
+            recv_unix_ns: 0,
+            recv_mono_ns_from_start: 0,
+            kind: EventKind::OfferBookV2 {
+                n_action: 4, n_position: 0, n_side: 0, n_qtd: 0, n_agent: 0, n_offer_id: 0, d_price: 0.0,
+                has_price: false, has_qtd: false, has_date: false, has_offer_id: false, has_agent: false,
+                date_str: None,
+                array_sell: Some(RawArrayBlock { size: 0, bytes: bytes.clone() }),
+                array_buy: Some(RawArrayBlock { size: 0, bytes }),
+            },
+        }
+    }
+
+    #[test]
+    fn compressed_roundtrip_for_each_codec() {
+        let write = |codec: Codec| {
+            let mut h = header();
+            h.set_codec(codec);
+            let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+            w.write_event(full_book(0)).unwrap();
+            w.write_event(state(1)).unwrap(); // small: stored as-is
+            w.finish().unwrap()
+        };
+        let plain = write(Codec::None);
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            let bytes = write(codec);
+            assert!(bytes.len() < plain.len() / 2, "{codec} did not shrink the capture");
+            let mut r = CaptureReader::new(bytes.as_slice()).unwrap();
+            assert_eq!(r.header().codec(), codec);
+            let frames: Vec<RecordFrame> = r.by_ref().map(Result::unwrap).collect();
+            assert_eq!(bincode::serialize(&frames).unwrap(), bincode::serialize(&vec![
+                RecordFrame::Event(full_book(0)),
+                RecordFrame::Event(state(1)),
+            ]).unwrap());
+        }
+    }
+
+    #[test]
+    fn decompression_is_capped_at_max_frame_len() {
+        let mut lz4 = vec![1u8];
+        lz4.extend_from_slice(&u32::MAX.to_le_bytes());
+        lz4.extend_from_slice(&[0; 16]);
+        assert!(compress::decompress(Codec::Lz4, lz4).is_err());
+        let big = vec![0u8; MAX_FRAME_LEN as usize + 1];
+        let mut zstd = vec![1u8];
+        zstd.extend_from_slice(&zstd::bulk::compress(&big, 3).unwrap());
+        assert!(compress::decompress(Codec::Zstd, zstd).is_err());
+    }
+
+    #[test]
+    fn fixed_encoding_reads_in_place_and_converts_back() {
+        let events = || vec![RecordFrame::Event(full_book(0)), RecordFrame::Event(state(1))];
+        for codec in [Codec::None, Codec::Zstd] {
+            let mut h = header();
+            h.set_codec(codec);
+            h.set_encoding(Encoding::Fixed);
+            let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+            for f in events() { w.write_frame(&f).unwrap(); }
+            w.write_trailer().unwrap();
+            let fixed = w.finish().unwrap();
+
+            let mut r = CaptureReader::new(fixed.as_slice()).unwrap();
+            assert_eq!(r.header().encoding(), Encoding::Fixed);
+            match r.read_view().unwrap() {
+                Some(FrameView::Event(view)) => assert_eq!(view.kind, KindView::from(&full_book(0).kind)),
+                other => panic!("unexpected {other:?}"),
+            }
+            assert!(matches!(r.read_view().unwrap(), Some(FrameView::Event(EventView { seq: 1, .. }))));
+            assert!(matches!(r.read_view().unwrap(), Some(FrameView::Frame(RecordFrame::Trailer(_)))));
+
+            let mut h = header();
+            h.set_codec(codec);
+            let (bincode_file, copied) = rewrite(CaptureReader::new(fixed.as_slice()).unwrap(), Vec::new(), h).unwrap();
+            assert_eq!(copied, 2);
+            let frames: Vec<RecordFrame> = CaptureReader::new(bincode_file.as_slice()).unwrap().map(Result::unwrap).take(2).collect();
+            assert_eq!(bincode::serialize(&frames).unwrap(), bincode::serialize(&events()).unwrap());
+        }
+    }
+
+    #[test]
+    fn trailer_summarizes_and_checks_the_file() {
+        let mut w = CaptureWriter::new(Vec::new(), header()).unwrap();
+        for seq in 3..6 { w.write_event(state(seq)).unwrap(); }
+        w.set_dropped_events(2);
+        let trailer = w.write_trailer().unwrap();
+        assert!(matches!(w.write_event(state(6)), Err(CaptureError::MisplacedTrailer { frame: 5 })));
+        let bytes = w.finish().unwrap();
+        assert_eq!((trailer.final_seq, trailer.counts.state, trailer.dropped_events), (Some(5), 3, 2));
+        assert_eq!((trailer.first_recv_unix_ns, trailer.last_recv_unix_ns), (Some(3), Some(5)));
+
+        let mut r = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(r.by_ref().map(Result::unwrap).count(), 4);
+        assert_eq!(r.trailer(), Some(&trailer));
+
+        // Flip a byte inside an event's bincode payload and fix up its frame
+        // CRC: only the whole-file checksum can notice
+        let mut bad = bytes.clone();
+        let hdr_len = CaptureReader::new(bytes.as_slice()).unwrap().offset() as usize;
+        let len = u32::from_le_bytes(bad[hdr_len..hdr_len + 4].try_into().unwrap()) as usize;
+        let payload = hdr_len + 8..hdr_len + 8 + len;
+        bad[payload.end - 1] ^= 1;
+        let crc = crc32fast::hash(&bad[payload.clone()]);
+        bad[hdr_len + 4..hdr_len + 8].copy_from_slice(&crc.to_le_bytes());
+        let err = CaptureReader::new(bad.as_slice()).unwrap().find_map(Result::err).unwrap();
+        assert!(matches!(err, CaptureError::ChecksumMismatch { .. }), "{err}");
+    }
+
+    #[test]
+    fn hash_chain_pinpoints_the_edited_frame() {
+        let mut h = header();
+        h.set_hash_chain(true);
+        let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+        for seq in 3..7 { w.write_event(state(seq)).unwrap(); }
+        let trailer = w.write_trailer().unwrap();
+        let bytes = w.finish().unwrap();
+
+        let mut r = CaptureReader::new(bytes.as_slice()).unwrap();
+        let mut starts = vec![r.offset() as usize];
+        while r.read_frame().unwrap().is_some() { starts.push(r.offset() as usize); }
+        assert_eq!(starts.len(), 6);
+        assert_eq!(r.trailer(), Some(&trailer));
+        assert!(trailer.chain_digest.is_some() && r.chain_digest() != trailer.chain_digest);
+
+        // Change the seq of the event in frame 3 and fix up its CRC
+        let fix_crc = |b: &mut Vec<u8>, at: usize, end: usize| {
+            let crc = crc32fast::hash(&b[at + 8..end]);
+            b[at + 4..at + 8].copy_from_slice(&crc.to_le_bytes());
+        };
+        let (at, end) = (starts[2], starts[3]);
+        let mut bad = bytes.clone();
+        bad[at + 8 + 4] ^= 1;
+        fix_crc(&mut bad, at, end);
+        let err = CaptureReader::new(bad.as_slice()).unwrap().find_map(Result::err).unwrap();
+        assert!(matches!(err, CaptureError::ChainMismatch { frame: 3, .. }), "{err}");
+
+        // Recomputing that frame's digest only moves the break to the next one
+        let prev: [u8; DIGEST_LEN] = bad[at - DIGEST_LEN..at].try_into().unwrap();
+        let digest = chain::link(&prev, &bad[at + 8..end - DIGEST_LEN]);
+        bad[end - DIGEST_LEN..end].copy_from_slice(&digest);
+        fix_crc(&mut bad, at, end);
+        let err = CaptureReader::new(bad.as_slice()).unwrap().find_map(Result::err).unwrap();
+        assert!(matches!(err, CaptureError::ChainMismatch { frame: 4, .. }), "{err}");
+    }
+
+    #[test]
+    fn provenance_roundtrips_in_the_header_and_resume_markers() {
+        let p = Provenance {
+            recorder_version: "0.1.0".into(),
+            dll_path: "dll/ProfitDLL.dll".into(),
+            dll_sha256: Some([7; DIGEST_LEN]),
+            hostname: "rec-01".into(),
+            options: "Args { password: \"<redacted>\" }".into(),
+            utc_offset_secs: Some(-10_800),
+            server_clock: ServerClock { result: 0, date: 45_904.5, year: 2025, month: 9, day: 4, hour: 12, ..Default::default() },
+            channel_capacity: 8192,
+        };
+        let mut h = header();
+        h.extensions.push(HeaderExtension::Provenance(p.clone()));
+        let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+        // A restarted recorder records its own provenance in the resume marker
+        let restarted = Provenance { recorder_version: "0.2.0".into(), ..p.clone() };
+        let extensions = vec![HeaderExtension::Provenance(restarted.clone())];
+        let marker = ResumeMarker { resumed_unix_ns: 9, last_seq: None, truncated_bytes: 0, server_clock_offset_ms: 0, scope: GapScope::All, extensions };
+        w.write_frame(&RecordFrame::Resumed(marker)).unwrap();
+        let bytes = w.finish().unwrap();
+        let mut r = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(r.header().provenance(), Some(&p));
+        assert!(matches!(r.next(), Some(Ok(RecordFrame::Resumed(m))) if m.provenance() == Some(&restarted)));
+        assert_eq!(header().provenance(), None);
+    }
+
+    #[test]
+    fn writer_rejects_second_header() {
+        let mut w = CaptureWriter::new(Vec::new(), header()).unwrap();
+        match w.write_frame(&RecordFrame::Header(header())) {
+            Err(CaptureError::MisplacedHeader { frame }) => assert_eq!(frame, 1),
+            other => panic!("expected misplaced header error, got {other:?}"),
+        }
+        assert_eq!(w.frames_written(), 1);
+    }
 }
@@ -0,0 +1,39 @@
+//! Tamper-evident hash chain over the frames of a capture.
+//!
+//! In a file whose header declares [`HeaderExtension::HashChain`], every
+//! frame after the header stores `[data][digest:32]`, where
+//! `digest = SHA-256(previous digest || data)` and `data` is the payload as
+//! it would be stored without a chain (after compression). The chain starts
+//! from the SHA-256 of the header payload, so editing, inserting, removing
+//! or reordering any frame breaks every later link unless the rest of the
+//! chain is recomputed as well; comparing the final digest with a copy kept
+//! elsewhere catches that too. CRC32 still covers the whole stored payload,
+//! digest included.
+//!
+//! [`HeaderExtension::HashChain`]: super::HeaderExtension::HashChain
+use sha2::{Digest, Sha256};
+
+/// Length of the digest appended to each frame.
+pub const DIGEST_LEN: usize = 32;
+
+/// Chain value before the first frame after the header.
+pub(super) fn seed(header_payload: &[u8]) -> [u8; DIGEST_LEN] {
+    Sha256::digest(header_payload).into()
+}
+
+/// Chain value after a frame storing `data`.
+pub(super) fn link(previous: &[u8; DIGEST_LEN], data: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut h = Sha256::new();
+    h.update(previous);
+    h.update(data);
+    h.finalize().into()
+}
+
+/// Remove the digest from the end of a stored payload and return it, or
+/// `None` if the payload is too short to hold one.
+pub(super) fn split(stored: &mut Vec<u8>) -> Option<[u8; DIGEST_LEN]> {
+    let at = stored.len().checked_sub(DIGEST_LEN)?;
+    let digest = stored[at..].try_into().ok()?;
+    stored.truncate(at);
+    Some(digest)
+}
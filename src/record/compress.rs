@@ -0,0 +1,67 @@
+//! Per-frame payload compression.
+//!
+//! When a capture declares a [`Codec`] other than [`Codec::None`], every
+//! frame after the header stores `[method:u8][data]` instead of the bare
+//! bincode payload. `method` is [`STORED`] for payloads kept as-is (small
+//! frames, or frames the codec could not shrink) and [`COMPRESSED`] for
+//! payloads compressed with the file's codec. CRC32 still covers the bytes
+//! exactly as stored on disk.
+use super::{Codec, MAX_FRAME_LEN};
+use std::borrow::Cow;
+use std::io;
+
+/// Payload stored uncompressed.
+const STORED: u8 = 0;
+/// Payload compressed with the file's codec.
+const COMPRESSED: u8 = 1;
+
+/// Payloads smaller than this are not worth a codec call.
+const MIN_COMPRESS_LEN: usize = 64;
+
+/// zstd level used by the writer (library default).
+const ZSTD_LEVEL: i32 = 3;
+
+/// Wrap a bincode payload for a file using `codec`. Borrowed for
+/// [`Codec::None`], which stores payloads unchanged.
+pub(super) fn compress(codec: Codec, payload: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    if codec == Codec::None { return Ok(Cow::Borrowed(payload)); }
+    if payload.len() >= MIN_COMPRESS_LEN {
+        let packed = match codec {
+            Codec::None => unreachable!(),
+            Codec::Zstd => zstd::bulk::compress(payload, ZSTD_LEVEL)?,
+            Codec::Lz4 => lz4_flex::compress_prepend_size(payload),
+        };
+        if packed.len() < payload.len() {
+            let mut out = Vec::with_capacity(packed.len() + 1);
+            out.push(COMPRESSED);
+            out.extend_from_slice(&packed);
+            return Ok(Cow::Owned(out));
+        }
+    }
+    let mut out = Vec::with_capacity(payload.len() + 1);
+    out.push(STORED);
+    out.extend_from_slice(payload);
+    Ok(Cow::Owned(out))
+}
+
+/// Recover the bincode payload from a stored frame payload. Payloads that
+/// would decompress beyond [`MAX_FRAME_LEN`] are rejected before allocating.
+pub(super) fn decompress(codec: Codec, stored: Vec<u8>) -> io::Result<Vec<u8>> {
+    if codec == Codec::None { return Ok(stored); }
+    match stored.split_first() {
+        Some((&STORED, rest)) => Ok(rest.to_vec()),
+        Some((&COMPRESSED, rest)) => match codec {
+            Codec::None => unreachable!(),
+            Codec::Zstd => zstd::bulk::decompress(rest, MAX_FRAME_LEN as usize),
+            Codec::Lz4 => {
+                let size = rest.first_chunk::<4>().map(|b| u32::from_le_bytes(*b));
+                if size.is_some_and(|n| n > MAX_FRAME_LEN) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame decompresses to more than {MAX_FRAME_LEN} bytes")));
+                }
+                lz4_flex::decompress_size_prepended(rest).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        },
+        Some((m, _)) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown frame method {m}"))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "empty compressed frame")),
+    }
+}
@@ -0,0 +1,337 @@
+//! Fixed-layout event encoding ([`Encoding::Fixed`]).
+//!
+//! In a file declaring [`Encoding::Fixed`], event payloads are hand-laid
+//! little-endian structs instead of bincode, so they can be read in place as
+//! an [`EventView`] borrowing the frame buffer: no `String` per date and no
+//! `Vec<u8>` per array block. Every other frame stays bincode. The first four
+//! bytes are bincode's `RecordFrame::Event` tag, which tells the two apart.
+//!
+//! ```text
+//! [tag:u32=1][kind:u8][seq:u64][instrument:u32][recv_unix_ns:u128][recv_mono_ns:u128][body]
+//!
+//! OfferBookV2  [action:i32][position:i32][side:i32][qtd:i64][agent:i32][offer_id:i64][price:f64]
+//!              [flags:u8][sell_size:u32][buy_size:u32][date_len:u32][sell_len:u32][buy_len:u32]
+//!              [date][sell bytes][buy bytes]
+//! NewTrade     [trade_number:u32][price:f64][volume:f64][qty:i32][buy_agent:i32][sell_agent:i32]
+//!              [trade_type:i32][edit_flag:u8][date_len:u32][date]
+//! HistoryTrade [trade_number:u32][price:f64][volume:f64][qty:i32][buy_agent:i32][sell_agent:i32]
+//!              [trade_type:i32][edit_flag:u8=0][date_len:u32][date]
+//! State        [state_type:i32][value:i32]
+//! ```
+//!
+//! Offer book `flags`: bits 0..=4 are `has_price`, `has_qtd`, `has_date`,
+//! `has_offer_id`, `has_agent`; bits 5..=7 mark a present date, sell block and
+//! buy block. Strings are UTF-8. Like the bincode layouts, this one is part of
+//! [`FORMAT_VERSION`](super::FORMAT_VERSION): changing it needs a bump.
+use super::{Encoding, EventKind, EventRecord, RawArrayBlock};
+
+/// Bincode variant tag of `RecordFrame::Event`, shared by fixed-layout events.
+const EVENT_TAG: [u8; 4] = 1u32.to_le_bytes();
+
+const OFFER_BOOK: u8 = 0;
+const NEW_TRADE: u8 = 1;
+const HISTORY_TRADE: u8 = 2;
+const STATE: u8 = 3;
+
+/// `true` if `payload` is an event in a file using `encoding`.
+pub(super) fn is_event(encoding: Encoding, payload: &[u8]) -> bool {
+    encoding == Encoding::Fixed && payload.starts_with(&EVENT_TAG)
+}
+
+/// Append the fixed-layout payload of `ev` to `out`.
+pub(super) fn encode_event(ev: &EventRecord, out: &mut Vec<u8>) {
+    out.extend_from_slice(&EVENT_TAG);
+    out.push(match ev.kind {
+        EventKind::OfferBookV2 { .. } => OFFER_BOOK,
+        EventKind::NewTrade { .. } => NEW_TRADE,
+        EventKind::HistoryTrade { .. } => HISTORY_TRADE,
+        EventKind::State { .. } => STATE,
+    });
+    out.extend_from_slice(&ev.seq.to_le_bytes());
+    out.extend_from_slice(&ev.instrument.to_le_bytes());
+    out.extend_from_slice(&ev.recv_unix_ns.to_le_bytes());
+    out.extend_from_slice(&ev.recv_mono_ns_from_start.to_le_bytes());
+    match &ev.kind {
+        EventKind::OfferBookV2 {
+            n_action, n_position, n_side, n_qtd, n_agent, n_offer_id, d_price,
+            has_price, has_qtd, has_date, has_offer_id, has_agent, date_str, array_sell, array_buy,
+        } => {
+            for v in [n_action, n_position, n_side] { out.extend_from_slice(&v.to_le_bytes()); }
+            out.extend_from_slice(&n_qtd.to_le_bytes());
+            out.extend_from_slice(&n_agent.to_le_bytes());
+            out.extend_from_slice(&n_offer_id.to_le_bytes());
+            out.extend_from_slice(&d_price.to_le_bytes());
+            let bits = [*has_price, *has_qtd, *has_date, *has_offer_id, *has_agent, date_str.is_some(), array_sell.is_some(), array_buy.is_some()];
+            out.push(bits.iter().enumerate().fold(0u8, |f, (i, b)| f | ((*b as u8) << i)));
+            let date = date_str.as_deref().unwrap_or("").as_bytes();
+            let (sell, buy) = (array_sell.as_ref(), array_buy.as_ref());
+            for v in [sell.map_or(0, |b| b.size), buy.map_or(0, |b| b.size)] { out.extend_from_slice(&v.to_le_bytes()); }
+            for bytes in [date, sell.map_or(&[][..], |b| &b.bytes), buy.map_or(&[][..], |b| &b.bytes)] {
+                out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            }
+            out.extend_from_slice(date);
+            if let Some(b) = sell { out.extend_from_slice(&b.bytes); }
+            if let Some(b) = buy { out.extend_from_slice(&b.bytes); }
+        }
+        EventKind::NewTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag } => {
+            encode_trade(out, date_str, *trade_number, *price, *volume, [*qty, *buy_agent, *sell_agent, *trade_type], *edit_flag);
+        }
+        EventKind::HistoryTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type } => {
+            encode_trade(out, date_str, *trade_number, *price, *volume, [*qty, *buy_agent, *sell_agent, *trade_type], 0);
+        }
+        EventKind::State { state_type, value } => {
+            out.extend_from_slice(&state_type.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Trade body; `ints` is `[qty, buy_agent, sell_agent, trade_type]`.
+fn encode_trade(out: &mut Vec<u8>, date: &str, trade_number: u32, price: f64, volume: f64, ints: [i32; 4], edit_flag: u8) {
+    out.extend_from_slice(&trade_number.to_le_bytes());
+    out.extend_from_slice(&price.to_le_bytes());
+    out.extend_from_slice(&volume.to_le_bytes());
+    for v in ints { out.extend_from_slice(&v.to_le_bytes()); }
+    out.push(edit_flag);
+    out.extend_from_slice(&(date.len() as u32).to_le_bytes());
+    out.extend_from_slice(date.as_bytes());
+}
+
+/// Event read in place from a fixed-layout payload. Mirrors [`EventRecord`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventView<'a> {
+    pub seq: u64,
+    pub instrument: u32,
+    pub recv_unix_ns: u128,
+    pub recv_mono_ns_from_start: u128,
+    pub kind: KindView<'a>,
+}
+
+/// Borrowed counterpart of [`EventKind`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KindView<'a> {
+    OfferBookV2 {
+        n_action: i32,
+        n_position: i32,
+        n_side: i32,
+        n_qtd: i64,
+        n_agent: i32,
+        n_offer_id: i64,
+        d_price: f64,
+        has_price: bool,
+        has_qtd: bool,
+        has_date: bool,
+        has_offer_id: bool,
+        has_agent: bool,
+        date_str: Option<&'a str>,
+        array_sell: Option<BlockView<'a>>,
+        array_buy: Option<BlockView<'a>>,
+    },
+    NewTrade {
+        date_str: &'a str,
+        trade_number: u32,
+        price: f64,
+        volume: f64,
+        qty: i32,
+        buy_agent: i32,
+        sell_agent: i32,
+        trade_type: i32,
+        edit_flag: u8,
+    },
+    HistoryTrade {
+        date_str: &'a str,
+        trade_number: u32,
+        price: f64,
+        volume: f64,
+        qty: i32,
+        buy_agent: i32,
+        sell_agent: i32,
+        trade_type: i32,
+    },
+    State { state_type: i32, value: i32 },
+}
+
+/// Borrowed counterpart of [`RawArrayBlock`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockView<'a> {
+    pub size: u32,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> EventView<'a> {
+    /// Read a fixed-layout event payload. On failure returns what is wrong
+    /// with it.
+    pub fn parse(payload: &'a [u8]) -> Result<Self, &'static str> {
+        let mut c = Cursor { bytes: payload, pos: 0 };
+        if c.take(4)? != EVENT_TAG { return Err("not an event payload"); }
+        let kind = c.u8()?;
+        let (seq, instrument, recv_unix_ns, recv_mono_ns_from_start) = (c.u64()?, c.u32()?, c.u128()?, c.u128()?);
+        let kind = match kind {
+            OFFER_BOOK => {
+                let (n_action, n_position, n_side) = (c.i32()?, c.i32()?, c.i32()?);
+                let (n_qtd, n_agent, n_offer_id, d_price) = (c.i64()?, c.i32()?, c.i64()?, c.f64()?);
+                let flags = c.u8()?;
+                let bit = |i: u8| flags & (1 << i) != 0;
+                let (sell_size, buy_size) = (c.u32()?, c.u32()?);
+                let (date_len, sell_len, buy_len) = (c.u32()?, c.u32()?, c.u32()?);
+                let date = c.str(date_len)?;
+                let sell = BlockView { size: sell_size, bytes: c.take(sell_len as usize)? };
+                let buy = BlockView { size: buy_size, bytes: c.take(buy_len as usize)? };
+                KindView::OfferBookV2 {
+                    n_action, n_position, n_side, n_qtd, n_agent, n_offer_id, d_price,
+                    has_price: bit(0), has_qtd: bit(1), has_date: bit(2), has_offer_id: bit(3), has_agent: bit(4),
+                    date_str: bit(5).then_some(date),
+                    array_sell: bit(6).then_some(sell),
+                    array_buy: bit(7).then_some(buy),
+                }
+            }
+            NEW_TRADE | HISTORY_TRADE => {
+                let (trade_number, price, volume) = (c.u32()?, c.f64()?, c.f64()?);
+                let (qty, buy_agent, sell_agent, trade_type) = (c.i32()?, c.i32()?, c.i32()?, c.i32()?);
+                let edit_flag = c.u8()?;
+                let len = c.u32()?;
+                let date_str = c.str(len)?;
+                if kind == NEW_TRADE {
+                    KindView::NewTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag }
+                } else {
+                    KindView::HistoryTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type }
+                }
+            }
+            STATE => KindView::State { state_type: c.i32()?, value: c.i32()? },
+            _ => return Err("unknown event kind"),
+        };
+        if c.pos != payload.len() { return Err("trailing bytes after event"); }
+        Ok(EventView { seq, instrument, recv_unix_ns, recv_mono_ns_from_start, kind })
+    }
+
+    /// Owned copy of the event.
+    pub fn to_record(&self) -> EventRecord {
+        EventRecord {
+            seq: self.seq,
+            instrument: self.instrument,
+            recv_unix_ns: self.recv_unix_ns,
+            recv_mono_ns_from_start: self.recv_mono_ns_from_start,
+            kind: self.kind.to_kind(),
+        }
+    }
+}
+
+impl<'a> KindView<'a> {
+    /// Owned copy of the event kind.
+    pub fn to_kind(&self) -> EventKind {
+        let block = |b: &BlockView| RawArrayBlock { size: b.size, bytes: b.bytes.to_vec() };
+        match *self {
+            KindView::OfferBookV2 {
+                n_action, n_position, n_side, n_qtd, n_agent, n_offer_id, d_price,
+                has_price, has_qtd, has_date, has_offer_id, has_agent, date_str, array_sell, array_buy,
+            } => EventKind::OfferBookV2 {
+                n_action, n_position, n_side, n_qtd, n_agent, n_offer_id, d_price,
+                has_price, has_qtd, has_date, has_offer_id, has_agent,
+                date_str: date_str.map(str::to_string),
+                array_sell: array_sell.as_ref().map(block),
+                array_buy: array_buy.as_ref().map(block),
+            },
+            KindView::NewTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag } => {
+                EventKind::NewTrade { date_str: date_str.to_string(), trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag }
+            }
+            KindView::HistoryTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type } => {
+                EventKind::HistoryTrade { date_str: date_str.to_string(), trade_number, price, volume, qty, buy_agent, sell_agent, trade_type }
+            }
+            KindView::State { state_type, value } => EventKind::State { state_type, value },
+        }
+    }
+}
+
+impl<'a> From<&'a EventKind> for KindView<'a> {
+    fn from(kind: &'a EventKind) -> Self {
+        let block = |b: &'a RawArrayBlock| BlockView { size: b.size, bytes: &b.bytes };
+        match kind {
+            EventKind::OfferBookV2 {
+                n_action, n_position, n_side, n_qtd, n_agent, n_offer_id, d_price,
+                has_price, has_qtd, has_date, has_offer_id, has_agent, date_str, array_sell, array_buy,
+            } => KindView::OfferBookV2 {
+                n_action: *n_action, n_position: *n_position, n_side: *n_side, n_qtd: *n_qtd, n_agent: *n_agent,
+                n_offer_id: *n_offer_id, d_price: *d_price,
+                has_price: *has_price, has_qtd: *has_qtd, has_date: *has_date, has_offer_id: *has_offer_id, has_agent: *has_agent,
+                date_str: date_str.as_deref(),
+                array_sell: array_sell.as_ref().map(block),
+                array_buy: array_buy.as_ref().map(block),
+            },
+            EventKind::NewTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type, edit_flag } => KindView::NewTrade {
+                date_str, trade_number: *trade_number, price: *price, volume: *volume, qty: *qty,
+                buy_agent: *buy_agent, sell_agent: *sell_agent, trade_type: *trade_type, edit_flag: *edit_flag,
+            },
+            EventKind::HistoryTrade { date_str, trade_number, price, volume, qty, buy_agent, sell_agent, trade_type } => KindView::HistoryTrade {
+                date_str, trade_number: *trade_number, price: *price, volume: *volume, qty: *qty,
+                buy_agent: *buy_agent, sell_agent: *sell_agent, trade_type: *trade_type,
+            },
+            EventKind::State { state_type, value } => KindView::State { state_type: *state_type, value: *value },
+        }
+    }
+}
+
+/// Bounds-checked little-endian reads over a payload.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.bytes.len()).ok_or("event payload truncated")?;
+        let out = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> { Ok(self.take(1)?[0]) }
+    fn u32(&mut self) -> Result<u32, &'static str> { self.array().map(u32::from_le_bytes) }
+    fn i32(&mut self) -> Result<i32, &'static str> { self.array().map(i32::from_le_bytes) }
+    fn u64(&mut self) -> Result<u64, &'static str> { self.array().map(u64::from_le_bytes) }
+    fn i64(&mut self) -> Result<i64, &'static str> { self.array().map(i64::from_le_bytes) }
+    fn f64(&mut self) -> Result<f64, &'static str> { self.array().map(f64::from_le_bytes) }
+    fn u128(&mut self) -> Result<u128, &'static str> { self.array().map(u128::from_le_bytes) }
+
+    fn str(&mut self, len: u32) -> Result<&'a str, &'static str> {
+        std::str::from_utf8(self.take(len as usize)?).map_err(|_| "event string is not UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_kind_roundtrips() {
+        let block = |n: u8| Some(RawArrayBlock { size: n as u32, bytes: vec![n; n as usize] });
+        let kinds = [
+            EventKind::OfferBookV2 {
+                n_action: 4, n_position: -1, n_side: 1, n_qtd: 5, n_agent: 7, n_offer_id: 9, d_price: 101.5,
+                has_price: true, has_qtd: false, has_date: true, has_offer_id: false, has_agent: true,
+                date_str: Some("04/09/2025 10:00:00.000".into()), array_sell: block(3), array_buy: None,
+            },
+            EventKind::OfferBookV2 {
+                n_action: 0, n_position: 0, n_side: 0, n_qtd: 0, n_agent: 0, n_offer_id: 0, d_price: 0.0,
+                has_price: false, has_qtd: false, has_date: false, has_offer_id: false, has_agent: false,
+                date_str: Some(String::new()), array_sell: None, array_buy: block(0),
+            },
+            EventKind::NewTrade { date_str: "d".into(), trade_number: 1, price: 2.0, volume: 3.0, qty: 4, buy_agent: 5, sell_agent: 6, trade_type: 7, edit_flag: 8 },
+            EventKind::HistoryTrade { date_str: "é".into(), trade_number: 1, price: 2.0, volume: 3.0, qty: 4, buy_agent: 5, sell_agent: 6, trade_type: 7 },
+            EventKind::State { state_type: -1, value: 2 },
+        ];
+        for (seq, kind) in kinds.into_iter().enumerate() {
+            let ev = EventRecord { seq: seq as u64, instrument: 3, recv_unix_ns: u128::MAX, recv_mono_ns_from_start: 11, kind };
+            let mut payload = Vec::new();
+            encode_event(&ev, &mut payload);
+            assert!(is_event(Encoding::Fixed, &payload));
+            let view = EventView::parse(&payload).unwrap();
+            assert_eq!(view.kind, KindView::from(&ev.kind));
+            assert_eq!(bincode::serialize(&view.to_record()).unwrap(), bincode::serialize(&ev).unwrap());
+            assert!(EventView::parse(&payload[..payload.len() - 1]).is_err());
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Recovery of damaged captures.
+//!
+//! [`repair`] copies every frame that still passes its CRC and decodes, and
+//! scans byte by byte past anything else until the next valid
+//! `[len][crc][payload]` frame. Each dropped region is replaced by a
+//! [`RecordFrame::Gap`] marker in the output. The header must be intact: it
+//! carries the version and codec needed to validate the frames after it. A
+//! surviving trailer is recomputed and written last. The hash chain of a
+//! chained file is not checked: the repaired file gets a new one.
+use super::{chain, compress, decode_owned, read_full, CaptureError, CaptureReader, CaptureWriter, Codec, Encoding, GapMarker, GapScope, RecordFrame, FRAME_PREFIX_LEN, MAX_FRAME_LEN};
+use std::io::{self, Read, Write};
+
+/// Read-ahead granularity of the scan window.
+const CHUNK: usize = 1 << 20;
+
+/// Summary of a [`repair`] run.
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    /// Valid frames copied after the header.
+    pub frames: u64,
+    /// Damaged regions that were dropped, in file order (also written to the
+    /// output as [`RecordFrame::Gap`] frames).
+    pub gaps: Vec<GapMarker>,
+}
+
+impl RepairReport {
+    /// Total bytes dropped.
+    pub fn skipped_bytes(&self) -> u64 { self.gaps.iter().map(|g| g.skipped_bytes).sum() }
+}
+
+/// Copy the readable frames of `input` into a new capture, with a gap marker
+/// in place of each damaged region (including a truncated tail). Returns the
+/// flushed output writer and a report of what was dropped.
+pub fn repair<R: Read, W: Write>(mut input: R, out: W) -> Result<(W, RepairReport), CaptureError> {
+    let (header, version, start) = {
+        let rdr = CaptureReader::new(&mut input)?;
+        (rdr.header().clone(), rdr.version(), rdr.offset())
+    };
+    let (codec, encoding, chained) = (header.codec(), header.encoding(), header.hash_chain());
+    let mut win = Window { inner: input, buf: Vec::new(), start: 0, pos: start, eof: false };
+    let mut w = CaptureWriter::new(out, header)?;
+    let mut report = RepairReport::default();
+    // Start offset of the damaged region being skipped, if any
+    let mut damaged: Option<u64> = None;
+    let mut last_seq = None;
+    let mut trailer = None;
+    while !win.fill(1)?.is_empty() {
+        let Some((frame, len)) = win.try_frame(version, codec, encoding, chained)? else {
+            damaged.get_or_insert(win.pos);
+            win.advance(1);
+            continue;
+        };
+        let seq = match &frame { RecordFrame::Event(ev) => Some(ev.seq), _ => None };
+        if let Some(offset) = damaged.take() {
+            let gap = GapMarker { offset, skipped_bytes: win.pos - offset, last_seq, next_seq: seq, scope: GapScope::All };
+            w.write_frame(&RecordFrame::Gap(gap.clone()))?;
+            report.gaps.push(gap);
+        }
+        if seq.is_some() { last_seq = seq; }
+        win.advance(len);
+        if let RecordFrame::Trailer(t) = frame {
+            trailer = Some(t);
+            continue;
+        }
+        w.write_frame(&frame)?;
+        report.frames += 1;
+    }
+    if let Some(offset) = damaged {
+        let gap = GapMarker { offset, skipped_bytes: win.pos - offset, last_seq, next_seq: None, scope: GapScope::All };
+        w.write_frame(&RecordFrame::Gap(gap.clone()))?;
+        report.gaps.push(gap);
+    }
+    if let Some(t) = trailer {
+        w.set_dropped_events(t.dropped_events);
+        w.write_trailer()?;
+    }
+    Ok((w.finish()?, report))
+}
+
+/// Sliding read-ahead buffer; `buf[start..]` holds the bytes from file
+/// offset `pos` on.
+struct Window<R> {
+    inner: R,
+    buf: Vec<u8>,
+    start: usize,
+    pos: u64,
+    eof: bool,
+}
+
+impl<R: Read> Window<R> {
+    /// Bytes from `pos` on, at least `n` of them unless EOF comes first.
+    fn fill(&mut self, n: usize) -> io::Result<&[u8]> {
+        while self.buf.len() - self.start < n && !self.eof {
+            if self.start >= CHUNK {
+                self.buf.drain(..self.start);
+                self.start = 0;
+            }
+            let want = (n - (self.buf.len() - self.start)).max(CHUNK);
+            let old = self.buf.len();
+            self.buf.resize(old + want, 0);
+            let got = read_full(&mut self.inner, &mut self.buf[old..])?;
+            self.buf.truncate(old + got);
+            self.eof = got < want;
+        }
+        let end = self.buf.len().min(self.start + n);
+        Ok(&self.buf[self.start..end])
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.start += n;
+        self.pos += n as u64;
+    }
+
+    /// Decode the frame at `pos` if it is valid, returning it with its
+    /// on-disk length. Any damage (bad length, short read, CRC, decode, a
+    /// second header) yields `None`.
+    fn try_frame(&mut self, version: u16, codec: Codec, encoding: Encoding, chained: bool) -> io::Result<Option<(RecordFrame, usize)>> {
+        const PREFIX: usize = FRAME_PREFIX_LEN as usize;
+        let prefix = self.fill(PREFIX)?;
+        if prefix.len() < PREFIX { return Ok(None); }
+        let len = u32::from_le_bytes(prefix[0..4].try_into().unwrap());
+        let crc = u32::from_le_bytes(prefix[4..8].try_into().unwrap());
+        if len > MAX_FRAME_LEN { return Ok(None); }
+        let total = PREFIX + len as usize;
+        // Check the variant tag before reading and hashing up to MAX_FRAME_LEN bytes
+        if !plausible(codec, &self.fill(total.min(PREFIX + 5))?[PREFIX..]) { return Ok(None); }
+        let bytes = self.fill(total)?;
+        if bytes.len() < total || crc32fast::hash(&bytes[PREFIX..]) != crc { return Ok(None); }
+        let mut stored = bytes[PREFIX..].to_vec();
+        if chained && chain::split(&mut stored).is_none() { return Ok(None); }
+        let Ok(payload) = compress::decompress(codec, stored) else { return Ok(None) };
+        match decode_owned(version, encoding, &payload, 0, 0) {
+            Ok(RecordFrame::Header(_)) | Err(_) => Ok(None),
+            Ok(frame) => Ok(Some((frame, total))),
+        }
+    }
+}
+
+/// Cheap test on the first bytes of a candidate payload: a bincode
+/// `RecordFrame` after the header starts with a small non-zero variant tag.
+fn plausible(codec: Codec, head: &[u8]) -> bool {
+    let tag_ok = |t: &[u8]| t.len() >= 4 && (1..256).contains(&u32::from_le_bytes(t[..4].try_into().unwrap()));
+    match codec {
+        Codec::None => tag_ok(head),
+        // `[method:u8][data]`: stored payloads are checked like plain ones
+        _ => match head.first() {
+            Some(0) => tag_ok(&head[1..]),
+            Some(1) => true,
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::testing::header;
+    use crate::record::{EventKind, EventRecord};
+
+    fn capture(codec: Codec, events: u64) -> (Vec<u8>, Vec<u64>) {
+        let mut h = header("T");
+        h.set_codec(codec);
+        let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+        let mut offsets = Vec::new();
+        for seq in 0..events {
+            offsets.push(w.bytes_written());
+            w.write_event(EventRecord { seq, instrument: 0, recv_unix_ns: 0, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 0, value: 0 } }).unwrap();
+        }
+        (w.finish().unwrap(), offsets)
+    }
+
+    fn seqs_and_gaps(bytes: &[u8]) -> (Vec<u64>, Vec<GapMarker>) {
+        let (mut seqs, mut gaps) = (Vec::new(), Vec::new());
+        for f in CaptureReader::new(bytes).unwrap() {
+            match f.unwrap() {
+                RecordFrame::Event(ev) => seqs.push(ev.seq),
+                RecordFrame::Gap(g) => gaps.push(g),
+                other => panic!("unexpected frame {other:?}"),
+            }
+        }
+        (seqs, gaps)
+    }
+
+    #[test]
+    fn skips_corrupted_frames_and_marks_the_gap() {
+        for codec in [Codec::None, Codec::Zstd] {
+            let (mut bytes, offsets) = capture(codec, 10);
+            // Damage frames 3 and 4 (flip a payload byte, then garble a length)
+            bytes[offsets[3] as usize + 9] ^= 0xff;
+            bytes[offsets[4] as usize..offsets[4] as usize + 4].copy_from_slice(&[0xff; 4]);
+            let (out, report) = repair(bytes.as_slice(), Vec::new()).unwrap();
+            assert_eq!(report.frames, 8);
+            assert_eq!(report.gaps, vec![GapMarker {
+                offset: offsets[3],
+                skipped_bytes: offsets[5] - offsets[3],
+                last_seq: Some(2),
+                next_seq: Some(5),
+                scope: GapScope::All,
+            }]);
+            let (seqs, gaps) = seqs_and_gaps(&out);
+            assert_eq!(seqs, vec![0, 1, 2, 5, 6, 7, 8, 9]);
+            assert_eq!(gaps, report.gaps);
+        }
+    }
+
+    #[test]
+    fn truncated_tail_becomes_trailing_gap() {
+        let (mut bytes, offsets) = capture(Codec::None, 4);
+        bytes.truncate(offsets[3] as usize + 5);
+        let (out, report) = repair(bytes.as_slice(), Vec::new()).unwrap();
+        assert_eq!(report.skipped_bytes(), 5);
+        let (seqs, gaps) = seqs_and_gaps(&out);
+        assert_eq!(seqs, vec![0, 1, 2]);
+        assert_eq!(gaps[..], [GapMarker { offset: offsets[3], skipped_bytes: 5, last_seq: Some(2), next_seq: None, scope: GapScope::All }]);
+    }
+}
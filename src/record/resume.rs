@@ -0,0 +1,178 @@
+//! Reopening a capture to append to it.
+//!
+//! [`resume`] reads an existing capture from the start, checking every frame
+//! as [`CaptureReader`] does, and returns a [`CaptureWriter`] positioned after
+//! its last complete frame. A partial last frame (recorder killed mid-write)
+//! is cut off, and so is the trailer of a file that was closed cleanly: the
+//! new writer writes a fresh one covering the whole file. The trailer's
+//! running summary, the whole-file CRC32, the hash chain and the seek index
+//! are rebuilt from the frames read, so the resumed file is
+//! indistinguishable from one written in a single run, apart from the
+//! [`RecordFrame::Resumed`] marker the caller writes next.
+//!
+//! Any other damage is reported as an error: resuming never drops frames
+//! from the middle of a file (see [`super::repair`] for that).
+use super::{CaptureError, CaptureReader, CaptureWriter, Instrument, RecordFrame, Trailer, DIGEST_LEN, FORMAT_VERSION};
+use crate::index::IndexBuilder;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom};
+use std::path::Path;
+
+/// What [`resume`] found in the existing file.
+#[derive(Debug, Clone)]
+pub struct ResumeReport {
+    /// Trailer removed from the end of a cleanly closed file.
+    pub trailer: Option<Trailer>,
+    /// Bytes of a partial last frame cut off the end.
+    pub truncated_bytes: u64,
+    /// Seq of the last event in the file.
+    pub last_seq: Option<u64>,
+    /// Instrument table: the header's plus any instrument frames.
+    pub instruments: Vec<Instrument>,
+    /// Frames kept, including the header.
+    pub frames: u64,
+}
+
+/// Reader state at a frame boundary, where the file may be cut.
+struct Cut {
+    frame: u64,
+    offset: u64,
+    file_crc: crc32fast::Hasher,
+    chain: Option<[u8; DIGEST_LEN]>,
+}
+
+/// Reopen the capture at `path` for appending. With `index_every > 0` the
+/// writer indexes the existing frames and the new ones, like
+/// [`CaptureWriter::enable_index`] on a new file. Files written with an
+/// older [`FORMAT_VERSION`] must be upgraded first.
+pub fn resume(path: impl AsRef<Path>, index_every: u32) -> Result<(CaptureWriter<BufWriter<File>>, ResumeReport), CaptureError> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = CaptureReader::new(BufReader::with_capacity(1 << 20, &file))?;
+    if reader.version() != FORMAT_VERSION {
+        return Err(CaptureError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("cannot append to a v{} capture; upgrade it first", reader.version()),
+        )));
+    }
+    let header = reader.header().clone();
+    let mut report = ResumeReport { trailer: None, truncated_bytes: 0, last_seq: None, instruments: header.instruments(), frames: 0 };
+    let mut summary = Trailer { final_seq: None, counts: Default::default(), first_recv_unix_ns: None, last_recv_unix_ns: None, dropped_events: 0, file_crc32: 0, chain_digest: None };
+    let mut index = (index_every > 0).then(|| IndexBuilder::new(index_every));
+    let mut cut = None;
+    loop {
+        let here = Cut {
+            frame: reader.frame,
+            offset: reader.offset,
+            file_crc: reader.file_crc.clone().unwrap_or_default(),
+            chain: reader.chain,
+        };
+        match reader.read_frame() {
+            Ok(None) => break,
+            Ok(Some(RecordFrame::Trailer(t))) => {
+                summary.dropped_events = t.dropped_events;
+                report.trailer = Some(t);
+                cut = Some(here);
+            }
+            Ok(Some(frame)) => {
+                if let Some(b) = &mut index { b.observe(here.frame, here.offset, &frame); }
+                match frame {
+                    RecordFrame::Event(ev) => {
+                        summary.record(&ev);
+                        report.last_seq = Some(ev.seq);
+                    }
+                    RecordFrame::Instrument(i) => report.instruments.push(i),
+                    _ => {}
+                }
+            }
+            // A frame cut short by a crash, possibly after the trailer
+            Err(CaptureError::Truncated { offset, .. }) => {
+                report.truncated_bytes = len - offset;
+                cut.get_or_insert(here);
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let cut = cut.unwrap_or(Cut { frame: reader.frame, offset: reader.offset, file_crc: reader.file_crc.clone().unwrap_or_default(), chain: reader.chain });
+    drop(reader);
+    file.set_len(cut.offset)?;
+    file.seek(SeekFrom::Start(cut.offset))?;
+    report.frames = cut.frame;
+    let (codec, encoding) = (header.codec(), header.encoding());
+    let w = CaptureWriter {
+        inner: BufWriter::with_capacity(1 << 20, file),
+        header,
+        codec,
+        encoding,
+        frames: cut.frame,
+        offset: cut.offset,
+        index,
+        file_crc: cut.file_crc,
+        chain: cut.chain,
+        summary,
+        closed: false,
+    };
+    Ok((w, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::CaptureIndex;
+    use crate::record::testing::header;
+    use crate::record::{EventKind, EventRecord, GapScope, ResumeMarker};
+
+    fn state(seq: u64) -> EventRecord {
+        EventRecord { seq, instrument: 0, recv_unix_ns: seq as u128, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 0, value: 0 } }
+    }
+
+    /// Resume `path`, add a marker and events `seqs`, and close it cleanly.
+    fn resume_and_close(path: &Path, seqs: std::ops::Range<u64>) -> ResumeReport {
+        let (mut w, report) = resume(path, 3).unwrap();
+        let marker = ResumeMarker { resumed_unix_ns: 0, last_seq: report.last_seq, truncated_bytes: report.truncated_bytes, server_clock_offset_ms: 0, scope: GapScope::All, extensions: Vec::new() };
+        w.write_frame(&RecordFrame::Resumed(marker)).unwrap();
+        for seq in seqs { w.write_event(state(seq)).unwrap(); }
+        w.write_trailer().unwrap();
+        let index = w.take_index().unwrap();
+        w.finish().unwrap();
+        assert_eq!(index, CaptureIndex::build(CaptureReader::open(path).unwrap(), 3).unwrap());
+        report
+    }
+
+    #[test]
+    fn resumed_files_read_like_single_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cap.bin");
+        let mut h = header("T");
+        h.set_hash_chain(true);
+        let mut w = CaptureWriter::create(&path, h).unwrap();
+        for seq in 0..4 { w.write_event(state(seq)).unwrap(); }
+        w.set_dropped_events(2);
+        w.write_trailer().unwrap();
+        w.finish().unwrap();
+
+        // Closed cleanly: the trailer is replaced
+        let report = resume_and_close(&path, 4..6);
+        assert_eq!((report.last_seq, report.truncated_bytes, report.frames), (Some(3), 0, 5));
+        assert_eq!(report.trailer.unwrap().dropped_events, 2);
+
+        // Killed mid-write: the partial frame is cut
+        let full = std::fs::read(&path).unwrap();
+        let mut r = CaptureReader::new(full.as_slice()).unwrap();
+        for _ in 0..7 { r.read_frame().unwrap(); }
+        let trailer_at = r.offset() as usize;
+        std::fs::write(&path, &full[..trailer_at + 5]).unwrap();
+        let report = resume_and_close(&path, 6..8);
+        assert_eq!((report.last_seq, report.truncated_bytes, report.trailer), (Some(5), 5, None));
+
+        let mut r = CaptureReader::open(&path).unwrap();
+        let frames: Vec<_> = r.by_ref().map(Result::unwrap).collect();
+        let seqs: Vec<_> = frames.iter().filter_map(|f| match f { RecordFrame::Event(ev) => Some(ev.seq), _ => None }).collect();
+        assert_eq!(seqs, (0..8).collect::<Vec<_>>());
+        assert_eq!(frames.iter().filter(|f| matches!(f, RecordFrame::Resumed(_))).count(), 2);
+        let t = r.trailer().unwrap();
+        assert_eq!((t.final_seq, t.counts.state, t.first_recv_unix_ns), (Some(7), 8, Some(0)));
+        assert!(r.chain_digest().is_some());
+    }
+}
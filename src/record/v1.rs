@@ -0,0 +1,68 @@
+//! Frozen v1 capture schema.
+//!
+//! Version 1 files were written before [`super::FileHeader::extensions`]
+//! existed. Only the types whose bincode layout differs from the current
+//! schema are redeclared here; everything else is shared. Do not change
+//! these definitions: they describe files that already exist on disk.
+use serde::{Deserialize, Serialize};
+
+/// v1 file header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileHeader {
+    pub version: u16,
+    pub created_unix_ns: u128,
+    pub ticker: String,
+    pub exchange: String,
+    pub server_clock_offset_ms: i64,
+}
+
+/// v1/v2 event, before [`super::EventRecord::instrument`] existed. Converts
+/// to instrument 0, the file's only instrument.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub seq: u64,
+    pub recv_unix_ns: u128,
+    pub recv_mono_ns_from_start: u128,
+    pub kind: super::EventKind,
+}
+
+/// v1 frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordFrame {
+    Header(FileHeader),
+    Event(EventRecord),
+}
+
+impl From<FileHeader> for super::FileHeader {
+    fn from(h: FileHeader) -> Self {
+        super::FileHeader {
+            version: h.version,
+            created_unix_ns: h.created_unix_ns,
+            ticker: h.ticker,
+            exchange: h.exchange,
+            server_clock_offset_ms: h.server_clock_offset_ms,
+            extensions: Vec::new(),
+        }
+    }
+}
+
+impl From<EventRecord> for super::EventRecord {
+    fn from(ev: EventRecord) -> Self {
+        super::EventRecord {
+            seq: ev.seq,
+            instrument: 0,
+            recv_unix_ns: ev.recv_unix_ns,
+            recv_mono_ns_from_start: ev.recv_mono_ns_from_start,
+            kind: ev.kind,
+        }
+    }
+}
+
+impl From<RecordFrame> for super::RecordFrame {
+    fn from(f: RecordFrame) -> Self {
+        match f {
+            RecordFrame::Header(h) => super::RecordFrame::Header(h.into()),
+            RecordFrame::Event(ev) => super::RecordFrame::Event(ev.into()),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Frozen v2 capture schema.
+//!
+//! Version 2 files predate multi-instrument captures: events and checkpoints
+//! carry no instrument id. Everything else is shared with the current
+//! schema. Do not change these definitions: they describe files that
+//! already exist on disk.
+use serde::{Deserialize, Serialize};
+
+/// v2 checkpoint, before [`super::BookCheckpoint::instrument`] existed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookCheckpoint {
+    pub seq: u64,
+    pub recv_unix_ns: u128,
+    pub hash: u64,
+    pub book: crate::book::Book,
+}
+
+/// v2 frame. Events use the v1 layout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordFrame {
+    Header(super::FileHeader),
+    Event(super::v1::EventRecord),
+    Checkpoint(BookCheckpoint),
+    Gap(super::v3::GapMarker),
+    Trailer(super::v4::Trailer),
+}
+
+impl From<BookCheckpoint> for super::BookCheckpoint {
+    fn from(cp: BookCheckpoint) -> Self {
+        super::BookCheckpoint { instrument: 0, seq: cp.seq, recv_unix_ns: cp.recv_unix_ns, hash: cp.hash, book: cp.book }
+    }
+}
+
+impl From<RecordFrame> for super::RecordFrame {
+    fn from(f: RecordFrame) -> Self {
+        match f {
+            RecordFrame::Header(h) => super::RecordFrame::Header(h),
+            RecordFrame::Event(ev) => super::RecordFrame::Event(ev.into()),
+            RecordFrame::Checkpoint(cp) => super::RecordFrame::Checkpoint(cp.into()),
+            RecordFrame::Gap(g) => super::RecordFrame::Gap(g.into()),
+            RecordFrame::Trailer(t) => super::RecordFrame::Trailer(t.into()),
+        }
+    }
+}
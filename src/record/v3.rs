@@ -0,0 +1,46 @@
+//! Frozen v3 capture schema.
+//!
+//! Version 3 files predate scoped gap markers: a gap marker has no
+//! [`super::GapScope`] and applies to every instrument, and the trailer is
+//! the v4 one. Everything else is shared with the current schema. Do not
+//! change these definitions: they describe files that already exist on disk.
+use serde::{Deserialize, Serialize};
+
+/// v1-v3 gap marker, before [`super::GapMarker::scope`] existed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GapMarker {
+    pub offset: u64,
+    pub skipped_bytes: u64,
+    pub last_seq: Option<u64>,
+    pub next_seq: Option<u64>,
+}
+
+/// v3 frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordFrame {
+    Header(super::FileHeader),
+    Event(super::EventRecord),
+    Checkpoint(super::BookCheckpoint),
+    Gap(GapMarker),
+    Trailer(super::v4::Trailer),
+    Instrument(super::Instrument),
+}
+
+impl From<GapMarker> for super::GapMarker {
+    fn from(g: GapMarker) -> Self {
+        super::GapMarker { offset: g.offset, skipped_bytes: g.skipped_bytes, last_seq: g.last_seq, next_seq: g.next_seq, scope: super::GapScope::All }
+    }
+}
+
+impl From<RecordFrame> for super::RecordFrame {
+    fn from(f: RecordFrame) -> Self {
+        match f {
+            RecordFrame::Header(h) => super::RecordFrame::Header(h),
+            RecordFrame::Event(ev) => super::RecordFrame::Event(ev),
+            RecordFrame::Checkpoint(cp) => super::RecordFrame::Checkpoint(cp),
+            RecordFrame::Gap(g) => super::RecordFrame::Gap(g.into()),
+            RecordFrame::Trailer(t) => super::RecordFrame::Trailer(t.into()),
+            RecordFrame::Instrument(i) => super::RecordFrame::Instrument(i),
+        }
+    }
+}
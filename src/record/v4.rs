@@ -0,0 +1,56 @@
+//! Frozen v4 capture schema.
+//!
+//! Version 4 files predate hash chains: the trailer has no
+//! [`super::Trailer::chain_digest`]. Everything else is shared with the
+//! current schema. Do not change these definitions: they describe files that
+//! already exist on disk.
+use serde::{Deserialize, Serialize};
+
+/// v2-v4 trailer, before [`super::Trailer::chain_digest`] existed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trailer {
+    pub final_seq: Option<u64>,
+    pub counts: super::EventCounts,
+    pub first_recv_unix_ns: Option<u128>,
+    pub last_recv_unix_ns: Option<u128>,
+    pub dropped_events: u64,
+    pub file_crc32: u32,
+}
+
+/// v4 frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordFrame {
+    Header(super::FileHeader),
+    Event(super::EventRecord),
+    Checkpoint(super::BookCheckpoint),
+    Gap(super::GapMarker),
+    Trailer(Trailer),
+    Instrument(super::Instrument),
+}
+
+impl From<Trailer> for super::Trailer {
+    fn from(t: Trailer) -> Self {
+        super::Trailer {
+            final_seq: t.final_seq,
+            counts: t.counts,
+            first_recv_unix_ns: t.first_recv_unix_ns,
+            last_recv_unix_ns: t.last_recv_unix_ns,
+            dropped_events: t.dropped_events,
+            file_crc32: t.file_crc32,
+            chain_digest: None,
+        }
+    }
+}
+
+impl From<RecordFrame> for super::RecordFrame {
+    fn from(f: RecordFrame) -> Self {
+        match f {
+            RecordFrame::Header(h) => super::RecordFrame::Header(h),
+            RecordFrame::Event(ev) => super::RecordFrame::Event(ev),
+            RecordFrame::Checkpoint(cp) => super::RecordFrame::Checkpoint(cp),
+            RecordFrame::Gap(g) => super::RecordFrame::Gap(g),
+            RecordFrame::Trailer(t) => super::RecordFrame::Trailer(t.into()),
+            RecordFrame::Instrument(i) => super::RecordFrame::Instrument(i),
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Book reconstruction from capture frames.
+//!
+//! [`Replayer`] applies Offer Book V2 events to a [`Book`] through a
+//! [`BookBuilder`], which assembles multi-packet FullBook snapshots (applied
+//! per side once `OB_LAST_PACKET` arrives), and handles [`BookCheckpoint`]
+//! frames: a fresh replayer restores its book from the first checkpoint it
+//! sees, an active one verifies its own state against the checkpoint hash.
+//! A fresh replayer's book is stale until its first complete FullBook or
+//! checkpoint, since a capture may start mid-stream; so is the book after a
+//! [`crate::record::GapMarker`] or a [`crate::record::ResumeMarker`], until
+//! the next one restores it. Normalized files replay through
+//! [`Replayer::apply_normalized`].
+//!
+//! A replayer follows one instrument; callers filter events by
+//! [`EventRecord::instrument`]. [`Checkpointer`] keeps one replayer per
+//! instrument to write checkpoints for a whole multi-instrument capture.
+//!
+//! [`Replayer::with_order_tracking`] also records each order's lifecycle
+//! (see [`crate::orders`]), timed by the events that changed it, and
+//! [`Replayer::place_order`] follows the queue position of a hypothetical
+//! order (see [`crate::queue`]).
+use crate::book::{AnomalyCounts, Book, BookBuilder, OfferChange, OfferPosition, Side};
+use crate::l2::L2Book;
+use crate::normalize::{NormalizedEvent, NormalizedKind};
+use crate::orders::OrderTracker;
+use crate::queue::VirtualOrder;
+use crate::record::{BookCheckpoint, CaptureReader, CaptureWriter, EventRecord, EventView, GapMarker, GapScope, KindView, RecordFrame, ResumeMarker, NO_INSTRUMENT};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Outcome of [`Replayer::apply_checkpoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointOutcome {
+    /// The replayer had no state and adopted the checkpoint's book.
+    Restored,
+    /// The replayed book matches the checkpoint.
+    Verified,
+    /// The replayed book differs from the checkpoint.
+    Mismatch { expected: u64, actual: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    instrument: u32,
+    builder: BookBuilder,
+    /// Seq and receive time of the last event or checkpoint applied.
+    last: Option<(u64, u128)>,
+    /// No snapshot has set the book yet, or events were lost (see
+    /// [`Replayer::apply_gap`]) and none has replaced it since.
+    stale: bool,
+    orders: Option<OrderTracker>,
+    virtual_orders: Vec<VirtualOrder>,
+}
+
+impl Default for Replayer {
+    fn default() -> Self {
+        Replayer {
+            instrument: 0,
+            builder: BookBuilder::default(),
+            last: None,
+            stale: true,
+            orders: None,
+            virtual_orders: Vec::new(),
+        }
+    }
+}
+
+impl Replayer {
+    pub fn new() -> Self { Self::default() }
+
+    /// Replayer whose checkpoints are tagged with `instrument`.
+    pub fn for_instrument(instrument: u32) -> Self { Replayer { instrument, ..Self::default() } }
+
+    /// Also maintain price levels (see [`Self::levels`]).
+    pub fn with_levels(mut self) -> Self {
+        self.builder = self.builder.with_levels();
+        self
+    }
+
+    /// Also index offers by id (see [`Self::find_offer`]).
+    pub fn with_offer_index(mut self) -> Self {
+        self.builder = self.builder.with_offer_index();
+        self
+    }
+
+    /// Also record order histories (see [`Self::orders`]).
+    pub fn with_order_tracking(mut self) -> Self {
+        self.orders = Some(OrderTracker::new());
+        self
+    }
+
+    /// Instrument this replayer follows.
+    pub fn instrument(&self) -> u32 { self.instrument }
+
+    /// Current book.
+    pub fn book(&self) -> &Book { self.builder.book() }
+
+    /// Price levels of the current book, if enabled with
+    /// [`Self::with_levels`].
+    pub fn levels(&self) -> Option<&L2Book> { self.builder.levels() }
+
+    /// Order histories, if enabled with [`Self::with_order_tracking`].
+    pub fn orders(&self) -> Option<&OrderTracker> { self.orders.as_ref() }
+
+    /// Where `offer_id` currently rests, if it does.
+    pub fn find_offer(&self, offer_id: i64) -> Option<OfferPosition> { self.builder.find_offer(offer_id) }
+
+    /// Place a virtual order of `qty` at `price` on `side` of the current
+    /// book, followed by every later event. Returns its index in
+    /// [`Self::virtual_orders`], or `None` if `price` crosses the book.
+    pub fn place_order(&mut self, side: Side, price: f64, qty: i64) -> Option<usize> {
+        let (seq, recv_unix_ns) = self.last.unwrap_or_default();
+        self.virtual_orders.push(VirtualOrder::place(self.book(), side, price, qty, seq, recv_unix_ns)?);
+        Some(self.virtual_orders.len() - 1)
+    }
+
+    /// Virtual orders, in placement order.
+    pub fn virtual_orders(&self) -> &[VirtualOrder] { &self.virtual_orders }
+
+    /// `false` while a multi-packet FullBook is being accumulated.
+    pub fn is_consistent(&self) -> bool { self.builder.is_consistent() }
+
+    /// Book events skipped for an unknown action or side.
+    pub fn anomalies(&self) -> AnomalyCounts { self.builder.anomalies() }
+
+    /// `true` until the first complete FullBook or checkpoint, and between a
+    /// gap and the next one.
+    pub fn is_stale(&self) -> bool { self.stale }
+
+    /// Seq of the last event applied, if any.
+    pub fn last_seq(&self) -> Option<u64> { self.last.map(|(s, _)| s) }
+
+    /// Apply one event. Non-book events only advance the position.
+    pub fn apply_event(&mut self, ev: &EventRecord) -> Result<()> {
+        self.apply(ev.seq, ev.recv_unix_ns, KindView::from(&ev.kind))
+    }
+
+    /// [`Self::apply_event`] for an event read in place.
+    pub fn apply_view(&mut self, ev: &EventView) -> Result<()> {
+        self.apply(ev.seq, ev.recv_unix_ns, ev.kind)
+    }
+
+    /// Apply one event of a normalized file (see [`crate::normalize`]).
+    pub fn apply_normalized(&mut self, ev: &NormalizedEvent) {
+        let (seq, recv_unix_ns) = (ev.seq, ev.recv_unix_ns);
+        self.last = Some((seq, recv_unix_ns));
+        let orders = &mut self.orders;
+        let mut observe = |c: &OfferChange| if let Some(t) = orders { t.record(seq, recv_unix_ns, c) };
+        match &ev.kind {
+            NormalizedKind::FullBook { bids, asks, complete } => {
+                self.builder.apply_assembled_observed(bids.clone(), asks.clone(), *complete, &mut observe);
+                if *complete { self.stale = false; }
+            }
+            NormalizedKind::Trade(t) => {
+                if !t.history { self.observe_trade(t.price, t.qty); }
+                return;
+            }
+            kind => {
+                if let Some(action) = kind.action() { self.builder.apply_entry_observed(action, &mut observe); }
+            }
+        }
+        self.observe_book();
+    }
+
+    fn apply(&mut self, seq: u64, recv_unix_ns: u128, kind: KindView) -> Result<()> {
+        self.last = Some((seq, recv_unix_ns));
+        if let KindView::NewTrade { price, qty, .. } = kind { self.observe_trade(price, qty); }
+        let Some(action) = self.builder.decode(kind) else { return Ok(()) };
+        let orders = &mut self.orders;
+        if self.builder.apply_observed(action, &mut |c| if let Some(t) = orders { t.record(seq, recv_unix_ns, c) })? {
+            self.stale = false;
+        }
+        self.observe_book();
+        Ok(())
+    }
+
+    /// Pass the book after the last event to the virtual orders.
+    fn observe_book(&mut self) {
+        let (seq, recv_unix_ns) = self.last.unwrap_or_default();
+        for q in &mut self.virtual_orders { q.observe_book(self.builder.book(), seq, recv_unix_ns); }
+    }
+
+    fn observe_trade(&mut self, price: f64, qty: i32) {
+        let (seq, recv_unix_ns) = self.last.unwrap_or_default();
+        for q in &mut self.virtual_orders { q.observe_trade(price, qty.into(), seq, recv_unix_ns); }
+    }
+
+    /// Snapshot of the current book, or `None` while mid-FullBook, stale, or
+    /// before any event was applied.
+    pub fn checkpoint(&self) -> Option<BookCheckpoint> {
+        let (seq, recv_unix_ns) = self.last?;
+        if !self.is_consistent() || self.stale { return None; }
+        Some(BookCheckpoint { instrument: self.instrument, seq, recv_unix_ns, hash: self.book().state_hash(), book: self.book().clone() })
+    }
+
+    /// Restore from `cp` if nothing was applied yet or the book is stale,
+    /// otherwise compare.
+    pub fn apply_checkpoint(&mut self, cp: &BookCheckpoint) -> CheckpointOutcome {
+        if self.last.is_none() || self.stale {
+            self.restore(cp);
+            return CheckpointOutcome::Restored;
+        }
+        let actual = self.book().state_hash();
+        if actual == cp.hash { CheckpointOutcome::Verified } else { CheckpointOutcome::Mismatch { expected: cp.hash, actual } }
+    }
+
+    /// Replace all state with the checkpoint's book. Tracked orders missing
+    /// from it are closed as if a FullBook had replaced both sides.
+    pub fn restore(&mut self, cp: &BookCheckpoint) {
+        if let Some(t) = &mut self.orders {
+            for side in [Side::Bid, Side::Ask] {
+                let change = OfferChange::Replaced { side, old: self.builder.book().side(side), new: cp.book.side(side) };
+                t.record(cp.seq, cp.recv_unix_ns, &change);
+            }
+        }
+        self.instrument = cp.instrument;
+        self.builder.reset(cp.book.clone());
+        self.last = Some((cp.seq, cp.recv_unix_ns));
+        self.stale = false;
+        self.observe_book();
+    }
+
+    /// Record lost events. A partial FullBook is dropped and the book stays
+    /// stale until the next snapshot.
+    pub fn apply_gap(&mut self) {
+        self.builder.abort_full_book();
+        self.stale = true;
+    }
+}
+
+/// Decides when to emit checkpoints, based on event receive time.
+#[derive(Debug, Clone)]
+pub struct CheckpointTimer {
+    interval_ns: u128,
+    /// Receive time of the last checkpoint (or of the first event seen).
+    last_ns: Option<u128>,
+}
+
+impl CheckpointTimer {
+    pub fn new(interval: Duration) -> Self {
+        CheckpointTimer { interval_ns: interval.as_nanos().max(1), last_ns: None }
+    }
+
+    /// Checkpoint to write after `replayer` applied its latest event, if one
+    /// is due and the book is not mid-FullBook.
+    pub fn poll(&mut self, replayer: &Replayer) -> Option<BookCheckpoint> {
+        let (_, now) = replayer.last?;
+        let last = *self.last_ns.get_or_insert(now);
+        if now.saturating_sub(last) < self.interval_ns { return None; }
+        let cp = replayer.checkpoint()?;
+        self.last_ns = Some(now);
+        Some(cp)
+    }
+}
+
+/// One [`Replayer`] and [`CheckpointTimer`] per instrument, created as
+/// instruments appear.
+#[derive(Debug, Clone)]
+pub struct Checkpointer {
+    interval: Duration,
+    books: BTreeMap<u32, (Replayer, CheckpointTimer)>,
+}
+
+impl Checkpointer {
+    pub fn new(interval: Duration) -> Self { Checkpointer { interval, books: BTreeMap::new() } }
+
+    /// Apply `ev` to its instrument's book. Returns a checkpoint of that book
+    /// if one is due.
+    pub fn apply_event(&mut self, ev: &EventRecord) -> Result<Option<BookCheckpoint>> {
+        if ev.instrument == NO_INSTRUMENT { return Ok(None); }
+        let (replay, timer) = self.books.entry(ev.instrument)
+            .or_insert_with(|| (Replayer::for_instrument(ev.instrument), CheckpointTimer::new(self.interval)));
+        replay.apply_event(ev)?;
+        Ok(timer.poll(replay))
+    }
+
+    /// Events of the instruments in `scope` were lost: their books are
+    /// stale until their next snapshot.
+    pub fn apply_gap(&mut self, scope: &GapScope) {
+        for (_, (replay, _)) in self.books.iter_mut().filter(|(id, _)| scope.covers(**id)) { replay.apply_gap(); }
+    }
+
+    /// Checkpoints of every book that can currently be checkpointed, e.g. to
+    /// start a new file.
+    pub fn checkpoints(&self) -> Vec<BookCheckpoint> {
+        self.books.values().filter_map(|(replay, _)| replay.checkpoint()).collect()
+    }
+}
+
+/// Copy a capture, replacing any existing checkpoint frames with fresh ones
+/// every `interval` of receive time, per instrument (a trailer is
+/// recomputed). Returns the flushed output writer and the number of
+/// checkpoints written.
+pub fn insert_checkpoints<R: std::io::Read, W: std::io::Write>(
+    reader: CaptureReader<R>,
+    out: W,
+    interval: Duration,
+) -> Result<(W, u64)> {
+    let mut w = CaptureWriter::new(out, reader.header().clone())?;
+    let mut books = Checkpointer::new(interval);
+    let mut written = 0u64;
+    for frame in reader {
+        let frame = frame?;
+        match &frame {
+            RecordFrame::Checkpoint(_) => continue,
+            RecordFrame::Trailer(t) => {
+                w.set_dropped_events(t.dropped_events);
+                w.write_trailer()?;
+            }
+            RecordFrame::Gap(GapMarker { scope, .. }) | RecordFrame::Resumed(ResumeMarker { scope, .. }) => {
+                books.apply_gap(scope);
+                w.write_frame(&frame)?;
+            }
+            RecordFrame::Event(ev) => {
+                w.write_frame(&frame)?;
+                if let Some(cp) = books.apply_event(ev)? {
+                    w.write_frame(&RecordFrame::Checkpoint(cp))?;
+                    written += 1;
+                }
+            }
+            _ => w.write_frame(&frame)?,
+        }
+    }
+    Ok((w.finish()?, written))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::testing::price_block;
+    use crate::record::testing::{full_book, header, offer_book};
+
+    fn add(seq: u64, side: i32, price: f64) -> EventRecord {
+        EventRecord { seq, instrument: 0, recv_unix_ns: seq as u128, recv_mono_ns_from_start: 0, kind: offer_book(0, side, 0, seq as i64, price) }
+    }
+
+    /// Complete FullBook with an empty ask side.
+    fn full(seq: u64, bids: &[f64]) -> EventRecord {
+        EventRecord { kind: full_book(Some(price_block(bids, true)), Some(price_block(&[], true))), ..add(seq, 0, 0.0) }
+    }
+
+    #[test]
+    fn checkpoint_restores_and_verifies() {
+        let mut a = Replayer::new();
+        assert!(a.checkpoint().is_none());
+        a.apply_event(&full(0, &[10.0])).unwrap();
+        a.apply_event(&add(1, 0, 9.0)).unwrap();
+        let cp = a.checkpoint().unwrap();
+        assert_eq!((cp.seq, cp.hash), (1, a.book().state_hash()));
+
+        let mut b = Replayer::new();
+        assert_eq!(b.apply_checkpoint(&cp), CheckpointOutcome::Restored);
+        for r in [&mut a, &mut b] { r.apply_event(&add(2, 1, 11.0)).unwrap(); }
+        assert_eq!(a.book(), b.book());
+        assert_eq!(b.last_seq(), Some(2));
+        assert_eq!(a.apply_checkpoint(&b.checkpoint().unwrap()), CheckpointOutcome::Verified);
+
+        b.apply_event(&add(3, 1, 12.0)).unwrap();
+        assert!(matches!(a.apply_checkpoint(&b.checkpoint().unwrap()), CheckpointOutcome::Mismatch { .. }));
+    }
+
+    #[test]
+    fn gap_makes_book_stale_until_checkpoint() {
+        let mut a = Replayer::new();
+        a.apply_event(&full(0, &[10.0])).unwrap();
+        let cp = a.checkpoint().unwrap();
+        a.apply_event(&add(1, 0, 9.0)).unwrap();
+        a.apply_gap();
+        assert!(a.is_stale() && a.checkpoint().is_none());
+        // A stale replayer adopts the next checkpoint instead of comparing
+        assert_eq!(a.apply_checkpoint(&cp), CheckpointOutcome::Restored);
+        assert!(!a.is_stale());
+        assert_eq!(a.book().buys.len(), 1);
+    }
+
+    #[test]
+    fn inserted_checkpoints_match_full_replay() {
+        let mut w = CaptureWriter::new(Vec::new(), header("T")).unwrap();
+        w.write_event(full(0, &[100.0])).unwrap();
+        for seq in 1..10 { w.write_event(add(seq, (seq % 2) as i32, 100.0 - seq as f64)).unwrap(); }
+        let src = w.finish().unwrap();
+
+        // add() receives event `seq` at t=seq ns: checkpoint every 3 ns
+        let (out, n) = insert_checkpoints(CaptureReader::new(src.as_slice()).unwrap(), Vec::new(), Duration::from_nanos(3)).unwrap();
+        assert_eq!(n, 3);
+        let mut full = Replayer::new();
+        let mut cps = Vec::new();
+        for f in CaptureReader::new(out.as_slice()).unwrap() {
+            match f.unwrap() {
+                RecordFrame::Event(ev) => full.apply_event(&ev).unwrap(),
+                RecordFrame::Checkpoint(cp) => {
+                    assert_eq!(full.apply_checkpoint(&cp), CheckpointOutcome::Verified);
+                    cps.push(cp.seq);
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(cps, vec![3, 6, 9]);
+    }
+
+    #[test]
+    fn capture_starting_mid_stream_is_stale_until_full_book() {
+        let mut w = CaptureWriter::new(Vec::new(), header("T")).unwrap();
+        for seq in 0..4 { w.write_event(add(seq, 0, 100.0 - seq as f64)).unwrap(); }
+        w.write_event(full(4, &[90.0])).unwrap();
+        w.write_event(add(5, 0, 89.0)).unwrap();
+        let src = w.finish().unwrap();
+
+        let mut r = Replayer::new();
+        r.apply_event(&add(0, 0, 100.0)).unwrap();
+        assert!(r.is_stale() && r.checkpoint().is_none());
+        let (out, n) = insert_checkpoints(CaptureReader::new(src.as_slice()).unwrap(), Vec::new(), Duration::from_nanos(1)).unwrap();
+        let seqs: Vec<_> = CaptureReader::new(out.as_slice()).unwrap().filter_map(|f| match f.unwrap() {
+            RecordFrame::Checkpoint(cp) => Some(cp.seq),
+            _ => None,
+        }).collect();
+        assert_eq!((n, seqs), (2, vec![4, 5]));
+    }
+
+    #[test]
+    fn views_replay_like_owned_events() {
+        use crate::record::{Encoding, FrameView};
+        let mut h = header("T");
+        h.set_encoding(Encoding::Fixed);
+        let mut w = CaptureWriter::new(Vec::new(), h).unwrap();
+        let mut owned = Replayer::new();
+        for seq in 0..8 {
+            let ev = add(seq, (seq % 2) as i32, 100.0 - seq as f64);
+            owned.apply_event(&ev).unwrap();
+            w.write_event(ev).unwrap();
+        }
+        let bytes = w.finish().unwrap();
+        let mut r = CaptureReader::new(bytes.as_slice()).unwrap();
+        let mut viewed = Replayer::new();
+        while let Some(frame) = r.read_view().unwrap() {
+            let FrameView::Event(ev) = frame else { panic!("expected a fixed-layout event") };
+            viewed.apply_view(&ev).unwrap();
+        }
+        assert_eq!(viewed.book(), owned.book());
+        assert_eq!(viewed.last_seq(), Some(7));
+    }
+
+    #[test]
+    fn checkpointer_keeps_one_book_per_instrument() {
+        let mut cps = Checkpointer::new(Duration::from_nanos(100));
+        let mut solo = Replayer::for_instrument(1);
+        for seq in 0..6 {
+            let ev = if seq < 2 { full(seq, &[]) } else { add(seq, 0, 100.0 - seq as f64) };
+            let ev = EventRecord { instrument: (seq % 2) as u32, ..ev };
+            if ev.instrument == 1 { solo.apply_event(&ev).unwrap(); }
+            cps.apply_event(&ev).unwrap();
+        }
+        let all = cps.checkpoints();
+        assert_eq!(all.iter().map(|cp| (cp.instrument, cp.seq)).collect::<Vec<_>>(), vec![(0, 4), (1, 5)]);
+        assert_eq!(all[1].book, *solo.book());
+        assert_eq!(solo.apply_checkpoint(&all[1]), CheckpointOutcome::Verified);
+    }
+}
@@ -0,0 +1,359 @@
+//! Rotated capture sets.
+//!
+//! A long recording can be split into parts by [`RotatingWriter`] according
+//! to a [`RotationPolicy`] (size, elapsed time, or session boundaries). Part
+//! 0 keeps the configured file name; part `n` is named `<stem>.NNN.<ext>`
+//! next to it (see [`part_path`]). Every part is a complete capture with its
+//! own header and trailer; later parts carry a
+//! [`HeaderExtension::Continuation`] naming the previous file and the last
+//! seq written to it.
+//!
+//! [`CaptureSet`] finds the parts of a set from its first file and
+//! [`ChainReader`] reads them back as one stream of frames, yielding each
+//! later part's header as a [`RecordFrame::Header`] where the part begins.
+use crate::index::CaptureIndex;
+use crate::record::{self, CaptureError, CaptureReader, CaptureWriter, FileHeader, HeaderExtension, RecordFrame, ResumeReport};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// When [`RotatingWriter`] starts a new part. All conditions are optional;
+/// the default never rotates.
+#[derive(Debug, Clone, Default)]
+pub struct RotationPolicy {
+    /// Rotate once the current part holds at least this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Rotate after this much time since the part was started.
+    pub interval: Option<Duration>,
+    /// Rotate when crossing one of these times of day (e.g. session
+    /// boundaries), interpreted at `utc_offset`.
+    pub session_times: Vec<time::Time>,
+    pub utc_offset: Option<time::UtcOffset>,
+}
+
+impl RotationPolicy {
+    /// Earliest time-based rotation instant for a part started at `start_ns`.
+    fn next_due_ns(&self, start_ns: u128) -> Option<u128> {
+        let by_interval = self.interval.map(|d| start_ns + d.as_nanos());
+        let offset = self.utc_offset.unwrap_or(time::UtcOffset::UTC);
+        let start = time::OffsetDateTime::from_unix_timestamp_nanos(start_ns as i128).ok()?.to_offset(offset);
+        let by_session = self.session_times.iter().filter_map(|&t| {
+            let at = start.replace_time(t);
+            let at = if at <= start { at + time::Duration::days(1) } else { at };
+            u128::try_from(at.unix_timestamp_nanos()).ok()
+        }).min();
+        by_interval.into_iter().chain(by_session).min()
+    }
+}
+
+/// Path of part `n` of the set whose first file is `base`:
+/// `captures/WIN.bin` -> `captures/WIN.001.bin`.
+pub fn part_path(base: impl AsRef<Path>, n: u32) -> PathBuf {
+    let base = base.as_ref();
+    if n == 0 { return base.to_path_buf(); }
+    let mut name = base.file_stem().unwrap_or_default().to_owned();
+    name.push(format!(".{n:03}"));
+    if let Some(ext) = base.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    base.with_file_name(name)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Capture writer that starts a new part whenever its policy says so.
+///
+/// Rotation is checked explicitly with [`RotatingWriter::rotate_if_due`]
+/// (the recorder calls it before each event), so callers can add frames such
+/// as a checkpoint at the start of a new part. With `index_every > 0` each
+/// part gets its own seek index sidecar.
+pub struct RotatingWriter {
+    base: PathBuf,
+    header: FileHeader,
+    policy: RotationPolicy,
+    index_every: u32,
+    part: u32,
+    path: PathBuf,
+    writer: CaptureWriter<BufWriter<File>>,
+    next_due_ns: Option<u128>,
+    last_seq: Option<u64>,
+    dropped_events: u64,
+}
+
+impl RotatingWriter {
+    /// Create part 0 at `base` with `header`.
+    pub fn create(base: impl Into<PathBuf>, header: FileHeader, policy: RotationPolicy, index_every: u32) -> Result<Self, CaptureError> {
+        let base = base.into();
+        let writer = Self::open_part(&base, header.clone(), index_every)?;
+        let next_due_ns = policy.next_due_ns(header.created_unix_ns);
+        Ok(RotatingWriter { path: base.clone(), base, header, policy, index_every, part: 0, writer, next_due_ns, last_seq: None, dropped_events: 0 })
+    }
+
+    /// Continue the set whose first file is `base` by reopening its last
+    /// part with [`record::resume`]. Later parts copy that part's header.
+    /// The caller should write a [`RecordFrame::Resumed`] marker next.
+    pub fn resume(base: impl Into<PathBuf>, policy: RotationPolicy, index_every: u32) -> Result<(Self, ResumeReport), CaptureError> {
+        let base = base.into();
+        let set = CaptureSet::discover(&base);
+        let part = set.parts().len() as u32 - 1;
+        let path = set.parts()[part as usize].clone();
+        let (writer, report) = record::resume(&path, index_every)?;
+        let mut header = writer.header().clone();
+        header.extensions.retain(|e| !matches!(e, HeaderExtension::Continuation { .. }));
+        header.set_instruments(report.instruments.clone());
+        let next_due_ns = policy.next_due_ns(header.created_unix_ns);
+        let dropped_events = report.trailer.as_ref().map_or(0, |t| t.dropped_events);
+        let w = RotatingWriter { base, header, policy, index_every, part, path, writer, next_due_ns, last_seq: report.last_seq, dropped_events };
+        Ok((w, report))
+    }
+
+    fn open_part(path: &Path, header: FileHeader, index_every: u32) -> Result<CaptureWriter<BufWriter<File>>, CaptureError> {
+        let mut w = CaptureWriter::create(path, header)?;
+        if index_every > 0 { w.enable_index(index_every); }
+        Ok(w)
+    }
+
+    /// Path of the part being written.
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Header of the part being written.
+    pub fn header(&self) -> &FileHeader { self.writer.header() }
+
+    /// Number of the part being written (0 for the first file).
+    pub fn part(&self) -> u32 { self.part }
+
+    /// Start a new part if the current one is over its size limit or
+    /// `now_unix_ns` is past its time limit. Returns `true` if it rotated.
+    pub fn rotate_if_due(&mut self, now_unix_ns: u128) -> Result<bool, CaptureError> {
+        // A part always holds at least one frame after its header
+        if self.writer.frames_written() <= 1 { return Ok(false); }
+        let by_size = self.policy.max_bytes.is_some_and(|max| self.writer.bytes_written() >= max);
+        let by_time = self.next_due_ns.is_some_and(|due| now_unix_ns >= due);
+        if !(by_size || by_time) { return Ok(false); }
+        self.rotate(now_unix_ns)?;
+        Ok(true)
+    }
+
+    /// Close the current part and start the next one.
+    pub fn rotate(&mut self, now_unix_ns: u128) -> Result<(), CaptureError> {
+        let part = self.part + 1;
+        let path = part_path(&self.base, part);
+        let mut header = self.header.clone();
+        header.created_unix_ns = now_unix_ns;
+        header.extensions.push(HeaderExtension::Continuation { part, previous: file_name(&self.path), last_seq: self.last_seq });
+        let next = Self::open_part(&path, header, self.index_every)?;
+        let prev = std::mem::replace(&mut self.writer, next);
+        Self::close(prev, &self.path, self.dropped_events)?;
+        self.part = part;
+        self.path = path;
+        self.next_due_ns = self.policy.next_due_ns(now_unix_ns);
+        Ok(())
+    }
+
+    /// Append one frame to the current part.
+    pub fn write_frame(&mut self, frame: &RecordFrame) -> Result<(), CaptureError> {
+        self.writer.write_frame(frame)?;
+        match frame {
+            RecordFrame::Event(ev) => self.last_seq = Some(ev.seq),
+            // Later parts list the instrument in their header table
+            RecordFrame::Instrument(i) => {
+                let mut table = self.header.instruments();
+                table.push(i.clone());
+                self.header.set_instruments(table);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Total events dropped before reaching the writer so far; reported in
+    /// the trailer of each part closed from now on.
+    pub fn set_dropped_events(&mut self, n: u64) { self.dropped_events = n; }
+
+    /// Write the trailer of the current part, flush it and write its index
+    /// sidecar.
+    pub fn finish(self) -> Result<(), CaptureError> { Self::close(self.writer, &self.path, self.dropped_events) }
+
+    fn close(mut w: CaptureWriter<BufWriter<File>>, path: &Path, dropped_events: u64) -> Result<(), CaptureError> {
+        w.set_dropped_events(dropped_events);
+        w.write_trailer()?;
+        let index = w.take_index();
+        w.finish()?;
+        if let Some(idx) = index { idx.save(CaptureIndex::sidecar_path(path))?; }
+        Ok(())
+    }
+}
+
+/// The files of a rotated set, in order.
+#[derive(Debug, Clone)]
+pub struct CaptureSet {
+    parts: Vec<PathBuf>,
+}
+
+impl CaptureSet {
+    /// `first` plus every existing `part_path(first, 1..)`, stopping at the
+    /// first missing part. A plain capture is a set of one.
+    pub fn discover(first: impl Into<PathBuf>) -> Self {
+        let first = first.into();
+        let mut parts = vec![first.clone()];
+        loop {
+            let next = part_path(&first, parts.len() as u32);
+            if !next.exists() { break; }
+            parts.push(next);
+        }
+        CaptureSet { parts }
+    }
+
+    pub fn parts(&self) -> &[PathBuf] { &self.parts }
+
+    /// Read the whole set from the start of part 0.
+    pub fn reader(&self) -> Result<ChainReader, CaptureError> {
+        let reader = CaptureReader::open(&self.parts[0])?;
+        Ok(ChainReader { parts: self.parts.clone(), part: 0, reader, unclean: Vec::new(), done: false })
+    }
+}
+
+/// Reads the parts of a [`CaptureSet`] as one stream of frames.
+///
+/// Part 0's header is available from [`ChainReader::header`]; the headers
+/// of later parts are yielded in-stream. Opening a part whose continuation
+/// does not name the previous file fails with [`CaptureError::BrokenChain`].
+/// Parts that end without a trailer are listed by
+/// [`ChainReader::unclean_parts`].
+pub struct ChainReader {
+    parts: Vec<PathBuf>,
+    part: usize,
+    reader: CaptureReader<BufReader<File>>,
+    unclean: Vec<PathBuf>,
+    done: bool,
+}
+
+impl ChainReader {
+    /// Header of the part being read.
+    pub fn header(&self) -> &FileHeader { self.reader.header() }
+
+    /// Index of the part being read.
+    pub fn part(&self) -> usize { self.part }
+
+    /// Path of the part being read.
+    pub fn path(&self) -> &Path { &self.parts[self.part] }
+
+    /// Parts read to their end that had no trailer (not closed cleanly).
+    pub fn unclean_parts(&self) -> &[PathBuf] { &self.unclean }
+
+    /// Position at frame `frame` (byte `offset`) of part `part`, e.g. from
+    /// that part's seek index.
+    pub fn seek_to(&mut self, part: usize, frame: u64, offset: u64) -> Result<(), CaptureError> {
+        if part != self.part { self.open(part)?; }
+        self.reader.seek_to(frame, offset)?;
+        self.done = false;
+        Ok(())
+    }
+
+    fn open(&mut self, part: usize) -> Result<(), CaptureError> {
+        let reader = CaptureReader::open(&self.parts[part])?;
+        if part > 0 {
+            let expected_previous = file_name(&self.parts[part - 1]);
+            if reader.header().continuation().map(|(_, prev, _)| prev) != Some(expected_previous.as_str()) {
+                return Err(CaptureError::BrokenChain { path: self.parts[part].clone(), expected_previous });
+            }
+        }
+        self.reader = reader;
+        self.part = part;
+        Ok(())
+    }
+}
+
+impl Iterator for ChainReader {
+    type Item = Result<RecordFrame, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done { return None; }
+        let res = match self.reader.read_frame() {
+            Ok(Some(f)) => return Some(Ok(f)),
+            Ok(None) => {
+                if self.reader.trailer().is_none() { self.unclean.push(self.parts[self.part].clone()); }
+                if self.part + 1 == self.parts.len() {
+                    self.done = true;
+                    return None;
+                }
+                self.open(self.part + 1)
+            }
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) => Some(Ok(RecordFrame::Header(self.reader.header().clone()))),
+            Err(e) => { self.done = true; Some(Err(e)) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::testing::header;
+    use crate::record::{EventKind, EventRecord};
+
+    fn state(seq: u64, recv_unix_ns: u128) -> RecordFrame {
+        RecordFrame::Event(EventRecord { seq, instrument: 0, recv_unix_ns, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 0, value: 0 } })
+    }
+
+    #[test]
+    fn part_names() {
+        assert_eq!(part_path("c/WIN.bin", 0), PathBuf::from("c/WIN.bin"));
+        assert_eq!(part_path("c/WIN.bin", 2), PathBuf::from("c/WIN.002.bin"));
+        assert_eq!(part_path("c/WIN", 1), PathBuf::from("c/WIN.001"));
+    }
+
+    #[test]
+    fn session_boundary_is_next_occurrence() {
+        let policy = RotationPolicy { session_times: vec![time::macros::time!(10:00)], ..Default::default() };
+        let day = 86_400 * 1_000_000_000u128;
+        let ten = 10 * 3_600 * 1_000_000_000u128;
+        assert_eq!(policy.next_due_ns(0), Some(ten));
+        assert_eq!(policy.next_due_ns(ten), Some(day + ten));
+    }
+
+    #[test]
+    fn rotated_set_reads_as_one_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("cap.bin");
+        let policy = RotationPolicy { interval: Some(Duration::from_nanos(10)), ..Default::default() };
+        let mut w = RotatingWriter::create(&base, header("T"), policy, 2).unwrap();
+        for seq in 0..25u64 {
+            w.rotate_if_due(seq as u128).unwrap();
+            w.write_frame(&state(seq, seq as u128)).unwrap();
+        }
+        assert_eq!(w.part(), 2);
+        w.finish().unwrap();
+
+        let set = CaptureSet::discover(&base);
+        assert_eq!(set.parts().len(), 3);
+        assert!(CaptureIndex::load_fresh(&set.parts()[2]).is_some());
+        let part1 = CaptureReader::open(&set.parts()[1]).unwrap();
+        assert_eq!(part1.header().continuation(), Some((1, "cap.bin", Some(9))));
+
+        let (mut seqs, mut headers, mut trailers) = (Vec::new(), 0, 0);
+        let mut rdr = set.reader().unwrap();
+        for f in rdr.by_ref() {
+            match f.unwrap() {
+                RecordFrame::Event(ev) => seqs.push(ev.seq),
+                RecordFrame::Header(_) => headers += 1,
+                RecordFrame::Trailer(_) => trailers += 1,
+                other => panic!("unexpected frame {other:?}"),
+            }
+        }
+        assert_eq!(seqs, (0..25).collect::<Vec<_>>());
+        assert_eq!((headers, trailers), (2, 3));
+        assert!(rdr.unclean_parts().is_empty());
+
+        // A part that does not continue its predecessor breaks the chain
+        CaptureWriter::create(&set.parts()[1], header("T")).unwrap().finish().unwrap();
+        let err = set.reader().unwrap().find_map(Result::err).unwrap();
+        assert!(matches!(err, CaptureError::BrokenChain { .. }));
+    }
+}
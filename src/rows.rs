@@ -0,0 +1,362 @@
+//! Flat rows of capture events for text exports.
+//!
+//! [`TradeRow`], [`BookEventRow`] and [`SnapshotRow`] turn normalized events
+//! (see [`crate::normalize`]) and reconstructed books into rows with stable
+//! column names, and [`RowWriter`] prints them as CSV (with a header row) or
+//! JSON Lines. Columns match the tables of the Arrow export, except that
+//! times are integer UNIX nanoseconds (`recv_unix_ns`, `server_unix_ns`).
+use crate::book::{Book, Side};
+use crate::normalize::{parse_server_time, NormalizedEvent, NormalizedKind, Trade};
+use std::io::Write;
+
+/// Output format of the player and the text exports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// Comma-separated values with a header row; one table per stream.
+    Csv,
+    /// One JSON object per line, tagged with its table.
+    Jsonl,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self { Format::Text => "text", Format::Csv => "csv", Format::Jsonl => "jsonl" })
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "json" => Ok(Format::Jsonl),
+            _ => Err(format!("unknown format {s:?} (expected text, csv or jsonl)")),
+        }
+    }
+}
+
+/// One field of a row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell<'a> {
+    Null,
+    UInt(u64),
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Str(&'a str),
+}
+
+/// Plain rendering: empty for null, strings unquoted.
+impl std::fmt::Display for Cell<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cell::Null => Ok(()),
+            Cell::UInt(v) => write!(f, "{v}"),
+            Cell::Int(v) => write!(f, "{v}"),
+            Cell::Float(v) => write!(f, "{v}"),
+            Cell::Bool(v) => write!(f, "{v}"),
+            Cell::Str(s) => f.write_str(s),
+        }
+    }
+}
+
+/// Columns shared by every row.
+pub const KEY_COLUMNS: [&str; 4] = ["seq", "instrument", "ticker", "recv_unix_ns"];
+
+/// Event identity at the start of every row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowKey {
+    pub seq: u64,
+    pub instrument: u32,
+    pub ticker: String,
+    pub recv_unix_ns: u128,
+}
+
+impl RowKey {
+    pub fn new(ev: &NormalizedEvent, ticker: &str) -> Self {
+        RowKey { seq: ev.seq, instrument: ev.instrument, ticker: ticker.to_string(), recv_unix_ns: ev.recv_unix_ns }
+    }
+
+    fn cells(&self) -> [Cell<'_>; 4] {
+        [Cell::UInt(self.seq), Cell::UInt(self.instrument as u64), Cell::Str(&self.ticker), Cell::Int(self.recv_unix_ns as i128)]
+    }
+}
+
+/// `NewTrade` or `HistoryTrade`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeRow<'a> {
+    pub key: RowKey,
+    pub trade: &'a Trade,
+}
+
+impl<'a> TradeRow<'a> {
+    pub const TABLE: &'static str = "trades";
+    pub const COLUMNS: [&'static str; 11] = [
+        "server_unix_ns", "date_str", "history", "trade_number", "price", "volume", "qty", "buy_agent", "sell_agent", "trade_type", "edit_flag",
+    ];
+
+    /// Row of `ev`, if it is a trade.
+    pub fn new(ev: &'a NormalizedEvent, ticker: &str) -> Option<Self> {
+        let NormalizedKind::Trade(trade) = &ev.kind else { return None };
+        Some(TradeRow { key: RowKey::new(ev, ticker), trade })
+    }
+
+    pub fn columns() -> Vec<String> { KEY_COLUMNS.iter().chain(&Self::COLUMNS).map(|c| c.to_string()).collect() }
+
+    pub fn cells(&self) -> Vec<Cell<'_>> {
+        let t = self.trade;
+        let mut cells = self.key.cells().to_vec();
+        cells.extend([
+            t.server_unix_ns.map_or(Cell::Null, Cell::Int),
+            Cell::Str(&t.date_str),
+            Cell::Bool(t.history),
+            Cell::UInt(t.trade_number as u64),
+            Cell::Float(t.price),
+            Cell::Float(t.volume),
+            Cell::Int(t.qty as i128),
+            Cell::Int(t.buy_agent as i128),
+            Cell::Int(t.sell_agent as i128),
+            Cell::Int(t.trade_type as i128),
+            Cell::UInt(t.edit_flag as u64),
+        ]);
+        cells
+    }
+}
+
+/// Book action, with the fields it does not carry left `None`. A FullBook
+/// row gives the size of each assembled side instead of entry fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookEventRow {
+    pub key: RowKey,
+    /// `add`, `edit`, `delete`, `delete_from` or `full_book`.
+    pub action: &'static str,
+    pub side: Option<Side>,
+    pub position: Option<i32>,
+    pub price: Option<f64>,
+    pub qty: Option<i64>,
+    pub agent: Option<i32>,
+    pub offer_id: Option<i64>,
+    pub date: Option<String>,
+    /// `date` parsed as a server time.
+    pub server_unix_ns: Option<i128>,
+    pub bids: Option<u32>,
+    pub asks: Option<u32>,
+    pub complete: Option<bool>,
+}
+
+impl BookEventRow {
+    pub const TABLE: &'static str = "book_events";
+    pub const COLUMNS: [&'static str; 12] = [
+        "action", "side", "position", "price", "qty", "agent", "offer_id", "date", "server_unix_ns", "bids", "asks", "complete",
+    ];
+
+    /// Row of `ev`, if it is a book event, with dates read at
+    /// `utc_offset_secs` from UTC.
+    pub fn new(ev: &NormalizedEvent, ticker: &str, utc_offset_secs: i32) -> Option<Self> {
+        let (action, side, position) = match &ev.kind {
+            NormalizedKind::Add { side, position, .. } => ("add", Some(*side), Some(*position)),
+            NormalizedKind::Edit { side, position, .. } => ("edit", Some(*side), Some(*position)),
+            NormalizedKind::Delete { side, position } => ("delete", Some(*side), Some(*position)),
+            NormalizedKind::DeleteFrom { side, position } => ("delete_from", Some(*side), Some(*position)),
+            NormalizedKind::FullBook { .. } => ("full_book", None, None),
+            NormalizedKind::Trade(_) | NormalizedKind::State { .. } => return None,
+        };
+        let (price, qty, agent, offer_id, date) = match &ev.kind {
+            NormalizedKind::Add { entry: e, .. } => (Some(e.price), Some(e.qty), Some(e.agent), Some(e.offer_id), e.date.clone()),
+            NormalizedKind::Edit { price, qty, agent, offer_id, date, .. } => (*price, *qty, *agent, *offer_id, date.clone().flatten()),
+            _ => (None, None, None, None, None),
+        };
+        let (bids, asks, complete) = match &ev.kind {
+            NormalizedKind::FullBook { bids, asks, complete } => {
+                (bids.as_ref().map(|b| b.len() as u32), asks.as_ref().map(|a| a.len() as u32), Some(*complete))
+            }
+            _ => (None, None, None),
+        };
+        let server_unix_ns = date.as_deref().and_then(|d| parse_server_time(d, utc_offset_secs));
+        Some(BookEventRow { key: RowKey::new(ev, ticker), action, side, position, price, qty, agent, offer_id, date, server_unix_ns, bids, asks, complete })
+    }
+
+    pub fn columns() -> Vec<String> { KEY_COLUMNS.iter().chain(&Self::COLUMNS).map(|c| c.to_string()).collect() }
+
+    pub fn cells(&self) -> Vec<Cell<'_>> {
+        let opt = |v: Option<i128>| v.map_or(Cell::Null, Cell::Int);
+        let mut cells = self.key.cells().to_vec();
+        cells.extend([
+            Cell::Str(self.action),
+            self.side.map_or(Cell::Null, |s| Cell::Str(side_name(s))),
+            opt(self.position.map(i128::from)),
+            self.price.map_or(Cell::Null, Cell::Float),
+            opt(self.qty.map(i128::from)),
+            opt(self.agent.map(i128::from)),
+            opt(self.offer_id.map(i128::from)),
+            self.date.as_deref().map_or(Cell::Null, Cell::Str),
+            opt(self.server_unix_ns),
+            self.bids.map_or(Cell::Null, |n| Cell::UInt(n as u64)),
+            self.asks.map_or(Cell::Null, |n| Cell::UInt(n as u64)),
+            self.complete.map_or(Cell::Null, Cell::Bool),
+        ]);
+        cells
+    }
+}
+
+/// Lower-case side name used in rows.
+pub fn side_name(side: Side) -> &'static str {
+    match side { Side::Bid => "bid", Side::Ask => "ask" }
+}
+
+/// Best `top` offers of each side after an event; levels past the book's
+/// depth are null.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotRow<'a> {
+    pub key: RowKey,
+    pub book: &'a Book,
+    pub top: usize,
+}
+
+impl<'a> SnapshotRow<'a> {
+    pub const TABLE: &'static str = "snapshots";
+
+    pub fn new(ev: &NormalizedEvent, ticker: &str, book: &'a Book, top: usize) -> Self {
+        SnapshotRow { key: RowKey::new(ev, ticker), book, top }
+    }
+
+    /// `bid_price_0`, `bid_qty_0`, ... `ask_qty_{top-1}` after the key columns.
+    pub fn columns(top: usize) -> Vec<String> {
+        let mut cols: Vec<String> = KEY_COLUMNS.iter().map(|c| c.to_string()).collect();
+        for side in ["bid", "ask"] {
+            for i in 0..top {
+                cols.push(format!("{side}_price_{i}"));
+                cols.push(format!("{side}_qty_{i}"));
+            }
+        }
+        cols
+    }
+
+    pub fn cells(&self) -> Vec<Cell<'_>> {
+        let mut cells = self.key.cells().to_vec();
+        for side in [&self.book.buys, &self.book.sells] {
+            for i in 0..self.top {
+                let e = side.get(i);
+                cells.push(e.map_or(Cell::Null, |e| Cell::Float(e.price)));
+                cells.push(e.map_or(Cell::Null, |e| Cell::Int(e.qty as i128)));
+            }
+        }
+        cells
+    }
+}
+
+/// Writes rows as CSV or JSON Lines.
+#[derive(Debug)]
+pub struct RowWriter<W: Write> {
+    out: W,
+    format: Format,
+}
+
+impl<W: Write> RowWriter<W> {
+    /// Writer of `format` rows; [`Format::Text`] is written like CSV.
+    pub fn new(out: W, format: Format) -> Self { RowWriter { out, format } }
+
+    /// Write the CSV header row (nothing for JSON Lines).
+    pub fn header(&mut self, columns: &[String]) -> std::io::Result<()> {
+        if self.format == Format::Jsonl { return Ok(()); }
+        let names: Vec<Cell> = columns.iter().map(|c| Cell::Str(c)).collect();
+        self.csv(&names)
+    }
+
+    /// Write one row of `table` with `columns` (JSON keys) and `cells`.
+    pub fn row(&mut self, table: &str, columns: &[String], cells: &[Cell]) -> std::io::Result<()> {
+        if self.format != Format::Jsonl { return self.csv(cells); }
+        write!(self.out, "{{\"table\":")?;
+        json_str(&mut self.out, table)?;
+        for (name, cell) in columns.iter().zip(cells) {
+            write!(self.out, ",")?;
+            json_str(&mut self.out, name)?;
+            write!(self.out, ":")?;
+            match cell {
+                Cell::Null => write!(self.out, "null")?,
+                Cell::Float(v) if !v.is_finite() => write!(self.out, "null")?,
+                Cell::Str(s) => json_str(&mut self.out, s)?,
+                cell => write!(self.out, "{cell}")?,
+            }
+        }
+        writeln!(self.out, "}}")
+    }
+
+    /// Flush and return the wrapped writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn csv(&mut self, cells: &[Cell]) -> std::io::Result<()> {
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 { write!(self.out, ",")?; }
+            match cell {
+                Cell::Str(s) if s.contains([',', '"', '\n', '\r']) => write!(self.out, "\"{}\"", s.replace('"', "\"\""))?,
+                cell => write!(self.out, "{cell}")?,
+            }
+        }
+        writeln!(self.out)
+    }
+}
+
+/// Write `s` as a JSON string literal.
+fn json_str<W: Write>(out: &mut W, s: &str) -> std::io::Result<()> {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    write!(out, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::Entry;
+
+    fn event(kind: NormalizedKind) -> NormalizedEvent {
+        NormalizedEvent { seq: 3, instrument: 0, recv_unix_ns: 1_000, recv_mono_ns_from_start: 0, kind }
+    }
+
+    #[test]
+    fn writes_csv_and_json_lines() {
+        let trade = event(NormalizedKind::Trade(Trade {
+            history: false, server_unix_ns: None, date_str: "04/09/2025 \"10:15\"".into(), trade_number: 1,
+            price: 10.5, volume: 21.0, qty: 2, buy_agent: 1, sell_agent: 2, trade_type: 3, edit_flag: 0,
+        }));
+        let row = TradeRow::new(&trade, "WIN,FUT").unwrap();
+        let mut w = RowWriter::new(Vec::new(), Format::Csv);
+        w.header(&TradeRow::columns()).unwrap();
+        w.row(TradeRow::TABLE, &TradeRow::columns(), &row.cells()).unwrap();
+        let csv = String::from_utf8(w.finish().unwrap()).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "seq,instrument,ticker,recv_unix_ns,server_unix_ns,date_str,history,trade_number,price,volume,qty,buy_agent,sell_agent,trade_type,edit_flag");
+        assert_eq!(lines[1], "3,0,\"WIN,FUT\",1000,,\"04/09/2025 \"\"10:15\"\"\",false,1,10.5,21,2,1,2,3,0");
+
+        let add = event(NormalizedKind::Add { side: Side::Ask, position: 0, entry: Entry { price: 11.0, qty: 4, agent: 9, offer_id: 77, date: None } });
+        let row = BookEventRow::new(&add, "WINFUT", 0).unwrap();
+        let mut w = RowWriter::new(Vec::new(), Format::Jsonl);
+        w.header(&BookEventRow::columns()).unwrap();
+        w.row(BookEventRow::TABLE, &BookEventRow::columns(), &row.cells()).unwrap();
+        let json = String::from_utf8(w.finish().unwrap()).unwrap();
+        assert_eq!(json, concat!(
+            r#"{"table":"book_events","seq":3,"instrument":0,"ticker":"WINFUT","recv_unix_ns":1000,"action":"add","side":"ask","position":0,"#,
+            r#""price":11,"qty":4,"agent":9,"offer_id":77,"date":null,"server_unix_ns":null,"bids":null,"asks":null,"complete":null}"#,
+            "\n",
+        ));
+        assert!(TradeRow::new(&add, "WINFUT").is_none());
+    }
+}
@@ -0,0 +1,285 @@
+//! Cutting a window out of a capture.
+//!
+//! [`slice`] replays a capture up to the start of the window, then writes a
+//! checkpoint of every instrument's reconstructed book followed by the
+//! window's frames, so the slice replays to the same book as the original at
+//! every event inside it. An instrument that is in the middle of a
+//! multi-packet FullBook at the cut gets the checkpoint from before that
+//! FullBook plus the packets already received, so the snapshot completes
+//! exactly as it did in the original. Normalized captures (see
+//! [`crate::normalize`]) slice the same way.
+//!
+//! Bounds are half-open: the window holds the events from the first one
+//! that reaches `start` up to, not including, the first one that reaches
+//! `end`. Seq bounds assume seqs increase through the file, which holds for
+//! recorder output but not for merged captures (see [`crate::merge`]).
+use crate::normalize::NormalizedKind;
+use crate::record::{BookCheckpoint, CaptureReader, CaptureWriter, EventKind, EventRecord, GapMarker, RecordFrame, ResumeMarker, NO_INSTRUMENT};
+use crate::replay::Replayer;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Window edge, by event seq or receive time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Seq(u64),
+    /// `recv_unix_ns`.
+    Time(u128),
+}
+
+impl Bound {
+    /// `true` once `ev` is at or after this bound.
+    pub fn reached(&self, ev: &EventRecord) -> bool { self.reached_at(ev.seq, ev.recv_unix_ns) }
+
+    /// `true` once an event with this seq and receive time is at or after
+    /// this bound.
+    pub fn reached_at(&self, seq: u64, recv_unix_ns: u128) -> bool {
+        match *self {
+            Bound::Seq(s) => seq >= s,
+            Bound::Time(t) => recv_unix_ns >= t,
+        }
+    }
+}
+
+/// Parse a time as UNIX nanoseconds, or as local wall-clock `HH:MM[:SS]` on
+/// the day `created_unix_ns` falls on.
+pub fn parse_time(s: &str, created_unix_ns: u128) -> Result<u128> {
+    if let Ok(ns) = s.parse::<u128>() { return Ok(ns); }
+    let parts = s.split(':').map(|p| p.parse::<u8>()).collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid time {s:?}"))?;
+    let (h, m, sec) = match parts[..] {
+        [h, m] => (h, m, 0),
+        [h, m, sec] => (h, m, sec),
+        _ => bail!("invalid time {s:?}; expected HH:MM[:SS] or UNIX ns"),
+    };
+    let offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+    let created = time::OffsetDateTime::from_unix_timestamp_nanos(created_unix_ns as i128)?.to_offset(offset);
+    let at = created.replace_time(time::Time::from_hms(h, m, sec)?);
+    Ok(at.unix_timestamp_nanos().max(0) as u128)
+}
+
+/// Summary of a [`slice`] run.
+#[derive(Debug, Clone, Default)]
+pub struct SliceReport {
+    /// Checkpoints written at the start of the slice.
+    pub checkpoints: u64,
+    /// FullBook packets copied from before the window to complete a
+    /// snapshot in progress at the cut.
+    pub lead_in: u64,
+    /// Events copied from the window.
+    pub events: u64,
+    /// Instruments whose book could not be reconstructed at the cut (stale
+    /// after a gap); they replay from their next FullBook or checkpoint.
+    pub unsynced: Vec<u32>,
+}
+
+/// Replay state of one instrument before the window.
+#[derive(Debug, Default)]
+struct Lead {
+    replay: Replayer,
+    /// Checkpoint from just before the FullBook in progress, and the
+    /// packets of that FullBook received so far.
+    full_book: Option<(Option<BookCheckpoint>, Vec<RecordFrame>)>,
+}
+
+/// Instrument, seq and receive time of a raw or normalized event frame.
+fn event_of(frame: &RecordFrame) -> Option<(u32, u64, u128)> {
+    match frame {
+        RecordFrame::Event(ev) => Some((ev.instrument, ev.seq, ev.recv_unix_ns)),
+        RecordFrame::Normalized(ev) => Some((ev.instrument, ev.seq, ev.recv_unix_ns)),
+        _ => None,
+    }
+}
+
+/// `Some(true)` for a FullBook packet, `Some(false)` for another book event.
+fn is_full_book(frame: &RecordFrame) -> Option<bool> {
+    match frame {
+        RecordFrame::Event(ev) => match ev.kind {
+            EventKind::OfferBookV2 { n_action, .. } => Some(n_action == 4),
+            _ => None,
+        },
+        RecordFrame::Normalized(ev) => match ev.kind {
+            NormalizedKind::FullBook { .. } => Some(true),
+            NormalizedKind::Trade(_) | NormalizedKind::State { .. } => None,
+            _ => Some(false),
+        },
+        _ => None,
+    }
+}
+
+/// Copy the events of `reader` from `start` up to `end` (or the end of the
+/// file) into a new capture, preceded by checkpoints of the books at the
+/// cut. The slice gets a fresh trailer. Returns the flushed output writer
+/// and a report.
+pub fn slice<R: Read, W: Write>(mut reader: CaptureReader<R>, out: W, start: Bound, end: Option<Bound>) -> Result<(W, SliceReport)> {
+    let mut header = reader.header().clone();
+    let mut instruments = header.instruments();
+    let mut leads: BTreeMap<u32, Lead> = BTreeMap::new();
+    let mut report = SliceReport::default();
+    // Replay up to the first event in the window
+    let first = loop {
+        let Some(frame) = reader.read_frame()? else { break None };
+        if let Some((instrument, seq, recv_unix_ns)) = event_of(&frame) {
+            if start.reached_at(seq, recv_unix_ns) { break Some(frame); }
+            if instrument == NO_INSTRUMENT { continue; }
+            let lead = leads.entry(instrument).or_insert_with(|| Lead { replay: Replayer::for_instrument(instrument), full_book: None });
+            match is_full_book(&frame) {
+                Some(true) => {
+                    if lead.full_book.is_none() { lead.full_book = Some((lead.replay.checkpoint(), Vec::new())); }
+                    if let Some((_, packets)) = &mut lead.full_book { packets.push(frame.clone()); }
+                }
+                Some(false) => lead.full_book = None,
+                None => {}
+            }
+            match &frame {
+                RecordFrame::Event(ev) => lead.replay.apply_event(ev)?,
+                RecordFrame::Normalized(ev) => lead.replay.apply_normalized(ev),
+                _ => unreachable!("event_of accepts events only"),
+            }
+            if lead.replay.is_consistent() { lead.full_book = None; }
+            continue;
+        }
+        match frame {
+            RecordFrame::Checkpoint(cp) => {
+                let lead = leads.entry(cp.instrument).or_default();
+                lead.replay.apply_checkpoint(&cp);
+                lead.full_book = None;
+            }
+            RecordFrame::Gap(GapMarker { scope, .. }) | RecordFrame::Resumed(ResumeMarker { scope, .. }) => {
+                for (_, lead) in leads.iter_mut().filter(|(id, _)| scope.covers(**id)) {
+                    lead.replay.apply_gap();
+                    lead.full_book = None;
+                }
+            }
+            RecordFrame::Instrument(i) => instruments.push(i),
+            _ => {}
+        }
+    };
+    header.set_instruments(instruments);
+    let mut w = CaptureWriter::new(out, header)?;
+    let Some(first) = first else {
+        w.write_trailer()?;
+        return Ok((w.finish()?, report));
+    };
+    let reached_end = |frame: &RecordFrame| event_of(frame).is_some_and(|(_, seq, t)| end.is_some_and(|e| e.reached_at(seq, t)));
+    if reached_end(&first) { bail!("the window is empty: its end is not after its start"); }
+
+    for (&id, lead) in &leads {
+        let (checkpoint, packets) = match lead.full_book.clone() {
+            Some((before, packets)) => (before, packets),
+            None => (lead.replay.checkpoint(), Vec::new()),
+        };
+        if checkpoint.is_none() && packets.is_empty() {
+            report.unsynced.push(id);
+            continue;
+        }
+        if let Some(cp) = checkpoint {
+            w.write_frame(&RecordFrame::Checkpoint(cp))?;
+            report.checkpoints += 1;
+        }
+        for frame in packets {
+            w.write_frame(&frame)?;
+            report.lead_in += 1;
+        }
+    }
+
+    let mut next = Some(first);
+    while let Some(frame) = next {
+        if reached_end(&frame) { break; }
+        match frame {
+            RecordFrame::Trailer(_) => {}
+            frame => {
+                if event_of(&frame).is_some() { report.events += 1; }
+                w.write_frame(&frame)?;
+            }
+        }
+        next = reader.read_frame()?;
+    }
+    w.write_trailer()?;
+    Ok((w.finish()?, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::testing::price_block;
+    use crate::record::testing::{full_book, header, offer_book};
+
+    /// Event of instrument 0 for even seqs, 1 for odd ones.
+    fn ev(seq: u64, kind: EventKind) -> EventRecord {
+        EventRecord { seq, instrument: (seq % 2) as u32, recv_unix_ns: seq as u128, recv_mono_ns_from_start: 0, kind }
+    }
+
+    /// Books of every instrument after each event, keyed by seq.
+    fn books_by_seq(bytes: &[u8]) -> BTreeMap<u64, crate::book::Book> {
+        let mut replays: BTreeMap<u32, Replayer> = BTreeMap::new();
+        let mut out = BTreeMap::new();
+        for f in CaptureReader::new(bytes).unwrap() {
+            match f.unwrap() {
+                RecordFrame::Event(ev) => {
+                    let r = replays.entry(ev.instrument).or_default();
+                    r.apply_event(&ev).unwrap();
+                    out.insert(ev.seq, r.book().clone());
+                }
+                RecordFrame::Normalized(ev) => {
+                    let r = replays.entry(ev.instrument).or_default();
+                    r.apply_normalized(&ev);
+                    out.insert(ev.seq, r.book().clone());
+                }
+                RecordFrame::Checkpoint(cp) => { replays.entry(cp.instrument).or_default().apply_checkpoint(&cp); }
+                _ => {}
+            }
+        }
+        out
+    }
+
+    fn capture() -> Vec<u8> {
+        let mut w = CaptureWriter::new(Vec::new(), header("T")).unwrap();
+        // Both instruments start from a FullBook. Instrument 0 (even seqs) then
+        // adds levels; instrument 1 (odd seqs) is in the middle of a
+        // three-packet FullBook when the window opens at seq 6
+        for seq in 0..12 {
+            let ev = match seq {
+                0 => ev(0, full_book(Some(price_block(&[100.0], true)), None)),
+                1 => ev(1, full_book(Some(price_block(&[61.0], true)), None)),
+                3 => ev(3, full_book(Some(price_block(&[60.0, 59.0], false)), None)),
+                5 => ev(5, full_book(Some(price_block(&[58.0], false)), None)),
+                7 => ev(7, full_book(Some(price_block(&[57.0], true)), None)),
+                _ => ev(seq, offer_book(0, 0, 0, seq as i64, 100.0 - seq as f64)),
+            };
+            w.write_event(ev).unwrap();
+        }
+        w.write_trailer().unwrap();
+        w.finish().unwrap()
+    }
+
+    #[test]
+    fn slice_replays_like_the_original_inside_the_window() {
+        let src = capture();
+        let (out, report) = slice(CaptureReader::new(src.as_slice()).unwrap(), Vec::new(), Bound::Seq(6), Some(Bound::Time(10))).unwrap();
+        assert_eq!((report.checkpoints, report.lead_in, report.events), (2, 2, 4));
+        assert!(report.unsynced.is_empty());
+        let original = books_by_seq(&src);
+        let sliced = books_by_seq(&out);
+        for seq in 6..10 { assert_eq!(sliced[&seq], original[&seq], "book after seq {seq}"); }
+        assert!(!sliced.contains_key(&10));
+        let mut r = CaptureReader::new(out.as_slice()).unwrap();
+        assert_eq!(r.by_ref().count(), 9);
+        assert_eq!(r.trailer().unwrap().counts.offer_book, 6);
+    }
+
+    #[test]
+    fn normalized_captures_slice_like_raw_ones() {
+        let (src, _) = crate::normalize::normalize(CaptureReader::new(capture().as_slice()).unwrap(), Vec::new(), "src.bin", 0).unwrap();
+        let (out, report) = slice(CaptureReader::new(src.as_slice()).unwrap(), Vec::new(), Bound::Seq(6), Some(Bound::Time(10))).unwrap();
+        // The normalizer writes the FullBook once it completes, so there are
+        // no packets to lead in
+        assert_eq!((report.checkpoints, report.lead_in, report.events), (2, 0, 4));
+        let original = books_by_seq(&src);
+        let sliced = books_by_seq(&out);
+        for seq in 6..10 { assert_eq!(sliced[&seq], original[&seq], "book after seq {seq}"); }
+        let r = CaptureReader::new(out.as_slice()).unwrap();
+        assert!(r.header().normalized().is_some());
+    }
+}
@@ -1,33 +1,20 @@
-use crc32fast::Hasher as Crc32;
-use market_data::book::{parse_block_v2, Book, Entry, OB_LAST_PACKET};
-use market_data::record::{EventKind, EventRecord, FileHeader, RawArrayBlock, RecordFrame};
+use market_data::book::{BookBuilder, Entry, OB_LAST_PACKET};
+use market_data::record::{CaptureError, CaptureReader, CaptureWriter, EventKind, EventRecord, FileHeader, RawArrayBlock, RecordFrame, FORMAT_VERSION};
 use std::fs::File;
-use std::io::{BufWriter, Write, Read, BufReader};
-
-fn write_frame(w: &mut BufWriter<File>, frame: &RecordFrame) {
-    let payload = bincode::serialize(frame).unwrap();
-    let mut hasher = Crc32::new(); hasher.update(&payload); let crc = hasher.finalize();
-    let len = payload.len() as u32;
-    w.write_all(&len.to_le_bytes()).unwrap();
-    w.write_all(&crc.to_le_bytes()).unwrap();
-    w.write_all(&payload).unwrap();
-}
+use std::io::{BufWriter, Write};
 
 #[test]
 fn end_to_end_reconstruct_small_book() {
     let dir = tempfile::tempdir().unwrap();
     let path = dir.path().join("test.bin");
-    let f = File::create(&path).unwrap();
-    let mut w = BufWriter::new(f);
-
-    // Header
-    write_frame(&mut w, &RecordFrame::Header(FileHeader {
-        version: 1,
+    let mut w = CaptureWriter::create(&path, FileHeader {
+        version: FORMAT_VERSION,
         created_unix_ns: 0,
         ticker: "TST".into(),
         exchange: "X".into(),
         server_clock_offset_ms: 0,
-    }));
+        extensions: Vec::new(),
+    }).unwrap();
 
     // Full book: buys [101, 100], sells [102, 103]
     let buys = vec![
@@ -62,8 +49,9 @@ fn end_to_end_reconstruct_small_book() {
     let (buy_block, _) = (make_block(&buys), OB_LAST_PACKET);
     let (sell_block, _) = (make_block(&sells), OB_LAST_PACKET);
 
-    write_frame(&mut w, &RecordFrame::Event(EventRecord {
+    w.write_event(EventRecord {
         seq: 0,
+        instrument: 0,
         recv_unix_ns: 0,
         recv_mono_ns_from_start: 0,
         kind: EventKind::OfferBookV2 {
@@ -83,11 +71,12 @@ fn end_to_end_reconstruct_small_book() {
             array_sell: Some(sell_block),
             array_buy: Some(buy_block),
         },
-    }));
+    }).unwrap();
 
     // atAdd: add a worse bid (nPosition=0 from end => insert after worst)
-    write_frame(&mut w, &RecordFrame::Event(EventRecord {
+    w.write_event(EventRecord {
         seq: 1,
+        instrument: 0,
         recv_unix_ns: 0,
         recv_mono_ns_from_start: 0,
         kind: EventKind::OfferBookV2 {
@@ -107,46 +96,26 @@ fn end_to_end_reconstruct_small_book() {
             array_sell: None,
             array_buy: None,
         },
-    }));
+    }).unwrap();
 
-    w.flush().unwrap(); drop(w);
+    w.finish().unwrap();
 
-    // Read and reconstruct using the same logic as player
-    let f = File::open(&path).unwrap();
-    let mut r = BufReader::new(f);
-    let mut book = Book::default();
-    let mut pend_buy: Vec<Entry> = Vec::new();
-    let mut pend_sell: Vec<Entry> = Vec::new();
+    // Read and reconstruct as the player does
+    let mut r = CaptureReader::open(&path).unwrap();
+    assert_eq!(r.header().ticker, "TST");
+    let mut builder = BookBuilder::new();
 
-    // helper read u32
-    let read_u32 = |r: &mut BufReader<File>| -> u32 { let mut b=[0u8;4]; r.read_exact(&mut b).unwrap(); u32::from_le_bytes(b) };
-
-    // header
-    let len = read_u32(&mut r) as usize; let _crc = read_u32(&mut r); let mut p = vec![0u8;len]; r.read_exact(&mut p).unwrap();
-    let _h: RecordFrame = bincode::deserialize(&p).unwrap();
-
-    // full book
-    let len = read_u32(&mut r) as usize; let _crc = read_u32(&mut r); let mut p = vec![0u8;len]; r.read_exact(&mut p).unwrap();
-    let fr: RecordFrame = bincode::deserialize(&p).unwrap();
-    if let RecordFrame::Event(ev) = fr {
-        if let EventKind::OfferBookV2 { array_buy, array_sell, n_action, .. } = ev.kind {
-            assert_eq!(n_action, 4);
-            let (mut b, fb) = parse_block_v2(&array_buy.unwrap()).unwrap(); assert_eq!(fb & OB_LAST_PACKET, OB_LAST_PACKET); pend_buy.append(&mut b); book.apply_full(Some(pend_buy.drain(..).collect()), None);
-            let (mut s, fs) = parse_block_v2(&array_sell.unwrap()).unwrap(); assert_eq!(fs & OB_LAST_PACKET, OB_LAST_PACKET); pend_sell.append(&mut s); book.apply_full(None, Some(pend_sell.drain(..).collect()));
-        } else { panic!("unexpected kind"); }
-    } else { panic!("unexpected frame"); }
+    // full book, both sides in one packet
+    let Some(Ok(RecordFrame::Event(ev))) = r.next() else { panic!("unexpected frame") };
+    assert!(builder.apply(&ev.kind).unwrap(), "single-packet FullBook completes the snapshot");
 
     // add
-    let len = read_u32(&mut r) as usize; let _crc = read_u32(&mut r); let mut p = vec![0u8;len]; r.read_exact(&mut p).unwrap();
-    let fr: RecordFrame = bincode::deserialize(&p).unwrap();
-    if let RecordFrame::Event(ev) = fr {
-    if let EventKind::OfferBookV2 { n_action, n_side, n_position, d_price, n_qtd, n_agent, n_offer_id, has_price: _ , has_qtd: _ , has_agent: _ , has_offer_id: _ , has_date: _ , date_str, .. } = ev.kind {
-            assert_eq!(n_action, 0);
-            book.apply_add(n_side, n_position, Entry { price: d_price, qty: n_qtd, agent: n_agent, offer_id: n_offer_id, date: date_str });
-        } else { panic!("unexpected kind"); }
-    } else { panic!("unexpected frame"); }
+    let Some(Ok(RecordFrame::Event(ev))) = r.next() else { panic!("unexpected frame") };
+    assert!(!builder.apply(&ev.kind).unwrap());
+    assert!(r.next().is_none());
 
     // final assertions
+    let book = builder.book();
     assert_eq!(book.buys.len(), 3);
     assert!((book.buys[0].price - 101.0).abs() < 1e-9);
     assert!((book.buys[2].price - 99.5).abs() < 1e-9);
@@ -160,7 +129,7 @@ fn crc_mismatch_detected() {
     let path = dir.path().join("bad.bin");
     let mut f = BufWriter::new(File::create(&path).unwrap());
     // write one frame with wrong CRC
-    let fr = RecordFrame::Header(FileHeader { version:1, created_unix_ns:0, ticker:"X".into(), exchange:"Y".into(), server_clock_offset_ms:0 });
+    let fr = RecordFrame::Header(FileHeader { version:FORMAT_VERSION, created_unix_ns:0, ticker:"X".into(), exchange:"Y".into(), server_clock_offset_ms:0, extensions: Vec::new() });
     let payload = bincode::serialize(&fr).unwrap();
     let bad_crc = 0xDEADBEEFu32;
     let len = payload.len() as u32;
@@ -169,23 +138,47 @@ fn crc_mismatch_detected() {
     f.write_all(&payload).unwrap();
     f.flush().unwrap();
 
-    let mut r = BufReader::new(File::open(&path).unwrap());
-    let mut b=[0u8;4];
-    r.read_exact(&mut b).unwrap(); let len = u32::from_le_bytes(b) as usize;
-    r.read_exact(&mut b).unwrap(); let crc_file = u32::from_le_bytes(b);
-    let mut payload = vec![0u8; len]; r.read_exact(&mut payload).unwrap();
-    let mut hasher = Crc32::new(); hasher.update(&payload); let crc_calc = hasher.finalize();
-    assert_ne!(crc_calc, crc_file, "CRC should mismatch");
+    match CaptureReader::open(&path) {
+        Err(CaptureError::CrcMismatch { frame, offset, expected, actual }) => {
+            assert_eq!((frame, offset), (0, 0));
+            assert_eq!(expected, bad_crc);
+            assert_ne!(actual, bad_crc, "CRC should mismatch");
+        }
+        other => panic!("expected CRC mismatch, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn truncated_tail_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trunc.bin");
+    let mut w = CaptureWriter::create(&path, FileHeader { version:FORMAT_VERSION, created_unix_ns:0, ticker:"T".into(), exchange:"X".into(), server_clock_offset_ms:0, extensions: Vec::new() }).unwrap();
+    w.write_event(EventRecord { seq: 0, instrument: 0, recv_unix_ns: 0, recv_mono_ns_from_start: 0, kind: EventKind::State { state_type: 1, value: 2 } }).unwrap();
+    w.finish().unwrap();
+    let full = std::fs::metadata(&path).unwrap().len();
+    // Chop the last frame in half, as if the recorder was killed mid-write
+    let f = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    f.set_len(full - 3).unwrap();
+    drop(f);
+
+    let mut r = CaptureReader::open(&path).unwrap();
+    let header_len = r.offset();
+    match r.next() {
+        Some(Err(CaptureError::Truncated { frame, offset, needed, available })) => {
+            assert_eq!(frame, 1);
+            assert_eq!(offset, header_len);
+            assert_eq!(needed - available, 3);
+        }
+        other => panic!("expected truncated frame, got {:?}", other),
+    }
+    assert!(r.next().is_none(), "reader is fused after an error");
 }
 
 #[test]
 fn fullbook_multipacket_accumulation() {
     let dir = tempfile::tempdir().unwrap();
     let path = dir.path().join("multi.bin");
-    let mut w = BufWriter::new(File::create(&path).unwrap());
-
-    // Header
-    write_frame(&mut w, &RecordFrame::Header(FileHeader { version:1, created_unix_ns:0, ticker:"T".into(), exchange:"X".into(), server_clock_offset_ms:0 }));
+    let mut w = CaptureWriter::create(&path, FileHeader { version:FORMAT_VERSION, created_unix_ns:0, ticker:"T".into(), exchange:"X".into(), server_clock_offset_ms:0, extensions: Vec::new() }).unwrap();
 
     // Two packets for buys and sells each
     let mk = |p: f64| Entry { price: p, qty: 1, agent: 1, offer_id: p as i64, date: None };
@@ -217,50 +210,39 @@ fn fullbook_multipacket_accumulation() {
     };
 
     // First FullBook frame: buy packet 1, sell packet 1 (both not last)
-    write_frame(&mut w, &RecordFrame::Event(EventRecord {
-        seq: 0, recv_unix_ns: 0, recv_mono_ns_from_start: 0,
+    w.write_event(EventRecord {
+        seq: 0, instrument: 0, recv_unix_ns: 0, recv_mono_ns_from_start: 0,
         kind: EventKind::OfferBookV2 { n_action: 4, n_position:0, n_side:0, n_qtd:0, n_agent:0, n_offer_id:0, d_price:0.0,
             has_price:false, has_qtd:false, has_date:false, has_offer_id:false, has_agent:false, date_str: None,
             array_sell: Some(make_block(&s1, false)), array_buy: Some(make_block(&b1, false)) }
-    }));
+    }).unwrap();
 
     // Second FullBook frame: buy packet 2 (last), sell packet 2 (last)
-    write_frame(&mut w, &RecordFrame::Event(EventRecord {
-        seq: 1, recv_unix_ns: 0, recv_mono_ns_from_start: 0,
+    w.write_event(EventRecord {
+        seq: 1, instrument: 0, recv_unix_ns: 0, recv_mono_ns_from_start: 0,
         kind: EventKind::OfferBookV2 { n_action: 4, n_position:0, n_side:0, n_qtd:0, n_agent:0, n_offer_id:0, d_price:0.0,
             has_price:false, has_qtd:false, has_date:false, has_offer_id:false, has_agent:false, date_str: None,
             array_sell: Some(make_block(&s2, true)), array_buy: Some(make_block(&b2, true)) }
-    }));
+    }).unwrap();
 
-    w.flush().unwrap(); drop(w);
+    w.finish().unwrap();
 
     // Read and accumulate
-    let f = File::open(&path).unwrap();
-    let mut r = BufReader::new(f);
-    let mut book = Book::default();
-    let mut pend_buy: Vec<Entry> = Vec::new();
-    let mut pend_sell: Vec<Entry> = Vec::new();
-
-    let read_u32 = |r: &mut BufReader<File>| -> u32 { let mut b=[0u8;4]; r.read_exact(&mut b).unwrap(); u32::from_le_bytes(b) };
-    // header
-    let len = read_u32(&mut r) as usize; let _ = read_u32(&mut r); let mut p = vec![0u8;len]; r.read_exact(&mut p).unwrap(); let _ : RecordFrame = bincode::deserialize(&p).unwrap();
-    // fb1
-    let len = read_u32(&mut r) as usize; let _ = read_u32(&mut r); let mut p = vec![0u8;len]; r.read_exact(&mut p).unwrap();
-    let fr: RecordFrame = bincode::deserialize(&p).unwrap();
-    if let RecordFrame::Event(ev) = fr { if let EventKind::OfferBookV2 { array_buy, array_sell, n_action, .. } = ev.kind {
-        assert_eq!(n_action, 4);
-        let (mut b, fb) = parse_block_v2(&array_buy.unwrap()).unwrap(); assert_eq!(fb & OB_LAST_PACKET, 0); pend_buy.append(&mut b);
-        let (mut s, fs) = parse_block_v2(&array_sell.unwrap()).unwrap(); assert_eq!(fs & OB_LAST_PACKET, 0); pend_sell.append(&mut s);
-    } }
-    // fb2
-    let len = read_u32(&mut r) as usize; let _ = read_u32(&mut r); let mut p = vec![0u8;len]; r.read_exact(&mut p).unwrap();
-    let fr: RecordFrame = bincode::deserialize(&p).unwrap();
-    if let RecordFrame::Event(ev) = fr { if let EventKind::OfferBookV2 { array_buy, array_sell, .. } = ev.kind {
-        let (mut b, fb) = parse_block_v2(&array_buy.unwrap()).unwrap(); assert_eq!(fb & OB_LAST_PACKET, OB_LAST_PACKET); pend_buy.append(&mut b); book.apply_full(Some(pend_buy.drain(..).collect()), None);
-        let (mut s, fs) = parse_block_v2(&array_sell.unwrap()).unwrap(); assert_eq!(fs & OB_LAST_PACKET, OB_LAST_PACKET); pend_sell.append(&mut s); book.apply_full(None, Some(pend_sell.drain(..).collect()));
-    } }
+    let mut r = CaptureReader::open(&path).unwrap();
+    let mut builder = BookBuilder::new();
+
+    // fb1: nothing replaced yet
+    let Some(Ok(RecordFrame::Event(ev))) = r.next() else { panic!("unexpected frame") };
+    assert!(!builder.apply(&ev.kind).unwrap());
+    assert!(!builder.is_consistent());
+    assert!(builder.book().buys.is_empty() && builder.book().sells.is_empty());
+    // fb2: both sides complete
+    let Some(Ok(RecordFrame::Event(ev))) = r.next() else { panic!("unexpected frame") };
+    assert!(builder.apply(&ev.kind).unwrap());
+    assert!(builder.is_consistent());
 
     // Final checks
+    let book = builder.book();
     assert_eq!(book.buys.len(), b1.len() + b2.len());
     assert_eq!(book.sells.len(), s1.len() + s2.len());
     assert!((book.buys[0].price - 100.0).abs() < 1e-9);